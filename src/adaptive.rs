@@ -0,0 +1,203 @@
+use std::ops::Range;
+use super::basic::*;
+use super::tracer::*;
+use super::mesh::*;
+use super::volume::*;
+use super::renderer::*;
+
+// running per-pixel sample count, mean and variance of the luminance (Welford's algorithm)
+#[derive(Clone, Debug, PartialEq)]
+pub struct PixelStats {
+    pub width: usize,
+    pub height: usize,
+    pub(crate) count: Vec<u32>,
+    pub(crate) mean: Vec<f32>,
+    pub(crate) m2: Vec<f32>, // sum of squared differences from the mean
+}
+
+impl PixelStats {
+    pub fn new(width: usize, height: usize) -> PixelStats {
+        PixelStats {
+            width,
+            height,
+            count: vec![0; width * height],
+            mean: vec![0.0; width * height],
+            m2: vec![0.0; width * height],
+        }
+    }
+
+    #[inline]
+    pub fn add(&mut self, row: usize, col: usize, value: f32) {
+        let i = row * self.width + col;
+        self.count[i] += 1;
+        let delta = value - self.mean[i];
+        self.mean[i] += delta / self.count[i] as f32;
+        self.m2[i] += delta * (value - self.mean[i]);
+    }
+
+    #[inline]
+    pub fn count(&self, row: usize, col: usize) -> u32 {
+        self.count[row * self.width + col]
+    }
+
+    #[inline]
+    pub fn mean(&self, row: usize, col: usize) -> f32 {
+        self.mean[row * self.width + col]
+    }
+
+    // unbiased sample variance, 0 below two samples
+    #[inline]
+    pub fn variance(&self, row: usize, col: usize) -> f32 {
+        let i = row * self.width + col;
+        if self.count[i] < 2 {
+            return 0.0;
+        }
+        self.m2[i] / (self.count[i] - 1) as f32
+    }
+
+    // half-width of the confidence interval of the mean, z = 1.96 gives 95%
+    pub fn error(&self, row: usize, col: usize, z: f32) -> f32 {
+        let n = self.count(row, col);
+        if n == 0 {
+            return f32::INFINITY;
+        }
+        z * (self.variance(row, col) / n as f32).sqrt()
+    }
+
+    // debug image of the sample counts, white at max_samples
+    pub fn sample_map(&self, max_samples: u32) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let v = self.count(row, col) as f32 / max_samples.max(1) as f32;
+                image[row][col] = Pix::from_vec3(Vec3 { x: v, y: v, z: v });
+            }
+        }
+        image
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSettings {
+    pub min_samples: u32, // every pixel gets at least these before its error is trusted
+    pub max_samples: u32,
+    pub threshold: f32, // converged once the error is below this fraction of the mean
+    pub confidence: f32, // z value of the confidence interval
+}
+
+impl AdaptiveSettings {
+    pub fn new(min_samples: u32, max_samples: u32, threshold: f32) -> AdaptiveSettings {
+        AdaptiveSettings { min_samples: min_samples.max(2), max_samples, threshold, confidence: 1.96 }
+    }
+}
+
+// sends settings.samples_per_pixel more samples per round to every pixel that has not converged yet
+pub struct AdaptiveRenderer<'a> {
+    camera: &'a Camera,
+    world: &'a dyn Hitable,
+    fog: Option<&'a Fog>,
+    pub settings: RenderSettings,
+    pub adaptive: AdaptiveSettings,
+    pub buffer: FrameBuffer,
+    pub stats: PixelStats,
+    pub rounds: u32,
+}
+
+impl<'a> AdaptiveRenderer<'a> {
+    pub fn new(
+        camera: &'a Camera,
+        world: &'a dyn Hitable,
+        fog: Option<&'a Fog>,
+        settings: RenderSettings,
+        adaptive: AdaptiveSettings,
+    ) -> AdaptiveRenderer<'a> {
+        let (width, height) = (settings.width, settings.height);
+        AdaptiveRenderer {
+            camera,
+            world,
+            fog,
+            settings,
+            adaptive,
+            buffer: FrameBuffer::new(width, height),
+            stats: PixelStats::new(width, height),
+            rounds: 0,
+        }
+    }
+
+    pub fn is_converged(&self, row: usize, col: usize) -> bool {
+        let n = self.stats.count(row, col);
+        if n >= self.adaptive.max_samples {
+            return true;
+        }
+        if n < self.adaptive.min_samples {
+            return false;
+        }
+        let mean = self.stats.mean(row, col);
+        self.stats.error(row, col, self.adaptive.confidence) <= self.adaptive.threshold * (mean.abs() + 1e-2)
+    }
+
+    // sample indices the pixel gets in the next round
+    fn next_samples(&self, row: usize, col: usize) -> Range<u32> {
+        let n = self.stats.count(row, col);
+        if self.is_converged(row, col) {
+            return n..n;
+        }
+        let batch = if n < self.adaptive.min_samples {
+            self.adaptive.min_samples - n
+        } else {
+            self.settings.samples_per_pixel.max(1)
+        };
+        n..(n + batch).min(self.adaptive.max_samples)
+    }
+
+    // trace one round and return the number of pixels that received samples
+    pub fn render_round(&mut self) -> usize {
+        let width = self.settings.width;
+        let mut ranges = Vec::with_capacity(width * self.settings.height);
+        for row in 0..self.settings.height {
+            for col in 0..width {
+                ranges.push(self.next_samples(row, col));
+            }
+        }
+        let active = ranges.iter().filter(|r| !r.is_empty()).count();
+        if active == 0 {
+            return 0;
+        }
+
+        let (buffer, stats, filter) = (&mut self.buffer, &mut self.stats, self.settings.filter);
+        let samples = |row: usize, col: usize| ranges[row * width + col].clone();
+        trace_rows(self.camera, self.world, self.fog, &self.settings, samples, |row, traced| {
+            for s in traced {
+                buffer.splat(s.x, s.y, s.color, &filter);
+                let col = (s.x as usize).min(width - 1);
+                stats.add(row, col, luminance(&s.color));
+            }
+        });
+        self.rounds += 1;
+        active
+    }
+
+    // render rounds until every pixel has converged or reached max_samples
+    pub fn run(&mut self) {
+        while self.render_round() > 0 {}
+    }
+
+    pub fn image(&self) -> Image {
+        self.settings.output.to_image(&self.buffer)
+    }
+
+    pub fn sample_map(&self) -> Image {
+        self.stats.sample_map(self.adaptive.max_samples)
+    }
+
+    // samples traced over the whole image
+    pub fn total_samples(&self) -> u64 {
+        let mut total = 0;
+        for row in 0..self.settings.height {
+            for col in 0..self.settings.width {
+                total += self.stats.count(row, col) as u64;
+            }
+        }
+        total
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs;
+use std::io;
+use super::basic::*;
+use super::tracer::*;
+use super::mesh::*;
+use super::renderer::*;
+use super::sampler::*;
+
+// auxiliary output variables, taken at the first surface hit of the camera rays
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov {
+    Depth, // distance along the viewing direction
+    Normal, // shading normal facing the camera
+    Albedo, // reflectance of the first hit material
+    ObjectId,
+    PrimitiveId,
+    Mask, // fraction of the pixel covered by geometry
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId, Aov::PrimitiveId, Aov::Mask];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::PrimitiveId => "primitive_id",
+            Aov::Mask => "mask",
+        }
+    }
+}
+
+// per-pixel AOVs; depth, normal and albedo are averaged over the samples that hit
+// something, the ids come from the ray through the pixel center
+#[derive(Clone, Debug, PartialEq)]
+pub struct AovBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub depth: Vec<f32>,
+    pub normal: Vec<Vec3>,
+    pub albedo: Vec<Vec3>,
+    pub mask: Vec<f32>,
+    pub object_id: Vec<Option<u32>>,
+    pub primitive_id: Vec<Option<u32>>,
+}
+
+impl AovBuffer {
+    pub fn new(width: usize, height: usize) -> AovBuffer {
+        AovBuffer {
+            width,
+            height,
+            depth: vec![0.0; width * height],
+            normal: vec![Vec3::new(); width * height],
+            albedo: vec![Vec3::new(); width * height],
+            mask: vec![0.0; width * height],
+            object_id: vec![None; width * height],
+            primitive_id: vec![None; width * height],
+        }
+    }
+
+    // displayable image of one AOV; depth is scaled so the nearest point is white
+    // and the farthest dark, ids get pseudo-random colors, misses are black
+    pub fn image(&self, aov: Aov) -> Image {
+        let max_depth = self.depth.iter().cloned().fold(0.0, f32::max);
+        let mut image = Image::new(self.width, self.height);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let i = row * self.width + col;
+                let hit = self.mask[i] > 0.0;
+                let color = match aov {
+                    Aov::Depth if hit => {
+                        let v = 1.0 - 0.9 * self.depth[i] / max_depth.max(f32::EPSILON);
+                        Vec3 { x: v, y: v, z: v }
+                    }
+                    Aov::Normal if hit => (self.normal[i] + Vec3 { x: 1.0, y: 1.0, z: 1.0 }) * 0.5,
+                    Aov::Albedo => self.albedo[i],
+                    Aov::ObjectId => self.object_id[i].map_or(Vec3::new(), id_color),
+                    Aov::PrimitiveId => self.primitive_id[i].map_or(Vec3::new(), id_color),
+                    Aov::Mask => Vec3 { x: self.mask[i], y: self.mask[i], z: self.mask[i] },
+                    _ => Vec3::new(),
+                };
+                image[row][col] = Pix::from_vec3(color);
+            }
+        }
+        image
+    }
+
+    // one PPM per AOV, named <prefix>_<aov name>.ppm
+    pub fn write_files(&self, prefix: &str) {
+        for aov in Aov::ALL {
+            write_p3_file(&format!("{}_{}.ppm", prefix, aov.name()), &self.image(aov));
+        }
+    }
+
+    // every AOV after the beauty image as a stream of P3 images in one file,
+    // each layer named by a comment line after its magic number
+    pub fn write_layers(&self, filename: &str, beauty: &Image) -> io::Result<()> {
+        let mut s = p3_layer("beauty", beauty);
+        for aov in Aov::ALL {
+            s.push_str(&p3_layer(aov.name(), &self.image(aov)));
+        }
+        fs::write(filename, s)
+    }
+}
+
+fn p3_layer(name: &str, image: &Image) -> String {
+    let p3 = image.get_p3();
+    let (magic, rest) = p3.split_at(3);
+    format!("{}# {}\n{}", magic, name, rest)
+}
+
+// well separated color for an id
+fn id_color(id: u32) -> Vec3 {
+    let h = hash(&[id as u64]);
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f32 / 255.0;
+    Vec3 { x: channel(0), y: channel(8), z: channel(16) }
+}
+
+// AOVs of the world seen by the camera, with the same pixel and lens samples as
+// the beauty render of these settings
+pub fn render_aovs(camera: &Camera, world: &dyn Hitable, settings: &RenderSettings) -> AovBuffer {
+    let (width, height) = (settings.width, settings.height);
+    let spp = settings.samples_per_pixel.max(1);
+    let forward = camera.view_direction();
+    let mut sampler = settings.sampler.create(spp, settings.seed);
+    let mut aovs = AovBuffer::new(width, height);
+    let t_range = Interval::initialize(1e-3, f32::MAX);
+    for row in 0..height {
+        for col in 0..width {
+            let i = row * width + col;
+            let mut hits = 0;
+            let (mut depth, mut normal, mut albedo) = (0.0, Vec3::new(), Vec3::new());
+            for index in 0..spp {
+                sampler.start_sample(row, col, index);
+                let (sx, sy) = sampler.get_2d();
+                let u = (col as f32 + sx) / width as f32;
+                let v = (row as f32 + sy) / height as f32;
+                let ray = camera.get_ray_lens(u, v, sampler.get_2d());
+                if let Some(rec) = world.is_hit(&ray, &t_range) {
+                    hits += 1;
+                    depth += dot(&(rec.p - camera.origin), &forward);
+                    normal += if rec.front_face { rec.normal } else { -rec.normal };
+                    albedo += rec.material.albedo();
+                }
+            }
+            if hits > 0 {
+                aovs.depth[i] = depth / hits as f32;
+                aovs.normal[i] = normal.normalized();
+                aovs.albedo[i] = albedo / hits as f32;
+                aovs.mask[i] = hits as f32 / spp as f32;
+            }
+
+            let center = camera.get_ray((col as f32 + 0.5) / width as f32, (row as f32 + 0.5) / height as f32);
+            if let Some(rec) = world.is_hit(&center, &t_range) {
+                aovs.object_id[i] = rec.object_id;
+                aovs.primitive_id[i] = rec.primitive_id;
+            }
+        }
+    }
+    aovs
+}
@@ -1,8 +1,10 @@
-use std::ops::{Add, Sub, Mul, Div, AddAssign, SubAssign, MulAssign, DivAssign};
+use std::ops::{Add, Sub, Mul, Div, Neg, AddAssign, SubAssign, MulAssign, DivAssign};
 use std::ops::{Index, IndexMut};
 use std::default::Default;
+use std::fmt;
+use std::cell::Cell;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -30,12 +32,124 @@ impl Vec3 {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
+    // the position transformed by m, including the translation
+    #[inline]
+    pub fn transform_point(&self, m: &Mat4) -> Vec3 {
+        m.transform_point(self)
+    }
+
+    // the direction transformed by m, ignoring the translation
+    #[inline]
+    pub fn transform_vector(&self, m: &Mat4) -> Vec3 {
+        m.transform_vector(self)
+    }
+
+    // the normal transformed by the inverse transpose of m, not normalized
+    #[inline]
+    pub fn transform_normal(&self, m: &Mat4) -> Vec3 {
+        match m.normal_matrix() {
+            Some(n) => n.transform(self),
+            None => *self,
+        }
+    }
+
+    // normalize in place, zero vectors are left unchanged
     #[inline]
     pub fn to_unit_len(&mut self) {
+        *self = self.normalized();
+    }
+
+    // unit length copy, or the zero vector if the length is zero
+    #[inline]
+    pub fn normalized(&self) -> Vec3 {
         let l = self.len();
-        self.x /= l;
-        self.y /= l;
-        self.z /= l;
+        if l == 0.0 {
+            return *self;
+        }
+        *self / l
+    }
+
+    // true if every component is close to zero
+    #[inline]
+    pub fn near_zero(&self) -> bool {
+        const S: f32 = 1e-8;
+        self.x.abs() < S && self.y.abs() < S && self.z.abs() < S
+    }
+
+    // component-wise minimum
+    #[inline]
+    pub fn min(&self, other: &Vec3) -> Vec3 {
+        Vec3 { x: self.x.min(other.x), y: self.y.min(other.y), z: self.z.min(other.z) }
+    }
+
+    // component-wise maximum
+    #[inline]
+    pub fn max(&self, other: &Vec3) -> Vec3 {
+        Vec3 { x: self.x.max(other.x), y: self.y.max(other.y), z: self.z.max(other.z) }
+    }
+
+    #[inline]
+    pub fn abs(&self) -> Vec3 {
+        Vec3 { x: self.x.abs(), y: self.y.abs(), z: self.z.abs() }
+    }
+
+    #[inline]
+    pub fn min_component(&self) -> f32 {
+        self.x.min(self.y).min(self.z)
+    }
+
+    #[inline]
+    pub fn max_component(&self) -> f32 {
+        self.x.max(self.y).max(self.z)
+    }
+
+    // linear interpolation, t = 0 gives self and t = 1 gives other
+    #[inline]
+    pub fn lerp(&self, other: &Vec3, t: f32) -> Vec3 {
+        *self * (1.0 - t) + *other * t
+    }
+
+    // uniformly distributed inside the unit sphere
+    pub fn random_in_unit_sphere() -> Vec3 {
+        loop {
+            let p = Vec3 {
+                x: random_f32() * 2.0 - 1.0,
+                y: random_f32() * 2.0 - 1.0,
+                z: random_f32() * 2.0 - 1.0,
+            };
+            if p.len_squared() < 1.0 {
+                return p;
+            }
+        }
+    }
+
+    // uniformly distributed on the unit sphere
+    pub fn random_unit_vector() -> Vec3 {
+        loop {
+            let p = Vec3::random_in_unit_sphere();
+            if p.len_squared() > 1e-8 {
+                return p.normalized();
+            }
+        }
+    }
+
+    // uniform direction on the unit sphere from two uniform numbers in [0, 1)
+    pub fn sample_unit_vector(u: (f32, f32)) -> Vec3 {
+        let z = 1.0 - 2.0 * u.0;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u.1;
+        Vec3 { x: r * phi.cos(), y: r * phi.sin(), z }
+    }
+
+    // uniform point inside the unit sphere from three uniform numbers in [0, 1)
+    pub fn sample_in_unit_sphere(u: (f32, f32), w: f32) -> Vec3 {
+        Vec3::sample_unit_vector(u) * w.cbrt()
+    }
+
+    // uniformly distributed on the unit hemisphere around the normal
+    pub fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
+        let v = Vec3::random_unit_vector();
+        if dot(&v, normal) > 0.0 { v } else { -v }
     }
 }
 
@@ -100,7 +214,104 @@ impl MulAssign<f32> for Vec3 {
     }
 }
 
+impl Neg for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn neg(self) -> Vec3 {
+        Vec3 { x: -self.x, y: -self.y, z: -self.z }
+    }
+}
+
+impl Mul<Vec3> for f32 {
+    type Output = Vec3;
+
+    #[inline]
+    fn mul(self, v: Vec3) -> Vec3 {
+        v * self
+    }
+}
+
+impl Div<f32> for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn div(self, scalar: f32) -> Vec3 {
+        self * (1.0 / scalar)
+    }
+}
+
+impl DivAssign<f32> for Vec3 {
+    #[inline]
+    fn div_assign(&mut self, scalar: f32) {
+        *self *= 1.0 / scalar;
+    }
+}
+
 // element-wise multiplication
+impl Mul for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn mul(self, other: Vec3) -> Vec3 {
+        Vec3 { x: self.x * other.x, y: self.y * other.y, z: self.z * other.z }
+    }
+}
+
+impl MulAssign for Vec3 {
+    #[inline]
+    fn mul_assign(&mut self, other: Vec3) {
+        self.x *= other.x;
+        self.y *= other.y;
+        self.z *= other.z;
+    }
+}
+
+// element-wise division
+impl Div for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn div(self, other: Vec3) -> Vec3 {
+        Vec3 { x: self.x / other.x, y: self.y / other.y, z: self.z / other.z }
+    }
+}
+
+impl DivAssign for Vec3 {
+    #[inline]
+    fn div_assign(&mut self, other: Vec3) {
+        self.x /= other.x;
+        self.y /= other.y;
+        self.z /= other.z;
+    }
+}
+
+// component access, 0 is x, 1 is y and 2 is z
+impl Index<usize> for Vec3 {
+    type Output = f32;
+
+    fn index(&self, index: usize) -> &f32 {
+        match index {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", index),
+        }
+    }
+}
+
+impl IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, index: usize) -> &mut f32 {
+        match index {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Vec3 index out of range: {}", index),
+        }
+    }
+}
+
+// dot product
 #[inline]
 pub fn dot(v1: &Vec3, v2: &Vec3) -> f32 {
     v1.x * v2.x + v1.y * v2.y + v1.z * v2.z
@@ -116,7 +327,531 @@ pub fn cross(v1: &Vec3, v2: &Vec3) -> Vec3 {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+// uniform point in the unit disk from two uniform numbers in [0, 1), using the
+// concentric mapping so that strata of the square stay compact on the disk
+pub fn sample_unit_disk(u: (f32, f32)) -> (f32, f32) {
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let quarter = std::f32::consts::FRAC_PI_4;
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, quarter * (b / a))
+    } else {
+        (b, 2.0 * quarter - quarter * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+// mirror v about the plane with unit normal n
+#[inline]
+pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
+    *v - *n * (2.0 * dot(v, n))
+}
+
+// refract the unit direction v through the surface with unit normal n (against v),
+// ratio is the incident over the transmitted refraction index,
+// None on total internal reflection
+#[inline]
+pub fn refract(v: &Vec3, n: &Vec3, ratio: f32) -> Option<Vec3> {
+    let cos_theta = dot(&-*v, n).min(1.0);
+    let r_out_perp = (*v + *n * cos_theta) * ratio;
+    let k = 1.0 - r_out_perp.len_squared();
+    if k < 0.0 {
+        return None;
+    }
+    Some(r_out_perp - *n * k.sqrt())
+}
+
+// 4x4 matrix, row-major, acting on column vectors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Mat4 { m }
+    }
+
+    pub fn translation(t: Vec3) -> Mat4 {
+        let mut mat = Mat4::identity();
+        mat.m[0][3] = t.x;
+        mat.m[1][3] = t.y;
+        mat.m[2][3] = t.z;
+        mat
+    }
+
+    pub fn scaling(s: Vec3) -> Mat4 {
+        let mut mat = Mat4::identity();
+        mat.m[0][0] = s.x;
+        mat.m[1][1] = s.y;
+        mat.m[2][2] = s.z;
+        mat
+    }
+
+    // rotation by angle (in radians) around the given axis, counter-clockwise looking down the axis
+    pub fn rotation(axis: Vec3, angle: f32) -> Mat4 {
+        let mut a = axis;
+        a.to_unit_len();
+        let (s, c) = angle.sin_cos();
+        let t = 1.0 - c;
+        Mat4 {
+            m: [
+                [t * a.x * a.x + c, t * a.x * a.y - s * a.z, t * a.x * a.z + s * a.y, 0.0],
+                [t * a.x * a.y + s * a.z, t * a.y * a.y + c, t * a.y * a.z - s * a.x, 0.0],
+                [t * a.x * a.z - s * a.y, t * a.y * a.z + s * a.x, t * a.z * a.z + c, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn rotation_x(angle: f32) -> Mat4 {
+        Mat4::rotation(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, angle)
+    }
+
+    pub fn rotation_y(angle: f32) -> Mat4 {
+        Mat4::rotation(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, angle)
+    }
+
+    pub fn rotation_z(angle: f32) -> Mat4 {
+        Mat4::rotation(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, angle)
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Mat4 { m }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None if the matrix is singular
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for col in 0..4 {
+            let mut pivot = col;
+            for row in col + 1..4 {
+                if a[row][col].abs() > a[pivot][col].abs() {
+                    pivot = row;
+                }
+            }
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= p;
+                inv[col][j] *= p;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { m: inv })
+    }
+
+    // transform a position, including the translation
+    #[inline]
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 || w == 0.0 {
+            Vec3 { x, y, z }
+        } else {
+            Vec3 { x: x / w, y: y / w, z: z / w }
+        }
+    }
+
+    // transform a direction, ignoring the translation
+    #[inline]
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+impl Mat4 {
+    // embed a 3x3 linear map, without translation
+    pub fn from_mat3(m3: &Mat3) -> Mat4 {
+        let mut mat = Mat4::identity();
+        for i in 0..3 {
+            for j in 0..3 {
+                mat.m[i][j] = m3.m[i][j];
+            }
+        }
+        mat
+    }
+
+    // upper-left 3x3 linear part
+    pub fn to_mat3(&self) -> Mat3 {
+        let mut m = [[0.0; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            row.copy_from_slice(&self.m[i][..3]);
+        }
+        Mat3 { m }
+    }
+
+    // inverse transpose of the linear part, for transforming normals
+    pub fn normal_matrix(&self) -> Option<Mat3> {
+        Some(self.to_mat3().inverse()?.transpose())
+    }
+
+    // right-handed view matrix: eye maps to the origin, looking down -z with up along +y
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Mat4 {
+        let mut w = eye - target;
+        w.to_unit_len();
+        let mut u = cross(&up, &w);
+        u.to_unit_len();
+        let v = cross(&w, &u);
+        Mat4 {
+            m: [
+                [u.x, u.y, u.z, -dot(&u, &eye)],
+                [v.x, v.y, v.z, -dot(&v, &eye)],
+                [w.x, w.y, w.z, -dot(&w, &eye)],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // OpenGL style projection, vertical field of view in radians, maps [-near, -far] to [-1, 1]
+    pub fn perspective(fovy: f32, aspect: f32, near: f32, far: f32) -> Mat4 {
+        let f = 1.0 / (fovy * 0.5).tan();
+        Mat4 {
+            m: [
+                [f / aspect, 0.0, 0.0, 0.0],
+                [0.0, f, 0.0, 0.0],
+                [0.0, 0.0, (far + near) / (near - far), 2.0 * far * near / (near - far)],
+                [0.0, 0.0, -1.0, 0.0],
+            ],
+        }
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Mat4 {
+        Mat4::identity()
+    }
+}
+
+// 3x3 matrix, row-major, acting on column vectors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3 {
+    pub m: [[f32; 3]; 3],
+}
+
+impl Mat3 {
+    pub fn identity() -> Mat3 {
+        Mat3 { m: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]] }
+    }
+
+    pub fn from_rows(r0: Vec3, r1: Vec3, r2: Vec3) -> Mat3 {
+        Mat3 { m: [[r0.x, r0.y, r0.z], [r1.x, r1.y, r1.z], [r2.x, r2.y, r2.z]] }
+    }
+
+    pub fn from_cols(c0: Vec3, c1: Vec3, c2: Vec3) -> Mat3 {
+        Mat3::from_rows(c0, c1, c2).transpose()
+    }
+
+    pub fn scaling(s: Vec3) -> Mat3 {
+        Mat3 { m: [[s.x, 0.0, 0.0], [0.0, s.y, 0.0], [0.0, 0.0, s.z]] }
+    }
+
+    // rotation by angle (in radians) around the given axis
+    pub fn rotation(axis: Vec3, angle: f32) -> Mat3 {
+        Mat4::rotation(axis, angle).to_mat3()
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        let mut m = [[0.0; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Mat3 { m }
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // adjugate over determinant, None if the matrix is singular
+    pub fn inverse(&self) -> Option<Mat3> {
+        let det = self.determinant();
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let m = &self.m;
+        let inv_det = 1.0 / det;
+        let mut inv = [[0.0; 3]; 3];
+        for (i, row) in inv.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                // cofactor of m[j][i]
+                let (r0, r1) = ((j + 1) % 3, (j + 2) % 3);
+                let (c0, c1) = ((i + 1) % 3, (i + 2) % 3);
+                *v = (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) * inv_det;
+            }
+        }
+        Some(Mat3 { m: inv })
+    }
+
+    #[inline]
+    pub fn transform(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+impl Default for Mat3 {
+    fn default() -> Mat3 {
+        Mat3::identity()
+    }
+}
+
+// matrix product, (a * b) applies b first
+impl Mul for Mat3 {
+    type Output = Mat3;
+
+    fn mul(self, other: Mat3) -> Mat3 {
+        let mut m = [[0.0; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..3).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat3 { m }
+    }
+}
+
+// quaternion w + xi + yj + zk, unit quaternions represent rotations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quat {
+    pub fn identity() -> Quat {
+        Quat { w: 1.0, x: 0.0, y: 0.0, z: 0.0 }
+    }
+
+    // rotation by angle (in radians) around the given axis
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Quat {
+        let mut a = axis;
+        a.to_unit_len();
+        let (s, c) = (angle * 0.5).sin_cos();
+        Quat { w: c, x: a.x * s, y: a.y * s, z: a.z * s }
+    }
+
+    // unit rotation axis and angle in [0, 2pi], the axis is arbitrary for the identity
+    pub fn to_axis_angle(&self) -> (Vec3, f32) {
+        let q = self.normalized();
+        let s = (1.0 - q.w * q.w).max(0.0).sqrt();
+        let angle = 2.0 * q.w.clamp(-1.0, 1.0).acos();
+        if s < 1e-6 {
+            return (Vec3 { x: 1.0, y: 0.0, z: 0.0 }, angle);
+        }
+        (Vec3 { x: q.x / s, y: q.y / s, z: q.z / s }, angle)
+    }
+
+    #[inline]
+    pub fn dot(&self, other: &Quat) -> f32 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    #[inline]
+    pub fn len(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(&self) -> Quat {
+        let l = self.len();
+        if l == 0.0 {
+            return Quat::identity();
+        }
+        Quat { w: self.w / l, x: self.x / l, y: self.y / l, z: self.z / l }
+    }
+
+    pub fn conjugate(&self) -> Quat {
+        Quat { w: self.w, x: -self.x, y: -self.y, z: -self.z }
+    }
+
+    pub fn inverse(&self) -> Quat {
+        let l2 = self.dot(self);
+        let c = self.conjugate();
+        Quat { w: c.w / l2, x: c.x / l2, y: c.y / l2, z: c.z / l2 }
+    }
+
+    // rotate a vector by this unit quaternion
+    pub fn rotate(&self, v: &Vec3) -> Vec3 {
+        let u = Vec3 { x: self.x, y: self.y, z: self.z };
+        let t = cross(&u, v) * 2.0;
+        *v + t * self.w + cross(&u, &t)
+    }
+
+    // spherical linear interpolation along the shortest arc
+    pub fn slerp(a: &Quat, b: &Quat, t: f32) -> Quat {
+        let mut b = *b;
+        let mut cos_theta = a.dot(&b);
+        if cos_theta < 0.0 {
+            b = Quat { w: -b.w, x: -b.x, y: -b.y, z: -b.z };
+            cos_theta = -cos_theta;
+        }
+        let (wa, wb) = if cos_theta > 0.9995 {
+            // nearly parallel, fall back to linear interpolation
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (((1.0 - t) * theta).sin() / sin_theta, (t * theta).sin() / sin_theta)
+        };
+        Quat {
+            w: a.w * wa + b.w * wb,
+            x: a.x * wa + b.x * wb,
+            y: a.y * wa + b.y * wb,
+            z: a.z * wa + b.z * wb,
+        }
+        .normalized()
+    }
+
+    pub fn to_mat3(&self) -> Mat3 {
+        let q = self.normalized();
+        let (w, x, y, z) = (q.w, q.x, q.y, q.z);
+        Mat3 {
+            m: [
+                [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y)],
+                [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x)],
+                [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y)],
+            ],
+        }
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        Mat4::from_mat3(&self.to_mat3())
+    }
+}
+
+impl Default for Quat {
+    fn default() -> Quat {
+        Quat::identity()
+    }
+}
+
+// Hamilton product, (a * b) rotates by b first
+impl Mul for Quat {
+    type Output = Quat;
+
+    fn mul(self, o: Quat) -> Quat {
+        Quat {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+}
+
+// matrix product, (a * b) applies b first
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+// PCG32 generator (O'Neill 2014): small state, many independent streams
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0, inc: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    #[inline]
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(6364136223846793005).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    // uniform in [0, 1)
+    #[inline]
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+}
+
+thread_local! {
+    static THREAD_RNG: Cell<Pcg32> = Cell::new(Pcg32::new(0, 0));
+}
+
+// restart the calling thread's random stream, the renderer does this for
+// every pixel sample so results do not depend on which thread traced it
+pub fn seed_random(seed: u64, stream: u64) {
+    THREAD_RNG.with(|rng| rng.set(Pcg32::new(seed, stream)));
+}
+
+// uniform random number in [0, 1) from the calling thread's stream
+#[inline]
+pub fn random_f32() -> f32 {
+    THREAD_RNG.with(|rng| {
+        let mut r = rng.get();
+        let v = r.next_f32();
+        rng.set(r);
+        v
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Pix {
     pub r: u8,
     pub g: u8,
@@ -134,7 +869,7 @@ impl Pix {
     pub fn from_vec3(v: Vec3) -> Pix {
         let mut pix = Pix::new();
         pix.set_float(v.x, v.y, v.z);
-        return pix
+        pix
     }
     // set the pixel values with u8
     #[inline]
@@ -145,27 +880,86 @@ impl Pix {
     }
     
     // set the pixel values with f32, range [0.0, 1.0]
+    // out of range values are clamped, NaN maps to 0
     #[inline]
     pub fn set_float(&mut self, r: f32, g: f32, b: f32) {
-        self.r = (255.99 * r) as u8;
-        self.g = (255.99 * g) as u8;
-        self.b = (255.99 * b) as u8;
+        self.r = float_to_u8(r);
+        self.g = float_to_u8(g);
+        self.b = float_to_u8(b);
+    }
+
+    // pixel values as f32 in [0.0, 1.0]
+    #[inline]
+    pub fn to_vec3(&self) -> Vec3 {
+        Vec3 { x: self.r as f32, y: self.g as f32, z: self.b as f32 } / 255.0
+    }
+
+    // linear interpolation, t = 0 gives self and t = 1 gives other
+    pub fn blend(&self, other: &Pix, t: f32) -> Pix {
+        Pix::from_vec3(self.to_vec3().lerp(&other.to_vec3(), t.clamp(0.0, 1.0)))
+    }
+
+    // composite self with the given opacity over the background (straight alpha)
+    pub fn over(&self, background: &Pix, alpha: f32) -> Pix {
+        background.blend(self, alpha)
+    }
+
+    // every channel multiplied by the scale, saturating at 255
+    pub fn scale(&self, scale: f32) -> Pix {
+        Pix::from_vec3(self.to_vec3() * scale)
+    }
+
+    // rounded mean of the pixels, black if there are none
+    pub fn average(pixels: &[Pix]) -> Pix {
+        if pixels.is_empty() {
+            return Pix::new();
+        }
+        let n = pixels.len() as u32;
+        let mut sum = [0u32; 3];
+        for p in pixels {
+            sum[0] += p.r as u32;
+            sum[1] += p.g as u32;
+            sum[2] += p.b as u32;
+        }
+        Pix {
+            r: ((sum[0] + n / 2) / n) as u8,
+            g: ((sum[1] + n / 2) / n) as u8,
+            b: ((sum[2] + n / 2) / n) as u8,
+        }
+    }
+}
+
+// clamp to [0.0, 1.0] and round to the nearest 8-bit value
+#[inline]
+fn float_to_u8(v: f32) -> u8 {
+    if v.is_nan() {
+        return 0;
     }
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+impl Default for Pix {
+    fn default() -> Pix {
+        Pix::new()
+    }
+}
 
-    pub fn to_string(&self) -> String {
-        format!("{} {} {}\n", self.r, self.g, self.b)
+impl fmt::Display for Pix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} {} {}", self.r, self.g, self.b)
     }
 }
 
+// saturating addition, channels stop at 255
 impl Add for Pix {
     type Output = Pix;
 
     #[inline]
     fn add(self, other: Pix) -> Pix {
         Pix {
-            r: self.r + other.r,
-            g: self.g + other.g,
-            b: self.b + other.b,
+            r: self.r.saturating_add(other.r),
+            g: self.g.saturating_add(other.g),
+            b: self.b.saturating_add(other.b),
         }
     }
 }
@@ -173,21 +967,22 @@ impl Add for Pix {
 impl AddAssign for Pix {
     #[inline]
     fn add_assign(&mut self, other: Pix) {
-        self.r += other.r;
-        self.g += other.g;
-        self.b += other.b;
+        self.r = self.r.saturating_add(other.r);
+        self.g = self.g.saturating_add(other.g);
+        self.b = self.b.saturating_add(other.b);
     }
 }
 
+// saturating subtraction, channels stop at 0
 impl Sub for Pix {
     type Output = Pix;
     
     #[inline]
     fn sub(self, other: Pix) -> Pix {
         Pix {
-            r: self.r - other.r,
-            g: self.g - other.g,
-            b: self.b - other.b,
+            r: self.r.saturating_sub(other.r),
+            g: self.g.saturating_sub(other.g),
+            b: self.b.saturating_sub(other.b),
         }
     }
 }
@@ -195,9 +990,9 @@ impl Sub for Pix {
 impl SubAssign for Pix {
     #[inline]
     fn sub_assign(&mut self, other: Pix) {
-        self.r -= other.r;
-        self.g -= other.g;
-        self.b -= other.b;
+        self.r = self.r.saturating_sub(other.r);
+        self.g = self.g.saturating_sub(other.g);
+        self.b = self.b.saturating_sub(other.b);
     }
 }
 
@@ -220,10 +1015,37 @@ impl Image {
         Image { width, height, pixels }
     }
 
+    // per pixel rounded mean of images of the same size, None if the sizes differ
+    pub fn average(images: &[Image]) -> Option<Image> {
+        let (width, height) = images.first().map_or((0, 0), |i| (i.width, i.height));
+        if images.iter().any(|i| (i.width, i.height) != (width, height)) {
+            return None;
+        }
+        let mut image = Image::new(width, height);
+        let mut samples = Vec::with_capacity(images.len());
+        for row in 0..height {
+            for col in 0..width {
+                samples.clear();
+                samples.extend(images.iter().map(|i| i[row][col]));
+                image[row][col] = Pix::average(&samples);
+            }
+        }
+        Some(image)
+    }
+
+    // composite the foreground with the given opacity over this image
+    pub fn composite_over(&mut self, foreground: &Image, alpha: f32) {
+        for (row, fg_row) in self.pixels.iter_mut().zip(foreground.pixels.iter()) {
+            for (pix, fg) in row.iter_mut().zip(fg_row.iter()) {
+                *pix = fg.over(pix, alpha);
+            }
+        }
+    }
+
     pub fn get_p3(&self) -> String {
         let mut s = self.get_p3_header();
         s.push_str(self.pix_string().as_str());
-        return s;
+        s
     }
 
     fn get_p3_header(&self) -> String {
@@ -238,7 +1060,7 @@ impl Image {
                 s.push_str(pix.to_string().as_str());
             }
         }
-        return s;
+        s
     }
 }
 
@@ -247,7 +1069,7 @@ impl Image {
 impl Index<usize> for Image {
     type Output = Vec<Pix>;
 
-    fn index<'a>(&'a self, index: usize) -> &'a Self::Output {
+    fn index(&self, index: usize) -> &Self::Output {
         &self.pixels[index]
     }
 }
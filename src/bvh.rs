@@ -0,0 +1,224 @@
+use super::basic::*;
+use super::tracer::*;
+use super::mesh::*;
+use super::transform::*;
+
+// axis-aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    // contains nothing, the identity of union
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3 { x: f32::MAX, y: f32::MAX, z: f32::MAX },
+            max: Vec3 { x: -f32::MAX, y: -f32::MAX, z: -f32::MAX },
+        }
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Aabb {
+        points.iter().fold(Aabb::empty(), |b, p| b.grow(p))
+    }
+
+    pub fn grow(&self, p: &Vec3) -> Aabb {
+        Aabb { min: self.min.min(p), max: self.max.max(p) }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb { min: self.min.min(&other.min), max: self.max.max(&other.max) }
+    }
+
+    // the box grown by margin on every side, gives flat boxes some thickness
+    pub fn padded(&self, margin: f32) -> Aabb {
+        let m = Vec3 { x: margin, y: margin, z: margin };
+        Aabb { min: self.min - m, max: self.max + m }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let e = self.extent().max(&Vec3::new());
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    // bounds of the box after the transform, from its eight corners
+    pub fn transformed(&self, transform: &Transform) -> Aabb {
+        let mut b = Aabb::empty();
+        for i in 0..8 {
+            let corner = Vec3 {
+                x: if i & 1 == 0 { self.min.x } else { self.max.x },
+                y: if i & 2 == 0 { self.min.y } else { self.max.y },
+                z: if i & 4 == 0 { self.min.z } else { self.max.z },
+            };
+            b = b.grow(&transform.point(&corner));
+        }
+        b
+    }
+
+    // slab test, true if the ray enters the box within (t_min, t_max)
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.clip(ray, t_min, t_max).is_some()
+    }
+
+    // the part of [t_min, t_max] where the ray is inside the box
+    pub fn clip(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for axis in 0..3 {
+            let inv = 1.0 / ray.direction[axis];
+            let mut near = (self.min[axis] - ray.origin[axis]) * inv;
+            let mut far = (self.max[axis] - ray.origin[axis]) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // max/min ignore the NaN of a ray lying in a slab plane
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t1 < t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+enum BvhNode {
+    Leaf(Aabb, usize, usize), // bounds, first object, object count
+    Inner(Aabb, usize, usize), // bounds, left child, right child
+}
+
+// bounding volume hierarchy over Hitables, reporting the index an object was
+// added with as the primitive id; objects without bounds are tested one by one
+pub struct Bvh {
+    objects: Vec<Box<dyn Hitable>>, // in tree order
+    ids: Vec<u32>, // original index of every object
+    nodes: Vec<BvhNode>, // the root is the first node
+    unbounded: Vec<(u32, Box<dyn Hitable>)>,
+}
+
+impl Bvh {
+    // split at the median of the object centers along the widest axis
+    pub fn new(objects: Vec<Box<dyn Hitable>>) -> Bvh {
+        let mut items = Vec::new();
+        let mut slots = Vec::new();
+        let mut unbounded = Vec::new();
+        for (i, object) in objects.into_iter().enumerate() {
+            match object.bounding_box() {
+                Some(b) => {
+                    items.push((i as u32, b));
+                    slots.push(Some(object));
+                }
+                None => {
+                    unbounded.push((i as u32, object));
+                    slots.push(None);
+                }
+            }
+        }
+        let mut bvh = Bvh { objects: Vec::new(), ids: Vec::new(), nodes: Vec::new(), unbounded };
+        if !items.is_empty() {
+            bvh.build(&mut items);
+            bvh.objects = bvh.ids.iter().filter_map(|&i| slots[i as usize].take()).collect();
+        }
+        bvh
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len() + self.unbounded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn build(&mut self, items: &mut [(u32, Aabb)]) -> usize {
+        let bounds = items.iter().fold(Aabb::empty(), |b, (_, item)| b.union(item));
+        let index = self.nodes.len();
+        if items.len() <= LEAF_SIZE {
+            self.nodes.push(BvhNode::Leaf(bounds, self.ids.len(), items.len()));
+            self.ids.extend(items.iter().map(|(i, _)| *i));
+            return index;
+        }
+        let centers = items.iter().fold(Aabb::empty(), |b, (_, item)| b.grow(&item.centroid()));
+        let e = centers.extent();
+        let axis = if e.x >= e.y && e.x >= e.z { 0 } else if e.y >= e.z { 1 } else { 2 };
+        let mid = items.len() / 2;
+        items.select_nth_unstable_by(mid, |a, b| a.1.centroid()[axis].total_cmp(&b.1.centroid()[axis]));
+        self.nodes.push(BvhNode::Inner(bounds, 0, 0));
+        let (left, right) = items.split_at_mut(mid);
+        let left = self.build(left);
+        let right = self.build(right);
+        self.nodes[index] = BvhNode::Inner(bounds, left, right);
+        index
+    }
+}
+
+impl Hitable for Bvh {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let mut closest = *t_range;
+        let mut hit: Option<Hitrecord> = None;
+        let mut record = |h: Option<Hitrecord>, id: u32, closest: &mut Interval| {
+            if let Some(mut h) = h {
+                closest.t_max = h.t;
+                // like in a Mesh, the id belongs to the container that set object_id
+                if h.object_id.is_none() {
+                    h.primitive_id = Some(id);
+                }
+                hit = Some(h);
+            }
+        };
+        for (id, object) in &self.unbounded {
+            let h = object.is_hit(ray, &closest);
+            record(h, *id, &mut closest);
+        }
+
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            match self.nodes[index] {
+                BvhNode::Leaf(bounds, first, count) => {
+                    if !bounds.hit(ray, closest.t_min, closest.t_max) {
+                        continue;
+                    }
+                    for i in first..first + count {
+                        let h = self.objects[i].is_hit(ray, &closest);
+                        record(h, self.ids[i], &mut closest);
+                    }
+                }
+                BvhNode::Inner(bounds, left, right) => {
+                    if bounds.hit(ray, closest.t_min, closest.t_max) {
+                        stack.push(right);
+                        stack.push(left);
+                    }
+                }
+            }
+        }
+        hit
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        match self.nodes.first() {
+            Some(BvhNode::Leaf(bounds, _, _)) | Some(BvhNode::Inner(bounds, _, _)) => Some(*bounds),
+            None => Some(Aabb::empty()),
+        }
+    }
+}
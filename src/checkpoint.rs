@@ -0,0 +1,344 @@
+use std::fs;
+use std::io;
+use std::time::Duration;
+use super::basic::*;
+use super::tracer::*;
+use super::mesh::*;
+use super::volume::*;
+use super::renderer::*;
+use super::sampler::*;
+use super::progressive::*;
+use super::adaptive::*;
+
+// Checkpoint file layout, all numbers little endian:
+//   magic, version (u32), renderer kind (u8), render settings, renderer state
+// Samples are a pure function of the seed and their (row, col, index), so the
+// seed in the settings and the sample counts are the whole random number state.
+pub const CHECKPOINT_MAGIC: [u8; 8] = *b"PTCHKPT\n";
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+const KIND_PROGRESSIVE: u8 = 0;
+const KIND_ADAPTIVE: u8 = 1;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn header(&mut self, kind: u8) {
+        self.bytes.extend_from_slice(&CHECKPOINT_MAGIC);
+        self.u32(CHECKPOINT_VERSION);
+        self.u8(kind);
+    }
+
+    // threads is left out, it does not change the image
+    fn settings(&mut self, s: &RenderSettings) {
+        self.u64(s.width as u64);
+        self.u64(s.height as u64);
+        self.u32(s.samples_per_pixel);
+        self.u8(match s.sampler {
+            SamplerKind::Independent => 0,
+            SamplerKind::Stratified => 1,
+            SamplerKind::Halton => 2,
+            SamplerKind::Sobol => 3,
+            SamplerKind::BlueNoise => 4,
+        });
+        let (tag, params) = match s.filter {
+            Filter::Box(r) => (0, [r, 0.0, 0.0]),
+            Filter::Tent(r) => (1, [r, 0.0, 0.0]),
+            Filter::Gaussian(r, alpha) => (2, [r, alpha, 0.0]),
+            Filter::Mitchell(r, b, c) => (3, [r, b, c]),
+        };
+        self.u8(tag);
+        params.iter().for_each(|p| self.f32(*p));
+        self.u32(s.max_depth);
+        self.f32(s.output.exposure);
+        let (tag, param) = match s.output.tone_map {
+            ToneMap::Clamp => (0, 0.0),
+            ToneMap::Reinhard => (1, 0.0),
+            ToneMap::ReinhardExtended(white) => (2, white),
+            ToneMap::Aces => (3, 0.0),
+        };
+        self.u8(tag);
+        self.f32(param);
+        let (tag, param) = match s.output.encoding {
+            Encoding::Linear => (0, 0.0),
+            Encoding::Srgb => (1, 0.0),
+            Encoding::Gamma(gamma) => (2, gamma),
+        };
+        self.u8(tag);
+        self.f32(param);
+        self.u64(s.seed);
+    }
+
+    fn frame_buffer(&mut self, buffer: &FrameBuffer) {
+        for (sum, weight) in buffer.sum.iter().zip(&buffer.weight) {
+            self.f32(sum.x);
+            self.f32(sum.y);
+            self.f32(sum.z);
+            self.f32(*weight);
+        }
+    }
+
+    fn duration(&mut self, d: Duration) {
+        self.u64(d.as_secs());
+        self.u32(d.subsec_nanos());
+    }
+
+    // write next to the target first so a render killed while saving keeps the previous checkpoint
+    fn save(&self, filename: &str) -> io::Result<()> {
+        let tmp = format!("{}.tmp", filename);
+        fs::write(&tmp, &self.bytes)?;
+        fs::rename(&tmp, filename)
+    }
+}
+
+struct Decoder<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Decoder<'b> {
+    fn new(bytes: &'b [u8]) -> Decoder<'b> {
+        Decoder { bytes, pos: 0 }
+    }
+
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let end = self.pos + N;
+        let slice = self.bytes.get(self.pos..end).ok_or_else(|| invalid("truncated checkpoint"))?;
+        self.pos = end;
+        Ok(slice.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    fn header(&mut self, kind: u8) -> io::Result<()> {
+        if self.take::<8>()? != CHECKPOINT_MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        let version = self.u32()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(invalid(&format!("unsupported checkpoint version {}", version)));
+        }
+        if self.u8()? != kind {
+            return Err(invalid("checkpoint was written by another renderer"));
+        }
+        Ok(())
+    }
+
+    fn settings(&mut self) -> io::Result<RenderSettings> {
+        let width = self.u64()? as usize;
+        let height = self.u64()? as usize;
+        // every pixel takes at least 16 bytes, reject sizes the file cannot hold before allocating
+        let fits = width.checked_mul(height).and_then(|n| n.checked_mul(16)).is_some_and(|n| n <= self.bytes.len());
+        if !fits {
+            return Err(invalid("image size does not match the checkpoint"));
+        }
+        let mut s = RenderSettings::new(width, height);
+        s.samples_per_pixel = self.u32()?;
+        s.sampler = match self.u8()? {
+            0 => SamplerKind::Independent,
+            1 => SamplerKind::Stratified,
+            2 => SamplerKind::Halton,
+            3 => SamplerKind::Sobol,
+            4 => SamplerKind::BlueNoise,
+            _ => return Err(invalid("unknown sampler")),
+        };
+        let tag = self.u8()?;
+        let (r, a, b) = (self.f32()?, self.f32()?, self.f32()?);
+        s.filter = match tag {
+            0 => Filter::Box(r),
+            1 => Filter::Tent(r),
+            2 => Filter::Gaussian(r, a),
+            3 => Filter::Mitchell(r, a, b),
+            _ => return Err(invalid("unknown filter")),
+        };
+        s.max_depth = self.u32()?;
+        s.output.exposure = self.f32()?;
+        let (tag, param) = (self.u8()?, self.f32()?);
+        s.output.tone_map = match tag {
+            0 => ToneMap::Clamp,
+            1 => ToneMap::Reinhard,
+            2 => ToneMap::ReinhardExtended(param),
+            3 => ToneMap::Aces,
+            _ => return Err(invalid("unknown tone map")),
+        };
+        let (tag, param) = (self.u8()?, self.f32()?);
+        s.output.encoding = match tag {
+            0 => Encoding::Linear,
+            1 => Encoding::Srgb,
+            2 => Encoding::Gamma(param),
+            _ => return Err(invalid("unknown encoding")),
+        };
+        s.seed = self.u64()?;
+        Ok(s)
+    }
+
+    fn frame_buffer(&mut self, width: usize, height: usize) -> io::Result<FrameBuffer> {
+        let mut buffer = FrameBuffer::new(width, height);
+        for i in 0..width * height {
+            buffer.sum[i] = Vec3 { x: self.f32()?, y: self.f32()?, z: self.f32()? };
+            buffer.weight[i] = self.f32()?;
+        }
+        Ok(buffer)
+    }
+
+    fn duration(&mut self) -> io::Result<Duration> {
+        let secs = self.u64()?;
+        let nanos = self.u32()?;
+        Ok(Duration::new(secs, nanos))
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        if self.pos != self.bytes.len() {
+            return Err(invalid("trailing data in checkpoint"));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ProgressiveRenderer<'a> {
+    pub fn save_checkpoint(&self, filename: &str) -> io::Result<()> {
+        let mut e = Encoder::default();
+        e.header(KIND_PROGRESSIVE);
+        e.settings(&self.settings);
+        e.u32(self.passes);
+        e.duration(self.elapsed);
+        e.frame_buffer(&self.buffer);
+        e.frame_buffer(&self.even);
+        e.frame_buffer(&self.odd);
+        e.save(filename)
+    }
+
+    // continue a render from a checkpoint; the scene is not part of the file and
+    // must be the one it was started with
+    pub fn resume(
+        camera: &'a Camera,
+        world: &'a dyn Hitable,
+        fog: Option<&'a Fog>,
+        filename: &str,
+    ) -> io::Result<ProgressiveRenderer<'a>> {
+        let bytes = fs::read(filename)?;
+        let mut d = Decoder::new(&bytes);
+        d.header(KIND_PROGRESSIVE)?;
+        let settings = d.settings()?;
+        let (width, height) = (settings.width, settings.height);
+        let mut renderer = ProgressiveRenderer::new(camera, world, fog, settings);
+        renderer.passes = d.u32()?;
+        renderer.elapsed = d.duration()?;
+        renderer.buffer = d.frame_buffer(width, height)?;
+        renderer.even = d.frame_buffer(width, height)?;
+        renderer.odd = d.frame_buffer(width, height)?;
+        d.finish()?;
+        Ok(renderer)
+    }
+
+    // like run, saving a checkpoint every `every` passes and once more at the end
+    pub fn run_checkpointed(&mut self, stop: &StopCondition, filename: &str, every: u32) -> io::Result<StopReason> {
+        loop {
+            if let Some(reason) = self.should_stop(stop) {
+                self.save_checkpoint(filename)?;
+                return Ok(reason);
+            }
+            self.render_pass();
+            if self.passes.is_multiple_of(every.max(1)) {
+                self.save_checkpoint(filename)?;
+            }
+        }
+    }
+}
+
+impl<'a> AdaptiveRenderer<'a> {
+    pub fn save_checkpoint(&self, filename: &str) -> io::Result<()> {
+        let mut e = Encoder::default();
+        e.header(KIND_ADAPTIVE);
+        e.settings(&self.settings);
+        e.u32(self.adaptive.min_samples);
+        e.u32(self.adaptive.max_samples);
+        e.f32(self.adaptive.threshold);
+        e.f32(self.adaptive.confidence);
+        e.u32(self.rounds);
+        e.frame_buffer(&self.buffer);
+        for i in 0..self.stats.count.len() {
+            e.u32(self.stats.count[i]);
+            e.f32(self.stats.mean[i]);
+            e.f32(self.stats.m2[i]);
+        }
+        e.save(filename)
+    }
+
+    // continue a render from a checkpoint; the scene must be the one it was started with
+    pub fn resume(
+        camera: &'a Camera,
+        world: &'a dyn Hitable,
+        fog: Option<&'a Fog>,
+        filename: &str,
+    ) -> io::Result<AdaptiveRenderer<'a>> {
+        let bytes = fs::read(filename)?;
+        let mut d = Decoder::new(&bytes);
+        d.header(KIND_ADAPTIVE)?;
+        let settings = d.settings()?;
+        let (width, height) = (settings.width, settings.height);
+        let adaptive = AdaptiveSettings {
+            min_samples: d.u32()?,
+            max_samples: d.u32()?,
+            threshold: d.f32()?,
+            confidence: d.f32()?,
+        };
+        let mut renderer = AdaptiveRenderer::new(camera, world, fog, settings, adaptive);
+        renderer.rounds = d.u32()?;
+        renderer.buffer = d.frame_buffer(width, height)?;
+        for i in 0..width * height {
+            renderer.stats.count[i] = d.u32()?;
+            renderer.stats.mean[i] = d.f32()?;
+            renderer.stats.m2[i] = d.f32()?;
+        }
+        d.finish()?;
+        Ok(renderer)
+    }
+
+    // like run, saving a checkpoint every `every` rounds and once more at the end
+    pub fn run_checkpointed(&mut self, filename: &str, every: u32) -> io::Result<()> {
+        while self.render_round() > 0 {
+            if self.rounds.is_multiple_of(every.max(1)) {
+                self.save_checkpoint(filename)?;
+            }
+        }
+        self.save_checkpoint(filename)
+    }
+}
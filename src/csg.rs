@@ -0,0 +1,113 @@
+use super::tracer::*;
+use super::mesh::*;
+use super::bvh::*;
+
+// Constructive solid geometry. The operands must be closed and report front_face
+// when the ray enters them, like the analytic primitives and watertight meshes;
+// the crossings of both inside the ray's range are merged and the ones where the
+// ray enters or leaves the combined solid are kept. Surfaces keep the material
+// of the operand they come from.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsgOp {
+    Union, // inside either operand
+    Intersection, // inside both
+    Difference, // inside the left operand and outside the right one
+}
+
+impl CsgOp {
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+pub struct Csg {
+    pub op: CsgOp,
+    pub left: Box<dyn Hitable>,
+    pub right: Box<dyn Hitable>,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Csg {
+        Csg { op, left, right }
+    }
+
+    pub fn union(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Csg {
+        Csg::new(CsgOp::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Csg {
+        Csg::new(CsgOp::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Csg {
+        Csg::new(CsgOp::Difference, left, right)
+    }
+}
+
+// crossings of an operand inside t_range, and whether the ray starts inside it as
+// told by the first crossing, looked for past the range if there is none in it
+fn crossings(object: &dyn Hitable, ray: &Ray, t_range: &Interval) -> (Vec<Hitrecord>, bool) {
+    let hits = object.all_hits(ray, t_range);
+    let inside = match hits.first() {
+        Some(h) => !h.front_face,
+        None => object.is_hit(ray, &Interval::initialize(t_range.t_max, f32::MAX)).is_some_and(|h| !h.front_face),
+    };
+    (hits, inside)
+}
+
+impl Hitable for Csg {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        self.all_hits(ray, t_range).into_iter().next()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.op {
+            CsgOp::Union => Some(self.left.bounding_box()?.union(&self.right.bounding_box()?)),
+            CsgOp::Intersection => match (self.left.bounding_box(), self.right.bounding_box()) {
+                (Some(a), Some(b)) => Some(Aabb::new(a.min.max(&b.min), a.max.min(&b.max))),
+                (a, b) => a.or(b),
+            },
+            CsgOp::Difference => self.left.bounding_box(),
+        }
+    }
+
+    fn all_hits(&self, ray: &Ray, t_range: &Interval) -> Vec<Hitrecord> {
+        let (left, mut in_left) = crossings(self.left.as_ref(), ray, t_range);
+        let (right, mut in_right) = crossings(self.right.as_ref(), ray, t_range);
+
+        let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+        let mut hits = Vec::new();
+        loop {
+            let was_inside = self.op.contains(in_left, in_right);
+            let from_left = match (left.peek(), right.peek()) {
+                (None, None) => break,
+                (Some(a), Some(b)) => a.t <= b.t,
+                (a, _) => a.is_some(),
+            };
+            let mut h = if from_left {
+                let h = left.next().unwrap();
+                in_left = h.front_face;
+                h
+            } else {
+                let h = right.next().unwrap();
+                in_right = h.front_face;
+                h
+            };
+            if self.op.contains(in_left, in_right) == was_inside {
+                continue;
+            }
+            // the right operand of a difference is seen from its inside
+            if !from_left && self.op == CsgOp::Difference {
+                h.normal = -h.normal;
+                h.front_face = !h.front_face;
+            }
+            hits.push(h);
+        }
+        hits
+    }
+}
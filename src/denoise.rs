@@ -0,0 +1,118 @@
+use super::basic::*;
+use super::renderer::*;
+use super::aov::*;
+
+// edge-avoiding à-trous wavelet filter (Dammertz et al. 2010); each iteration
+// doubles the spacing of the 5x5 B3 spline kernel and halves sigma_color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DenoiseSettings {
+    pub iterations: u32,
+    pub sigma_color: f32, // luminance difference of the demodulated color
+    pub sigma_normal: f32,
+    pub sigma_albedo: f32,
+    pub sigma_depth: f32, // relative to the depth of the center pixel
+    pub sigma_mask: f32, // keeps pixels partly covering the background apart from the others
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> DenoiseSettings {
+        DenoiseSettings {
+            iterations: 5,
+            sigma_color: 1.0,
+            sigma_normal: 0.3,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.1,
+            sigma_mask: 0.1,
+        }
+    }
+}
+
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// denoised copy of the HDR buffer guided by the AOVs of the same view; the
+// color is divided by the albedo before filtering so texture detail survives;
+// panics if the two differ in size
+pub fn denoise(buffer: &FrameBuffer, aovs: &AovBuffer, settings: &DenoiseSettings) -> FrameBuffer {
+    let (width, height) = (buffer.width, buffer.height);
+    assert_eq!(
+        (width, height),
+        (aovs.width, aovs.height),
+        "denoise: the buffer and the AOVs must have the same size"
+    );
+    // misses have no albedo
+    let albedo: Vec<Vec3> = (0..width * height)
+        .map(|i| if aovs.mask[i] > 0.0 {
+            aovs.albedo[i].max(&Vec3 { x: 1e-2, y: 1e-2, z: 1e-2 })
+        } else {
+            Vec3 { x: 1.0, y: 1.0, z: 1.0 }
+        })
+        .collect();
+    let mut color = Vec::with_capacity(width * height);
+    for row in 0..height {
+        for col in 0..width {
+            color.push(buffer.get(row, col) / albedo[row * width + col]);
+        }
+    }
+
+    let mut sigma_color = settings.sigma_color;
+    for k in 0..settings.iterations {
+        color = a_trous_pass(&color, aovs, 1 << k, sigma_color, settings);
+        sigma_color *= 0.5;
+    }
+
+    let mut denoised = FrameBuffer::new(width, height);
+    for row in 0..height {
+        for col in 0..width {
+            let i = row * width + col;
+            denoised.add_sample(row, col, color[i] * albedo[i], 1.0);
+        }
+    }
+    denoised
+}
+
+fn a_trous_pass(color: &[Vec3], aovs: &AovBuffer, step: usize, sigma_color: f32, settings: &DenoiseSettings) -> Vec<Vec3> {
+    let (width, height) = (aovs.width, aovs.height);
+    let edge = |diff: f32, sigma: f32| (-diff / (sigma * sigma).max(f32::EPSILON)).exp();
+    let mut out = Vec::with_capacity(width * height);
+    for row in 0..height {
+        for col in 0..width {
+            let i = row * width + col;
+            let (c, n, a, d, m) = (color[i], aovs.normal[i], aovs.albedo[i], aovs.depth[i], aovs.mask[i]);
+            // the background has no features to guide the filter, it is kept apart
+            if m <= 0.0 {
+                out.push(c);
+                continue;
+            }
+            let mut sum = Vec3::new();
+            let mut total = 0.0;
+            for (ky, hy) in KERNEL.iter().enumerate() {
+                let y = row as i64 + (ky as i64 - 2) * step as i64;
+                if y < 0 || y >= height as i64 {
+                    continue;
+                }
+                for (kx, hx) in KERNEL.iter().enumerate() {
+                    let x = col as i64 + (kx as i64 - 2) * step as i64;
+                    if x < 0 || x >= width as i64 {
+                        continue;
+                    }
+                    let j = y as usize * width + x as usize;
+                    if aovs.mask[j] <= 0.0 {
+                        continue;
+                    }
+                    let dc = luminance(&(color[j] - c));
+                    let w = hx * hy
+                        * edge(dc * dc, sigma_color)
+                        * edge((aovs.normal[j] - n).len_squared(), settings.sigma_normal)
+                        * edge((aovs.albedo[j] - a).len_squared(), settings.sigma_albedo)
+                        * edge(((aovs.depth[j] - d) / d.max(1e-3)).powi(2), settings.sigma_depth)
+                        * edge((aovs.mask[j] - m).powi(2), settings.sigma_mask);
+                    sum += color[j] * w;
+                    total += w;
+                }
+            }
+            // the center weight is never zero, so total > 0
+            out.push(sum / total);
+        }
+    }
+    out
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use super::mesh::*;
+use super::transform::*;
+use super::bvh::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GeometryId(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(pub u32);
+
+// named materials referenced by handle
+#[derive(Clone, Default)]
+pub struct MaterialLibrary {
+    materials: Vec<(String, Material)>,
+}
+
+impl MaterialLibrary {
+    pub fn new() -> MaterialLibrary {
+        MaterialLibrary { materials: Vec::new() }
+    }
+
+    // a material under a new name, or the new value of an existing one
+    pub fn add(&mut self, name: &str, material: Material) -> MaterialId {
+        match self.find(name) {
+            Some(id) => {
+                self.materials[id.0 as usize].1 = material;
+                id
+            }
+            None => {
+                self.materials.push((name.to_string(), material));
+                MaterialId(self.materials.len() as u32 - 1)
+            }
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<MaterialId> {
+        self.materials.iter().position(|(n, _)| n == name).map(|i| MaterialId(i as u32))
+    }
+
+    pub fn get(&self, id: MaterialId) -> Material {
+        self.materials[id.0 as usize].1
+    }
+
+    pub fn name(&self, id: MaterialId) -> &str {
+        &self.materials[id.0 as usize].0
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+}
+
+pub struct Node {
+    pub name: String,
+    pub transform: Transform, // relative to the parent
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    pub geometry: Option<(GeometryId, MaterialId)>,
+}
+
+// hierarchy of named nodes; geometry is stored once and may be placed by any number of nodes
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+    geometries: Vec<(String, Vec<Triangle>)>,
+    pub materials: MaterialLibrary,
+}
+
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        let root = Node {
+            name: "root".to_string(),
+            transform: Transform::new(),
+            parent: None,
+            children: Vec::new(),
+            geometry: None,
+        };
+        SceneGraph { nodes: vec![root], geometries: Vec::new(), materials: MaterialLibrary::new() }
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn add_node(&mut self, parent: NodeId, name: &str, transform: Transform) -> NodeId {
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(Node {
+            name: name.to_string(),
+            transform,
+            parent: Some(parent),
+            children: Vec::new(),
+            geometry: None,
+        });
+        self.nodes[parent.0 as usize].children.push(id);
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0 as usize]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0 as usize]
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // first node added with the name
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|n| n.name == name).map(|i| NodeId(i as u32))
+    }
+
+    // node reached from the root through the children named by the parts of a
+    // slash separated path, e.g. "house/door"
+    pub fn find_path(&self, path: &str) -> Option<NodeId> {
+        let mut id = self.root();
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            id = *self.node(id).children.iter().find(|c| self.node(**c).name == part)?;
+        }
+        Some(id)
+    }
+
+    pub fn add_geometry(&mut self, name: &str, triangles: Vec<Triangle>) -> GeometryId {
+        self.geometries.push((name.to_string(), triangles));
+        GeometryId(self.geometries.len() as u32 - 1)
+    }
+
+    pub fn find_geometry(&self, name: &str) -> Option<GeometryId> {
+        self.geometries.iter().position(|(n, _)| n == name).map(|i| GeometryId(i as u32))
+    }
+
+    pub fn geometry(&self, id: GeometryId) -> &[Triangle] {
+        &self.geometries[id.0 as usize].1
+    }
+
+    pub fn set_geometry(&mut self, node: NodeId, geometry: GeometryId, material: MaterialId) {
+        self.node_mut(node).geometry = Some((geometry, material));
+    }
+
+    // transform from the node's space to the world
+    pub fn world_transform(&self, id: NodeId) -> Transform {
+        let mut transform = Transform::new();
+        let mut node = Some(id);
+        while let Some(id) = node {
+            transform = transform.then(&self.node(id).transform);
+            node = self.node(id).parent;
+        }
+        transform
+    }
+
+    // every placed geometry in one BVH; each geometry and material pair is built
+    // into its own BVH once and instanced, hits report the node index as object id
+    pub fn flatten(&self) -> Bvh {
+        let mut assets: HashMap<(GeometryId, MaterialId), Arc<dyn Hitable>> = HashMap::new();
+        let mut objects: Vec<Box<dyn Hitable>> = Vec::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let Some((geometry, material)) = node.geometry else {
+                continue;
+            };
+            let asset = assets.entry((geometry, material)).or_insert_with(|| {
+                let m = self.materials.get(material);
+                let triangles = self.geometry(geometry).iter().map(|t| {
+                    let mut triangle = *t;
+                    triangle.material = m;
+                    Box::new(triangle) as Box<dyn Hitable>
+                });
+                Arc::new(Bvh::new(triangles.collect()))
+            });
+            let mut placed = Mesh::with_id(i as u32);
+            placed.add_triangle(Box::new(Instance::new(asset.clone(), self.world_transform(NodeId(i as u32)))));
+            objects.push(Box::new(placed));
+        }
+        Bvh::new(objects)
+    }
+}
+
+impl Default for SceneGraph {
+    fn default() -> SceneGraph {
+        SceneGraph::new()
+    }
+}
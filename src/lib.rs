@@ -2,4 +2,23 @@ pub mod tracer;
 pub mod tests;
 pub mod mesh;
 pub mod basic;
-// mod sphere_gen;
\ No newline at end of file
+pub mod volume;
+pub mod transform;
+pub mod renderer;
+pub mod sampler;
+pub mod progressive;
+pub mod adaptive;
+pub mod checkpoint;
+pub mod aov;
+pub mod denoise;
+pub mod sphere_gen;
+pub mod scene;
+pub mod bvh;
+pub mod graph;
+pub mod scenes;
+pub mod primitives;
+pub mod mesh_gen;
+pub mod subdivision;
+pub mod mesh_ops;
+pub mod csg;
+pub mod sdf;
@@ -1,3 +1,24 @@
+use std::env;
+use std::process;
+use ray_tracing::basic::*;
+use ray_tracing::scene::*;
+use ray_tracing::scenes;
+
+// usage: ray_tracing <scene name or .scene file> [output.ppm]
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().collect();
+    let Some(input) = args.get(1) else {
+        eprintln!("usage: {} <scene file or one of {}> [output.ppm]", args[0], scenes::NAMES.join(", "));
+        process::exit(2);
+    };
+    let output = args.get(2).map_or("out.ppm", |s| s.as_str());
+    let scene = match scenes::by_name(input, 400, 225) {
+        Some(scene) => scene,
+        None => Scene::load(input).unwrap_or_else(|e| {
+            eprintln!("{}: {}", input, e);
+            process::exit(1);
+        }),
+    };
+    let buffer = scene.render();
+    write_p3_file(output, &scene.settings.output.to_image(&buffer));
 }
@@ -1,14 +1,53 @@
 use std::default::Default;
-use std::f32::EPSILON;
 use std::ops::{Add, Sub, Mul, AddAssign, SubAssign, MulAssign};
 use super::tracer::*;
 use super::basic::*;
+use super::volume::*;
+use super::transform::*;
+use super::sampler::*;
+use super::bvh::*;
+
+// wavelengths (in nanometers) used for the r, g, b channels of dispersive materials
+pub const RGB_WAVELENGTHS: [f32; 3] = [630.0, 532.0, 465.0];
+
+// index of refraction, optionally depending on the wavelength
+#[derive(Clone, Copy, Debug)]
+pub enum Ior {
+    Constant(f32), // refraction index
+    Cauchy(f32, f32), // A, B (in um^2): n = A + B / lambda^2
+    Sellmeier([f32; 3], [f32; 3]), // B1..B3, C1..C3 (in um^2)
+}
+
+impl Ior {
+    // refraction index at the given wavelength (in nanometers)
+    pub fn at(&self, wavelength: f32) -> f32 {
+        let l2 = (wavelength * 1e-3) * (wavelength * 1e-3); // squared wavelength in um^2
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy(a, b) => a + b / l2,
+            Ior::Sellmeier(b, c) => {
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * l2 / (l2 - c[i]);
+                }
+                n2.max(1.0).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
 
 #[derive(Clone, Copy)]
 pub enum Material {
     Lambertian(f32), // albedo
     Metal(f32, f32), // albedo, fuzziness
-    Dielectric(f32), // refraction index
+    Dielectric(Ior, Vec3), // index of refraction, absorption coefficient per unit length
+    Isotropic(Vec3), // albedo, phase function of participating media
+    HenyeyGreenstein(Vec3, f32), // albedo, asymmetry in (-1, 1)
+    Emissive(Vec3), // emitted radiance, a light source that absorbs everything
 }
 
 impl Default for Material {
@@ -17,10 +56,151 @@ impl Default for Material {
     }
 }
 
-pub trait Hitable {
+impl Material {
+    // clear glass-like dielectric with a constant refraction index
+    pub fn dielectric(ior: f32) -> Material {
+        Material::Dielectric(Ior::Constant(ior), Vec3::new())
+    }
+
+    // reflectance color of the surface, used for the albedo AOV
+    pub fn albedo(&self) -> Vec3 {
+        match *self {
+            Material::Lambertian(albedo) | Material::Metal(albedo, _) => Vec3 { x: albedo, y: albedo, z: albedo },
+            Material::Dielectric(_, _) => Vec3 { x: 1.0, y: 1.0, z: 1.0 },
+            Material::Isotropic(albedo) | Material::HenyeyGreenstein(albedo, _) => albedo,
+            Material::Emissive(radiance) => radiance.min(&Vec3 { x: 1.0, y: 1.0, z: 1.0 }),
+        }
+    }
+
+    // radiance emitted towards the incoming ray
+    pub fn emitted(&self) -> Vec3 {
+        match *self {
+            Material::Emissive(radiance) => radiance,
+            _ => Vec3::new(),
+        }
+    }
+
+    // returns the attenuation and the scattered ray, or None if the ray is absorbed,
+    // random decisions draw their dimensions from the sampler; channel is the color
+    // channel the path was restricted to by dispersion, None until it meets any
+    pub fn scatter(
+        &self,
+        ray: &Ray,
+        rec: &Hitrecord,
+        channel: &mut Option<usize>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Vec3, Ray)> {
+        // normal pointing against the incoming ray
+        let normal = if rec.front_face { rec.normal } else { -rec.normal };
+        match *self {
+            Material::Lambertian(albedo) => {
+                let mut direction = normal + Vec3::sample_unit_vector(sampler.get_2d());
+                if direction.near_zero() {
+                    direction = normal;
+                }
+                let attenuation = Vec3 { x: albedo, y: albedo, z: albedo };
+                Some((attenuation, Ray { origin: rec.p, direction }))
+            }
+            Material::Metal(albedo, fuzz) => {
+                let unit_direction = ray.direction.normalized();
+                let fuzz_offset = Vec3::sample_in_unit_sphere(sampler.get_2d(), sampler.get_1d()) * fuzz;
+                let direction = reflect(&unit_direction, &normal) + fuzz_offset;
+                if dot(&direction, &normal) <= 0.0 {
+                    return None;
+                }
+                let attenuation = Vec3 { x: albedo, y: albedo, z: albedo };
+                Some((attenuation, Ray { origin: rec.p, direction }))
+            }
+            Material::Dielectric(ior, absorption) => {
+                // a back face hit means the ray travelled inside the medium: Beer-Lambert
+                let mut attenuation = Vec3 { x: 1.0, y: 1.0, z: 1.0 };
+                if !rec.front_face {
+                    let distance = rec.t * ray.direction.len();
+                    for i in 0..3 {
+                        attenuation[i] = (-absorption[i] * distance).exp();
+                    }
+                }
+
+                // dispersion: the path carries a single color channel with its own
+                // wavelength, picked at the first dispersive interface and weighted once
+                let eta = if ior.is_dispersive() {
+                    let c = match *channel {
+                        Some(c) => c,
+                        None => {
+                            let c = ((sampler.get_1d() * 3.0) as usize).min(2);
+                            attenuation[c] *= 3.0;
+                            *channel = Some(c);
+                            c
+                        }
+                    };
+                    let mut mask = Vec3::new();
+                    mask[c] = attenuation[c];
+                    attenuation = mask;
+                    ior.at(RGB_WAVELENGTHS[c])
+                } else {
+                    ior.at(RGB_WAVELENGTHS[channel.unwrap_or(1)])
+                };
+
+                let ratio = if rec.front_face { 1.0 / eta } else { eta };
+                let unit_direction = ray.direction.normalized();
+                let cos_theta = dot(&-unit_direction, &normal).min(1.0);
+                let u = sampler.get_1d();
+                let direction = match refract(&unit_direction, &normal, ratio) {
+                    Some(refracted) if reflectance(cos_theta, ratio) <= u => refracted,
+                    _ => reflect(&unit_direction, &normal),
+                };
+                Some((attenuation, Ray { origin: rec.p, direction }))
+            }
+            Material::Isotropic(albedo) => {
+                let direction = sample_henyey_greenstein(&ray.direction, 0.0, sampler.get_2d());
+                Some((albedo, Ray { origin: rec.p, direction }))
+            }
+            Material::HenyeyGreenstein(albedo, g) => {
+                let direction = sample_henyey_greenstein(&ray.direction, g, sampler.get_2d());
+                Some((albedo, Ray { origin: rec.p, direction }))
+            }
+            Material::Emissive(_) => None,
+        }
+    }
+}
+
+// Schlick's approximation of the Fresnel reflectance
+#[inline]
+fn reflectance(cosine: f32, ratio: f32) -> f32 {
+    let r0 = (1.0 - ratio) / (1.0 + ratio);
+    let r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+// shared between render threads
+pub trait Hitable: Send + Sync {
     fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord>;
+
+    // world space bounds, None for unbounded objects
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    // every surface crossing inside t_range, nearest first; the default steps
+    // along the ray with is_hit, 1e-4 past each hit whatever the ray's length,
+    // which lists both sides of a closed object
+    fn all_hits(&self, ray: &Ray, t_range: &Interval) -> Vec<Hitrecord> {
+        let step = 1e-4 / ray.direction.len();
+        let mut hits = Vec::new();
+        let mut t_min = t_range.t_min;
+        while let Some(h) = self.is_hit(ray, &Interval::initialize(t_min, t_range.t_max)) {
+            let next = h.t + step;
+            hits.push(h);
+            if next <= t_min {
+                break;
+            }
+            t_min = next;
+        }
+        hits
+    }
 }
 
+#[derive(Clone, Copy)]
 pub struct Triangle {
     pub a: Vec3,
     pub b: Vec3,
@@ -38,7 +218,20 @@ impl Triangle {
         Triangle { a, b, c, normal, material: Material::default()}
     }
 
-    pub fn default() -> Triangle {
+    // the triangle with every vertex transformed and its normal recomputed
+    pub fn transformed(&self, transform: &Transform) -> Triangle {
+        let mut t = Triangle::new(
+            transform.point(&self.a),
+            transform.point(&self.b),
+            transform.point(&self.c),
+        );
+        t.material = self.material;
+        t
+    }
+}
+
+impl Default for Triangle {
+    fn default() -> Triangle {
         let a = Default::default();
         let b = Default::default();
         let c = Default::default();
@@ -54,14 +247,14 @@ impl Hitable for Triangle {
         let e2 = self.c - self.a;
         let ray_cross_e2 = cross(&ray.direction, &e2);
         let det = dot(&e1, &ray_cross_e2);
-        if det > -EPSILON && det < EPSILON {
+        if det > -f32::EPSILON && det < f32::EPSILON {
             return None;
         }
 
         let inv_det = 1.0 / det;
         let s = ray.origin - self.a;
         let u = dot(&s, &ray_cross_e2) * inv_det;
-        if u < 0.0 || u > 1.0 {
+        if !(0.0..=1.0).contains(&u) {
             return None;
         }
 
@@ -79,16 +272,59 @@ impl Hitable for Triangle {
                 normal: Vec3::new(),
                 front_face: false,
                 material: self.material,
+                object_id: None,
+                primitive_id: None,
+                uv: (u, v),
             };
             h.t = t;
             h.p = ray.at(t);
             h.normal = self.normal;
             h.front_face = dot(&ray.direction, &self.normal) < 0.0;
-            return Some(h);
+            Some(h)
         } else {
-            return None;
+            None
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(&[self.a, self.b, self.c]).padded(1e-4))
+    }
+}
+
+// triangle shaded with normals and uv coordinates interpolated from its vertices;
+// front_face still follows the geometric normal
+#[derive(Clone, Copy)]
+pub struct SmoothTriangle {
+    pub triangle: Triangle,
+    pub normals: [Vec3; 3],
+    pub uvs: [(f32, f32); 3],
+}
+
+impl SmoothTriangle {
+    pub fn new(triangle: Triangle, normals: [Vec3; 3], uvs: [(f32, f32); 3]) -> SmoothTriangle {
+        SmoothTriangle { triangle, normals, uvs }
+    }
+}
+
+impl Hitable for SmoothTriangle {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let mut h = self.triangle.is_hit(ray, t_range)?;
+        let (u, v) = h.uv;
+        let w = 1.0 - u - v;
+        let normal = self.normals[0] * w + self.normals[1] * u + self.normals[2] * v;
+        if !normal.near_zero() {
+            h.normal = normal.normalized();
+        }
+        h.uv = (
+            self.uvs[0].0 * w + self.uvs[1].0 * u + self.uvs[2].0 * v,
+            self.uvs[0].1 * w + self.uvs[1].1 * u + self.uvs[2].1 * v,
+        );
+        Some(h)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.triangle.bounding_box()
+    }
 }
 
 impl Add<Vec3> for Triangle {
@@ -169,6 +405,9 @@ pub struct Hitrecord {
     pub normal: Vec3,
     pub front_face: bool,
     pub material: Material,
+    pub object_id: Option<u32>, // set by the innermost Mesh with an id around the primitive
+    pub primitive_id: Option<u32>, // index of the primitive in that Mesh, or in the outermost container without one
+    pub uv: (f32, f32), // surface coordinates, in [0, 1] on bounded primitives
 }
 
 // impl Hitrecord {
@@ -185,11 +424,16 @@ pub struct Hitrecord {
 
 pub struct Mesh {
     pub hitable_list: Vec<Box<dyn Hitable>>,
+    pub id: Option<u32>, // object id reported for hits on the primitives of this mesh, None leaves it to an enclosing one
 }
 
 impl Mesh {
     pub fn new() -> Mesh {
-        Mesh { hitable_list: Vec::new() }
+        Mesh { hitable_list: Vec::new(), id: None }
+    }
+
+    pub fn with_id(id: u32) -> Mesh {
+        Mesh { hitable_list: Vec::new(), id: Some(id) }
     }
 
     pub fn add_triangle(&mut self, triangle: Box<dyn Hitable>) {
@@ -197,18 +441,38 @@ impl Mesh {
     }
 }
 
+impl Default for Mesh {
+    fn default() -> Mesh {
+        Mesh::new()
+    }
+}
+
 impl Hitable for Mesh {
     fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
         let mut closest_t: f32 = t_range.t_max;
         let mut hit: Option<Hitrecord> = None;
-        for s in self.hitable_list.iter() {
-            if let Some(h) = s.is_hit(ray, t_range) {
+        for (i, s) in self.hitable_list.iter().enumerate() {
+            if let Some(mut h) = s.is_hit(ray, t_range) {
                 if h.t < closest_t {
                     closest_t = h.t;
+                    // nested meshes keep the ids of the innermost one with an object id,
+                    // so that (object_id, primitive_id) pairs are unique
+                    if h.object_id.is_none() {
+                        h.object_id = self.id;
+                        h.primitive_id = Some(i as u32);
+                    }
                     hit = Some(h);
                 }
             }
         }
-        return hit;
+        hit
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut bounds = Aabb::empty();
+        for s in self.hitable_list.iter() {
+            bounds = bounds.union(&s.bounding_box()?);
+        }
+        Some(bounds)
     }
 }
@@ -0,0 +1,289 @@
+use std::f32::consts::PI;
+use super::basic::*;
+use super::mesh::*;
+use super::transform::*;
+
+// triangle mesh with shared vertices, each with a normal and uv coordinates;
+// faces wind counter-clockwise seen from the front
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IndexedMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub faces: Vec<[u32; 3]>,
+}
+
+impl IndexedMesh {
+    pub fn new() -> IndexedMesh {
+        IndexedMesh::default()
+    }
+
+    pub fn add_vertex(&mut self, position: Vec3, normal: Vec3, uv: (f32, f32)) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.uvs.push(uv);
+        self.positions.len() as u32 - 1
+    }
+
+    pub fn add_face(&mut self, a: u32, b: u32, c: u32) {
+        self.faces.push([a, b, c]);
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    // appends the vertices and faces of other
+    pub fn append(&mut self, other: &IndexedMesh) {
+        let offset = self.positions.len() as u32;
+        self.positions.extend_from_slice(&other.positions);
+        self.normals.extend_from_slice(&other.normals);
+        self.uvs.extend_from_slice(&other.uvs);
+        self.faces.extend(other.faces.iter().map(|f| [f[0] + offset, f[1] + offset, f[2] + offset]));
+    }
+
+    pub fn transformed(&self, transform: &Transform) -> IndexedMesh {
+        IndexedMesh {
+            positions: self.positions.iter().map(|p| transform.point(p)).collect(),
+            normals: self.normals.iter().map(|n| transform.normal(n).normalized()).collect(),
+            uvs: self.uvs.clone(),
+            faces: self.faces.clone(),
+        }
+    }
+
+    // vertex normals as the area weighted mean of the normals of the faces around them
+    pub fn recompute_normals(&mut self) {
+        let mut normals = vec![Vec3::new(); self.positions.len()];
+        for f in &self.faces {
+            let [a, b, c] = f.map(|i| self.positions[i as usize]);
+            let n = cross(&(b - a), &(c - a));
+            for &i in f {
+                normals[i as usize] += n;
+            }
+        }
+        self.normals = normals.iter().map(|n| if n.near_zero() { *n } else { n.normalized() }).collect();
+    }
+
+    // flat shaded triangles, for the scene format which keeps positions only
+    pub fn triangles(&self) -> Vec<Triangle> {
+        self.faces
+            .iter()
+            .map(|f| Triangle::new(self.positions[f[0] as usize], self.positions[f[1] as usize], self.positions[f[2] as usize]))
+            .collect()
+    }
+
+    pub fn to_mesh(&self, material: Material) -> Mesh {
+        let mut mesh = Mesh::new();
+        for (f, mut triangle) in self.faces.iter().zip(self.triangles()) {
+            triangle.material = material;
+            let [a, b, c] = f.map(|i| i as usize);
+            let normals = [self.normals[a], self.normals[b], self.normals[c]];
+            let uvs = [self.uvs[a], self.uvs[b], self.uvs[c]];
+            mesh.add_triangle(Box::new(SmoothTriangle::new(triangle, normals, uvs)));
+        }
+        mesh
+    }
+
+    // Wavefront OBJ text with positions, uv coordinates and normals
+    pub fn to_obj(&self) -> String {
+        let mut out = String::new();
+        for p in &self.positions {
+            out.push_str(&format!("v {} {} {}\n", p.x, p.y, p.z));
+        }
+        for (u, v) in &self.uvs {
+            out.push_str(&format!("vt {} {}\n", u, v));
+        }
+        for n in &self.normals {
+            out.push_str(&format!("vn {} {} {}\n", n.x, n.y, n.z));
+        }
+        for f in &self.faces {
+            let [a, b, c] = f.map(|i| i + 1);
+            out.push_str(&format!("f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}\n"));
+        }
+        out
+    }
+
+    // faces over a grid of (cols + 1) x (rows + 1) vertices starting at first, row
+    // by row; the front is the side of d/dcol x d/drow. Faces with two vertices at
+    // the same place, as at the poles of a sphere, are left out.
+    fn add_grid_faces(&mut self, first: u32, cols: usize, rows: usize) {
+        let index = |row: usize, col: usize| first + (row * (cols + 1) + col) as u32;
+        for row in 0..rows {
+            for col in 0..cols {
+                let (a, b) = (index(row, col), index(row, col + 1));
+                let (c, d) = (index(row + 1, col + 1), index(row + 1, col));
+                for [a, b, c] in [[a, b, c], [a, c, d]] {
+                    let (pa, pb, pc) = (self.positions[a as usize], self.positions[b as usize], self.positions[c as usize]);
+                    if pa != pb && pb != pc && pc != pa {
+                        self.add_face(a, b, c);
+                    }
+                }
+            }
+        }
+    }
+
+    // a parametric surface sampled on a grid, f maps (u, v) in [0, 1] to the
+    // position, normal and uv of the vertex
+    fn add_surface<F>(&mut self, cols: usize, rows: usize, f: F)
+    where
+        F: Fn(f32, f32) -> (Vec3, Vec3, (f32, f32)),
+    {
+        let first = self.positions.len() as u32;
+        for row in 0..=rows {
+            for col in 0..=cols {
+                let (p, n, uv) = f(col as f32 / cols as f32, row as f32 / rows as f32);
+                self.add_vertex(p, n, uv);
+            }
+        }
+        self.add_grid_faces(first, cols, rows);
+    }
+}
+
+// Generators build their shapes around the origin with y up; place them with
+// IndexedMesh::transformed. Curved surfaces repeat the vertices of the seam so
+// the uv coordinates run from 0 to 1.
+
+// latitude-longitude sphere, u runs around the y axis and v from the south pole up
+pub fn uv_sphere(radius: f32, segments: usize, rings: usize) -> IndexedMesh {
+    let mut mesh = IndexedMesh::new();
+    mesh.add_surface(segments.max(3), rings.max(2), |u, v| {
+        let (phi, theta) = (2.0 * PI * u, PI * (1.0 - v));
+        // exactly on the axis at the poles, so their degenerate faces are dropped
+        let ring = if v == 0.0 || v == 1.0 { 0.0 } else { theta.sin() };
+        let n = Vec3 { x: ring * phi.cos(), y: theta.cos(), z: -ring * phi.sin() };
+        (n * radius, n, (u, v))
+    });
+    mesh
+}
+
+// cube of the given edge length, every face a grid of subdivisions x subdivisions
+// quads with its own vertices, so the edges stay sharp
+pub fn cube(size: f32, subdivisions: usize) -> IndexedMesh {
+    let n = subdivisions.max(1);
+    let e = |x: f32, y: f32, z: f32| Vec3 { x, y, z };
+    // normal, u and v axes with u x v = normal
+    let faces = [
+        (e(1.0, 0.0, 0.0), e(0.0, 0.0, -1.0), e(0.0, 1.0, 0.0)),
+        (e(-1.0, 0.0, 0.0), e(0.0, 0.0, 1.0), e(0.0, 1.0, 0.0)),
+        (e(0.0, 1.0, 0.0), e(1.0, 0.0, 0.0), e(0.0, 0.0, -1.0)),
+        (e(0.0, -1.0, 0.0), e(1.0, 0.0, 0.0), e(0.0, 0.0, 1.0)),
+        (e(0.0, 0.0, 1.0), e(1.0, 0.0, 0.0), e(0.0, 1.0, 0.0)),
+        (e(0.0, 0.0, -1.0), e(-1.0, 0.0, 0.0), e(0.0, 1.0, 0.0)),
+    ];
+    let mut mesh = IndexedMesh::new();
+    for (normal, du, dv) in faces {
+        mesh.add_surface(n, n, |u, v| ((normal * 0.5 + du * (u - 0.5) + dv * (v - 0.5)) * size, normal, (u, v)));
+    }
+    mesh
+}
+
+// cylinder standing on the origin, closed by caps when capped; the caps map the
+// disk to the unit square
+pub fn cylinder(radius: f32, height: f32, segments: usize, capped: bool) -> IndexedMesh {
+    let segments = segments.max(3);
+    let mut mesh = IndexedMesh::new();
+    mesh.add_surface(segments, 1, |u, v| {
+        let phi = 2.0 * PI * u;
+        let n = Vec3 { x: phi.cos(), y: 0.0, z: -phi.sin() };
+        (n * radius + Vec3 { x: 0.0, y: height * v, z: 0.0 }, n, (u, v))
+    });
+    if capped {
+        add_cap(&mut mesh, radius, 0.0, segments, false);
+        add_cap(&mut mesh, radius, height, segments, true);
+    }
+    mesh
+}
+
+// cone standing on the origin with the apex at height, closed by its base when capped
+pub fn cone(radius: f32, height: f32, segments: usize, capped: bool) -> IndexedMesh {
+    let segments = segments.max(3);
+    let mut mesh = IndexedMesh::new();
+    mesh.add_surface(segments, 1, |u, v| {
+        let phi = 2.0 * PI * u;
+        let ring = Vec3 { x: phi.cos(), y: 0.0, z: -phi.sin() };
+        let n = (ring * height + Vec3 { x: 0.0, y: radius, z: 0.0 }).normalized();
+        (ring * radius * (1.0 - v) + Vec3 { x: 0.0, y: height * v, z: 0.0 }, n, (u, v))
+    });
+    if capped {
+        add_cap(&mut mesh, radius, 0.0, segments, false);
+    }
+    mesh
+}
+
+// disk at height y facing up or down, as a fan of triangles around its center
+fn add_cap(mesh: &mut IndexedMesh, radius: f32, y: f32, segments: usize, up: bool) {
+    let normal = Vec3 { x: 0.0, y: if up { 1.0 } else { -1.0 }, z: 0.0 };
+    mesh.add_surface(segments, 1, |u, v| {
+        let phi = 2.0 * PI * u;
+        // the top goes from the rim to the center, the bottom the other way round
+        let r = if up { 1.0 - v } else { v };
+        let (x, z) = (r * phi.cos(), -r * phi.sin());
+        (Vec3 { x: x * radius, y, z: z * radius }, normal, (0.5 + 0.5 * x, 0.5 - 0.5 * z))
+    });
+}
+
+// torus around the y axis, u runs around the axis and v around the tube
+pub fn torus(major_radius: f32, minor_radius: f32, segments: usize, sides: usize) -> IndexedMesh {
+    let mut mesh = IndexedMesh::new();
+    mesh.add_surface(segments.max(3), sides.max(3), |u, v| {
+        let (phi, psi) = (2.0 * PI * u, 2.0 * PI * v);
+        let ring = Vec3 { x: phi.cos(), y: 0.0, z: -phi.sin() };
+        let n = ring * psi.cos() + Vec3 { x: 0.0, y: psi.sin(), z: 0.0 };
+        (ring * major_radius + n * minor_radius, n, (u, v))
+    });
+    mesh
+}
+
+// flat grid in the xz plane facing up, u along x and v along -z
+pub fn grid(width: f32, depth: f32, cols: usize, rows: usize) -> IndexedMesh {
+    heightfield(width, depth, cols, rows, |_, _| 0.0)
+}
+
+// grid whose vertices are raised by height(u, v), normals from the neighboring heights
+pub fn heightfield<F>(width: f32, depth: f32, cols: usize, rows: usize, height: F) -> IndexedMesh
+where
+    F: Fn(f32, f32) -> f32,
+{
+    let (cols, rows) = (cols.max(1), rows.max(1));
+    let mut heights = Vec::with_capacity((cols + 1) * (rows + 1));
+    for row in 0..=rows {
+        for col in 0..=cols {
+            heights.push(height(col as f32 / cols as f32, row as f32 / rows as f32));
+        }
+    }
+    let (dx, dz) = (width / cols as f32, depth / rows as f32);
+    let h = |row: usize, col: usize| heights[row * (cols + 1) + col];
+
+    let mut mesh = IndexedMesh::new();
+    for row in 0..=rows {
+        for col in 0..=cols {
+            let (u, v) = (col as f32 / cols as f32, row as f32 / rows as f32);
+            // slopes by central differences, one-sided at the border
+            let (c0, c1) = (col.saturating_sub(1), (col + 1).min(cols));
+            let (r0, r1) = (row.saturating_sub(1), (row + 1).min(rows));
+            let slope_x = (h(row, c1) - h(row, c0)) / ((c1 - c0) as f32 * dx);
+            let slope_z = -(h(r1, col) - h(r0, col)) / ((r1 - r0) as f32 * dz);
+            let normal = Vec3 { x: -slope_x, y: 1.0, z: -slope_z }.normalized();
+            let p = Vec3 { x: (u - 0.5) * width, y: h(row, col), z: (0.5 - v) * depth };
+            mesh.add_vertex(p, normal, (u, v));
+        }
+    }
+    mesh.add_grid_faces(0, cols, rows);
+    mesh
+}
+
+// heightfield with a vertex per pixel raised by its brightness times scale,
+// the top row of the image at the far (-z) edge
+pub fn heightfield_from_image(image: &Image, width: f32, depth: f32, scale: f32) -> IndexedMesh {
+    let (cols, rows) = (image.width.max(2) - 1, image.height.max(2) - 1);
+    heightfield(width, depth, cols, rows, |u, v| {
+        let col = ((u * cols as f32).round() as usize).min(image.width - 1);
+        let row = (((1.0 - v) * rows as f32).round() as usize).min(image.height - 1);
+        let pix = image[row][col];
+        (pix.r as f32 + pix.g as f32 + pix.b as f32) / (3.0 * 255.0) * scale
+    })
+}
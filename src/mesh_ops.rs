@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use super::basic::*;
+use super::mesh::*;
+use super::mesh_gen::*;
+use super::bvh::*;
+
+// how vertex normals are recomputed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormalMode {
+    Flat, // every face gets its own vertices with the face normal
+    AreaWeighted, // mean of the face normals around the vertex weighted by face area
+    AngleWeighted, // weighted by the angle of each face at the vertex, unaffected by how faces are split
+}
+
+// counts and measures of a mesh, see IndexedMesh::stats
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshStats {
+    pub vertices: usize,
+    pub faces: usize,
+    pub degenerate_faces: usize,
+    pub boundary_edges: usize, // edges of a single face
+    pub non_manifold_edges: usize, // edges of more than two faces, or two with the same winding
+    pub bounds: Aabb,
+    pub surface_area: f32,
+}
+
+impl MeshStats {
+    // closed and consistently wound, so the mesh has an inside
+    pub fn is_watertight(&self) -> bool {
+        self.boundary_edges == 0 && self.non_manifold_edges == 0
+    }
+}
+
+impl IndexedMesh {
+    // unwelded mesh of the triangles with their face normals and no uv coordinates,
+    // as loaded by scene::parse_obj
+    pub fn from_triangles(triangles: &[Triangle]) -> IndexedMesh {
+        let mut mesh = IndexedMesh::new();
+        for t in triangles {
+            let a = mesh.add_vertex(t.a, t.normal, (0.0, 0.0));
+            let b = mesh.add_vertex(t.b, t.normal, (0.0, 0.0));
+            let c = mesh.add_vertex(t.c, t.normal, (0.0, 0.0));
+            mesh.add_face(a, b, c);
+        }
+        mesh
+    }
+
+    fn corners(&self, face: &[u32; 3]) -> [Vec3; 3] {
+        face.map(|i| self.positions[i as usize])
+    }
+
+    // merges vertices closer than tolerance into the first of them, keeping its
+    // normal and uv; returns the number of vertices removed. Faces may become
+    // degenerate, see remove_degenerate.
+    pub fn weld(&mut self, tolerance: f32) -> usize {
+        let cell = tolerance.max(1e-6);
+        let key = |p: &Vec3| ((p.x / cell).floor() as i64, (p.y / cell).floor() as i64, (p.z / cell).floor() as i64);
+        let mut grid: HashMap<(i64, i64, i64), Vec<u32>> = HashMap::new();
+        let mut kept = IndexedMesh::new();
+        let mut remap = Vec::with_capacity(self.positions.len());
+        for (i, p) in self.positions.iter().enumerate() {
+            let (x, y, z) = key(p);
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        for &j in grid.get(&(x + dx, y + dy, z + dz)).into_iter().flatten() {
+                            if (kept.positions[j as usize] - *p).len() <= tolerance {
+                                found = Some(j);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            let index = found.unwrap_or_else(|| {
+                let j = kept.add_vertex(*p, self.normals[i], self.uvs[i]);
+                grid.entry((x, y, z)).or_default().push(j);
+                j
+            });
+            remap.push(index);
+        }
+        let removed = self.positions.len() - kept.positions.len();
+        kept.faces = self.faces.iter().map(|f| f.map(|i| remap[i as usize])).collect();
+        *self = kept;
+        removed
+    }
+
+    pub fn set_normals(&mut self, mode: NormalMode) {
+        match mode {
+            NormalMode::Flat => {
+                let mut flat = IndexedMesh::new();
+                for f in &self.faces {
+                    let [a, b, c] = self.corners(f);
+                    let normal = cross(&(b - a), &(c - a));
+                    let normal = if normal.near_zero() { normal } else { normal.normalized() };
+                    let [i, j, k] = f.map(|i| flat.add_vertex(self.positions[i as usize], normal, self.uvs[i as usize]));
+                    flat.add_face(i, j, k);
+                }
+                *self = flat;
+            }
+            NormalMode::AreaWeighted => self.recompute_normals(),
+            NormalMode::AngleWeighted => {
+                let mut normals = vec![Vec3::new(); self.positions.len()];
+                for f in &self.faces {
+                    let p = self.corners(f);
+                    let normal = cross(&(p[1] - p[0]), &(p[2] - p[0]));
+                    if normal.near_zero() {
+                        continue;
+                    }
+                    let normal = normal.normalized();
+                    for k in 0..3 {
+                        let e1 = (p[(k + 1) % 3] - p[k]).normalized();
+                        let e2 = (p[(k + 2) % 3] - p[k]).normalized();
+                        normals[f[k] as usize] += normal * dot(&e1, &e2).clamp(-1.0, 1.0).acos();
+                    }
+                }
+                self.normals = normals.iter().map(|n| if n.near_zero() { *n } else { n.normalized() }).collect();
+            }
+        }
+    }
+
+    // reverses the winding of every face and turns the normals around
+    pub fn flip(&mut self) {
+        for f in self.faces.iter_mut() {
+            f.swap(1, 2);
+        }
+        for n in self.normals.iter_mut() {
+            *n = -*n;
+        }
+    }
+
+    // a face with a repeated vertex, with its corners on a line, or so small that
+    // the determinant test of Triangle::is_hit rejects it for rays of unit length
+    pub fn is_degenerate(&self, face: &[u32; 3]) -> bool {
+        let [a, b, c] = self.corners(face);
+        let longest = (b - a).len_squared().max((c - b).len_squared()).max((a - c).len_squared());
+        let n = cross(&(b - a), &(c - a)).len();
+        face[0] == face[1] || face[1] == face[2] || face[2] == face[0] || n < f32::EPSILON || n <= f32::EPSILON * longest
+    }
+
+    // drops the degenerate faces, returns how many
+    pub fn remove_degenerate(&mut self) -> usize {
+        let count = self.faces.len();
+        let faces = std::mem::take(&mut self.faces);
+        self.faces = faces.into_iter().filter(|f| !self.is_degenerate(f)).collect();
+        count - self.faces.len()
+    }
+
+    // drops the vertices no face uses, returns how many
+    pub fn remove_unused_vertices(&mut self) -> usize {
+        let mut remap = vec![u32::MAX; self.positions.len()];
+        for f in &self.faces {
+            for &i in f {
+                remap[i as usize] = 0;
+            }
+        }
+        let mut kept = IndexedMesh::new();
+        for (i, r) in remap.iter_mut().enumerate() {
+            if *r == 0 {
+                *r = kept.add_vertex(self.positions[i], self.normals[i], self.uvs[i]);
+            }
+        }
+        let removed = self.positions.len() - kept.positions.len();
+        kept.faces = self.faces.iter().map(|f| f.map(|i| remap[i as usize])).collect();
+        *self = kept;
+        removed
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_points(&self.positions)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        self.faces
+            .iter()
+            .map(|f| {
+                let [a, b, c] = self.corners(f);
+                0.5 * cross(&(b - a), &(c - a)).len()
+            })
+            .sum()
+    }
+
+    // volume enclosed by a watertight mesh, negative when it is wound inside out
+    pub fn volume(&self) -> f32 {
+        self.faces
+            .iter()
+            .map(|f| {
+                let [a, b, c] = self.corners(f);
+                dot(&a, &cross(&b, &c)) / 6.0
+            })
+            .sum()
+    }
+
+    // edges by vertex index, each with the number of faces using it from a to b and from b to a
+    fn edge_uses(&self) -> HashMap<(u32, u32), (u32, u32)> {
+        let mut edges: HashMap<(u32, u32), (u32, u32)> = HashMap::new();
+        for f in &self.faces {
+            for k in 0..3 {
+                let (a, b) = (f[k], f[(k + 1) % 3]);
+                let uses = edges.entry((a.min(b), a.max(b))).or_default();
+                if a < b {
+                    uses.0 += 1;
+                } else {
+                    uses.1 += 1;
+                }
+            }
+        }
+        edges
+    }
+
+    // edges of a single face, in the direction of that face
+    pub fn boundary_edges(&self) -> Vec<[u32; 2]> {
+        let mut boundary: Vec<[u32; 2]> = self
+            .edge_uses()
+            .into_iter()
+            .filter_map(|((a, b), uses)| match uses {
+                (1, 0) => Some([a, b]),
+                (0, 1) => Some([b, a]),
+                _ => None,
+            })
+            .collect();
+        boundary.sort();
+        boundary
+    }
+
+    pub fn stats(&self) -> MeshStats {
+        let edges = self.edge_uses();
+        MeshStats {
+            vertices: self.positions.len(),
+            faces: self.faces.len(),
+            degenerate_faces: self.faces.iter().filter(|f| self.is_degenerate(f)).count(),
+            boundary_edges: edges.values().filter(|&&(x, y)| x + y == 1).count(),
+            non_manifold_edges: edges.values().filter(|&&(x, y)| x + y > 1 && (x, y) != (1, 1)).count(),
+            bounds: self.bounds(),
+            surface_area: self.surface_area(),
+        }
+    }
+
+    pub fn is_watertight(&self) -> bool {
+        self.stats().is_watertight()
+    }
+}
@@ -0,0 +1,591 @@
+use std::f32::consts::PI;
+use super::basic::*;
+use super::tracer::*;
+use super::mesh::*;
+use super::volume::*;
+use super::transform::*;
+use super::bvh::*;
+
+// Analytic primitives. Like Triangle they report the outward normal and set
+// front_face when the ray comes from outside; flat primitives are two-sided and
+// their normal follows the right hand rule.
+
+// orthonormal frame with w along an axis, for primitives defined around one
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    fn new(w: &Vec3) -> Frame {
+        let w = w.normalized();
+        let (u, v) = orthonormal_basis(&w);
+        Frame { u, v, w }
+    }
+
+    fn local(&self, a: &Vec3) -> Vec3 {
+        Vec3 { x: dot(a, &self.u), y: dot(a, &self.v), z: dot(a, &self.w) }
+    }
+
+    fn world(&self, a: &Vec3) -> Vec3 {
+        self.u * a.x + self.v * a.y + self.w * a.z
+    }
+
+    // world bounds of a disk of the given radius in the uv plane around center
+    fn disk_bounds(&self, center: &Vec3, radius: f32) -> Aabb {
+        let mut e = Vec3::new();
+        for i in 0..3 {
+            e[i] = radius * (1.0 - self.w[i] * self.w[i]).max(0.0).sqrt();
+        }
+        Aabb::new(*center - e, *center + e)
+    }
+}
+
+fn record(ray: &Ray, t: f32, normal: Vec3, uv: (f32, f32), material: Material) -> Hitrecord {
+    Hitrecord {
+        t,
+        p: ray.at(t),
+        normal,
+        front_face: dot(&ray.direction, &normal) < 0.0,
+        material,
+        object_id: None,
+        primitive_id: None,
+        uv,
+    }
+}
+
+// polar coordinates of a point in the local uv plane, angle and radius mapped to [0, 1]
+fn polar_uv(p: &Vec3, radius: f32) -> (f32, f32) {
+    let phi = p.y.atan2(p.x);
+    (phi / (2.0 * PI) + 0.5, ((p.x * p.x + p.y * p.y).sqrt() / radius).min(1.0))
+}
+
+// the roots of a t^2 + b t + c in increasing order
+fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    if a.abs() < 1e-12 {
+        if b == 0.0 {
+            return None;
+        }
+        return Some((-c / b, -c / b));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // avoids the cancellation of -b + sqrt(discriminant)
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+// point of the ray on the plane through point with the normal, None if the ray is parallel
+fn plane_t(ray: &Ray, point: &Vec3, normal: &Vec3) -> Option<f32> {
+    let denom = dot(&ray.direction, normal);
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+    Some(dot(&(*point - ray.origin), normal) / denom)
+}
+
+// infinite plane, the uv coordinates repeat every unit along its plane
+#[derive(Clone, Copy)]
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub material: Material,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Material) -> Plane {
+        Plane { point, normal: normal.normalized(), material }
+    }
+}
+
+impl Hitable for Plane {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let t = plane_t(ray, &self.point, &self.normal)?;
+        if !t_range.is_contained(t) {
+            return None;
+        }
+        let local = Frame::new(&self.normal).local(&(ray.at(t) - self.point));
+        Some(record(ray, t, self.normal, (local.x.rem_euclid(1.0), local.y.rem_euclid(1.0)), self.material))
+    }
+}
+
+// flat disk, uv are the angle around the center and the distance from it
+#[derive(Clone, Copy)]
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub material: Material,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f32, material: Material) -> Disk {
+        Disk { center, normal: normal.normalized(), radius, material }
+    }
+}
+
+impl Hitable for Disk {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let t = plane_t(ray, &self.center, &self.normal)?;
+        if !t_range.is_contained(t) {
+            return None;
+        }
+        let local = Frame::new(&self.normal).local(&(ray.at(t) - self.center));
+        if local.x * local.x + local.y * local.y > self.radius * self.radius {
+            return None;
+        }
+        Some(record(ray, t, self.normal, polar_uv(&local, self.radius), self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Frame::new(&self.normal).disk_bounds(&self.center, self.radius).padded(1e-4))
+    }
+}
+
+// parallelogram spanned by the edges u and v from origin, uv are the
+// coordinates along the edges; the normal is u x v
+#[derive(Clone, Copy)]
+pub struct Quad {
+    pub origin: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Material,
+}
+
+impl Quad {
+    pub fn new(origin: Vec3, u: Vec3, v: Vec3, material: Material) -> Quad {
+        Quad { origin, u, v, material }
+    }
+}
+
+impl Hitable for Quad {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let n = cross(&self.u, &self.v);
+        let t = plane_t(ray, &self.origin, &n)?;
+        if !t_range.is_contained(t) {
+            return None;
+        }
+        let w = n / dot(&n, &n);
+        let p = ray.at(t) - self.origin;
+        let alpha = dot(&w, &cross(&p, &self.v));
+        let beta = dot(&w, &cross(&self.u, &p));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some(record(ray, t, n.normalized(), (alpha, beta), self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let o = self.origin;
+        Some(Aabb::from_points(&[o, o + self.u, o + self.v, o + self.u + self.v]).padded(1e-4))
+    }
+}
+
+// slab test against the box from -half to half around the origin, returns the
+// distance, the hit face as axis and side, and the hit point in box coordinates
+fn box_hit(half: &Vec3, origin: &Vec3, direction: &Vec3, t_range: &Interval) -> Option<(f32, usize, f32, Vec3)> {
+    let (mut t_near, mut t_far) = (f32::MIN, f32::MAX);
+    let (mut near_face, mut far_face) = ((0, 0.0), (0, 0.0));
+    for axis in 0..3 {
+        if direction[axis].abs() < 1e-12 {
+            if origin[axis].abs() > half[axis] {
+                return None;
+            }
+            continue;
+        }
+        let inv = 1.0 / direction[axis];
+        let t0 = (-half[axis] - origin[axis]) * inv;
+        let t1 = (half[axis] - origin[axis]) * inv;
+        // the side of the box the ray enters through along this axis
+        let side = if inv < 0.0 { 1.0 } else { -1.0 };
+        let (t0, t1) = if inv < 0.0 { (t1, t0) } else { (t0, t1) };
+        if t0 > t_near {
+            t_near = t0;
+            near_face = (axis, side);
+        }
+        if t1 < t_far {
+            t_far = t1;
+            far_face = (axis, -side);
+        }
+    }
+    if t_near > t_far {
+        return None;
+    }
+    let (t, (axis, side)) = if t_range.is_contained(t_near) {
+        (t_near, near_face)
+    } else if t_range.is_contained(t_far) {
+        (t_far, far_face)
+    } else {
+        return None;
+    };
+    Some((t, axis, side, *origin + *direction * t))
+}
+
+// coordinates on a box face: the two other axes mapped to [0, 1]
+fn box_face_uv(p: &Vec3, half: &Vec3, axis: usize) -> (f32, f32) {
+    let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+    (0.5 + 0.5 * p[i] / half[i], 0.5 + 0.5 * p[j] / half[j])
+}
+
+// axis-aligned box between the corners min and max
+#[derive(Clone, Copy)]
+pub struct Cuboid {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub material: Material,
+}
+
+impl Cuboid {
+    pub fn new(a: Vec3, b: Vec3, material: Material) -> Cuboid {
+        Cuboid { min: a.min(&b), max: a.max(&b), material }
+    }
+}
+
+impl Hitable for Cuboid {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let center = (self.min + self.max) * 0.5;
+        let half = (self.max - self.min) * 0.5;
+        let (t, axis, side, p) = box_hit(&half, &(ray.origin - center), &ray.direction, t_range)?;
+        let mut normal = Vec3::new();
+        normal[axis] = side;
+        Some(record(ray, t, normal, box_face_uv(&p, &half, axis), self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+}
+
+// box of the given half size around center, its edges along the unit vectors of axes
+#[derive(Clone, Copy)]
+pub struct OrientedBox {
+    pub center: Vec3,
+    pub half_size: Vec3,
+    pub axes: [Vec3; 3],
+    pub material: Material,
+}
+
+impl OrientedBox {
+    // the axis-aligned box of the given size turned by the rotation
+    pub fn new(center: Vec3, size: Vec3, rotation: &Transform, material: Material) -> OrientedBox {
+        let axes = [
+            rotation.vector(&Vec3 { x: 1.0, y: 0.0, z: 0.0 }).normalized(),
+            rotation.vector(&Vec3 { x: 0.0, y: 1.0, z: 0.0 }).normalized(),
+            rotation.vector(&Vec3 { x: 0.0, y: 0.0, z: 1.0 }).normalized(),
+        ];
+        OrientedBox { center, half_size: size * 0.5, axes, material }
+    }
+
+    fn local(&self, a: &Vec3) -> Vec3 {
+        Vec3 { x: dot(a, &self.axes[0]), y: dot(a, &self.axes[1]), z: dot(a, &self.axes[2]) }
+    }
+}
+
+impl Hitable for OrientedBox {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let origin = self.local(&(ray.origin - self.center));
+        let direction = self.local(&ray.direction);
+        let (t, axis, side, p) = box_hit(&self.half_size, &origin, &direction, t_range)?;
+        let uv = box_face_uv(&p, &self.half_size, axis);
+        Some(record(ray, t, self.axes[axis] * side, uv, self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut e = Vec3::new();
+        for i in 0..3 {
+            for (axis, h) in self.axes.iter().zip([self.half_size.x, self.half_size.y, self.half_size.z]) {
+                e[i] += (axis[i] * h).abs();
+            }
+        }
+        Some(Aabb::new(self.center - e, self.center + e))
+    }
+}
+
+// distance, local normal and uv of a hit on one part of a primitive
+type Candidate = (f32, Vec3, (f32, f32));
+
+// closest of the candidate hits inside the range
+fn closest(candidates: &[Option<Candidate>], t_range: &Interval) -> Option<Candidate> {
+    candidates
+        .iter()
+        .flatten()
+        .filter(|c| t_range.is_contained(c.0))
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .copied()
+}
+
+// cylinder standing on the disk around base with the axis as normal, closed by
+// two caps; on the side uv are the angle and the height, on the caps as on a Disk
+#[derive(Clone, Copy)]
+pub struct Cylinder {
+    pub base: Vec3,
+    pub axis: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub material: Material,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, axis: Vec3, radius: f32, height: f32, material: Material) -> Cylinder {
+        Cylinder { base, axis: axis.normalized(), radius, height, material }
+    }
+}
+
+impl Hitable for Cylinder {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let frame = Frame::new(&self.axis);
+        let o = frame.local(&(ray.origin - self.base));
+        let d = frame.local(&ray.direction);
+        let (r, h) = (self.radius, self.height);
+
+        let side = |t: f32| {
+            let p = o + d * t;
+            if p.z < 0.0 || p.z > h {
+                return None;
+            }
+            let normal = Vec3 { x: p.x / r, y: p.y / r, z: 0.0 };
+            Some((t, normal, (polar_uv(&p, r).0, p.z / h)))
+        };
+        let cap = |z: f32, normal_z: f32| {
+            if d.z.abs() < 1e-12 {
+                return None;
+            }
+            let t = (z - o.z) / d.z;
+            let p = o + d * t;
+            if p.x * p.x + p.y * p.y > r * r {
+                return None;
+            }
+            Some((t, Vec3 { x: 0.0, y: 0.0, z: normal_z }, polar_uv(&p, r)))
+        };
+        let (t0, t1) = solve_quadratic(d.x * d.x + d.y * d.y, 2.0 * (o.x * d.x + o.y * d.y), o.x * o.x + o.y * o.y - r * r)
+            .map_or((None, None), |(t0, t1)| (Some(t0), Some(t1)));
+        let candidates = [t0.and_then(side), t1.and_then(side), cap(0.0, -1.0), cap(h, 1.0)];
+        let (t, normal, uv) = closest(&candidates, t_range)?;
+        Some(record(ray, t, frame.world(&normal), uv, self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let frame = Frame::new(&self.axis);
+        let bottom = frame.disk_bounds(&self.base, self.radius);
+        let top = frame.disk_bounds(&(self.base + self.axis * self.height), self.radius);
+        Some(bottom.union(&top).padded(1e-4))
+    }
+}
+
+// cone on the disk around base with its apex height along the axis above it,
+// closed by the base; uv as on a Cylinder
+#[derive(Clone, Copy)]
+pub struct Cone {
+    pub base: Vec3,
+    pub axis: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub material: Material,
+}
+
+impl Cone {
+    pub fn new(base: Vec3, axis: Vec3, radius: f32, height: f32, material: Material) -> Cone {
+        Cone { base, axis: axis.normalized(), radius, height, material }
+    }
+}
+
+impl Hitable for Cone {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let frame = Frame::new(&self.axis);
+        let o = frame.local(&(ray.origin - self.base));
+        let d = frame.local(&ray.direction);
+        let (r, h) = (self.radius, self.height);
+        // x^2 + y^2 = (s (h - z))^2 with s the radius per unit of height
+        let s2 = (r / h) * (r / h);
+        let q = h - o.z;
+
+        let side = |t: f32| {
+            let p = o + d * t;
+            if p.z < 0.0 || p.z > h {
+                return None;
+            }
+            let rho = (p.x * p.x + p.y * p.y).sqrt();
+            let normal = Vec3 { x: p.x, y: p.y, z: rho * r / h }.normalized();
+            Some((t, normal, (polar_uv(&p, r).0, p.z / h)))
+        };
+        let base = || {
+            if d.z.abs() < 1e-12 {
+                return None;
+            }
+            let t = -o.z / d.z;
+            let p = o + d * t;
+            if p.x * p.x + p.y * p.y > r * r {
+                return None;
+            }
+            Some((t, Vec3 { x: 0.0, y: 0.0, z: -1.0 }, polar_uv(&p, r)))
+        };
+        let (t0, t1) = solve_quadratic(
+            d.x * d.x + d.y * d.y - s2 * d.z * d.z,
+            2.0 * (o.x * d.x + o.y * d.y + s2 * q * d.z),
+            o.x * o.x + o.y * o.y - s2 * q * q,
+        )
+        .map_or((None, None), |(t0, t1)| (Some(t0), Some(t1)));
+        let candidates = [t0.and_then(side), t1.and_then(side), base()];
+        let (t, normal, uv) = closest(&candidates, t_range)?;
+        Some(record(ray, t, frame.world(&normal), uv, self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bottom = Frame::new(&self.axis).disk_bounds(&self.base, self.radius);
+        Some(bottom.grow(&(self.base + self.axis * self.height)).padded(1e-4))
+    }
+}
+
+// torus around center in the plane normal to the axis, the tube of minor radius
+// follows the circle of major radius; u goes around the axis, v around the tube
+#[derive(Clone, Copy)]
+pub struct Torus {
+    pub center: Vec3,
+    pub axis: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    pub material: Material,
+}
+
+impl Torus {
+    pub fn new(center: Vec3, axis: Vec3, major_radius: f32, minor_radius: f32, material: Material) -> Torus {
+        Torus { center, axis: axis.normalized(), major_radius, minor_radius, material }
+    }
+}
+
+impl Hitable for Torus {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let frame = Frame::new(&self.axis);
+        let length = ray.direction.len();
+        if length == 0.0 {
+            return None;
+        }
+        // solved in f64 along the unit direction, from the point of the ray closest
+        // to the center, which keeps the quartic well conditioned far from the torus
+        let d = frame.local(&ray.direction) / length;
+        let o = frame.local(&(ray.origin - self.center));
+        let shift = -dot(&o, &d);
+        let o = o + d * shift;
+        let (big, small) = (self.major_radius as f64, self.minor_radius as f64);
+        if dot(&o, &o) as f64 > (big + small) * (big + small) {
+            return None;
+        }
+        let (ox, oy, oz) = (o.x as f64, o.y as f64, o.z as f64);
+        let (dx, dy, dz) = (d.x as f64, d.y as f64, d.z as f64);
+        // (|o + s d|^2 + R^2 - r^2)^2 = 4 R^2 ((ox + s dx)^2 + (oy + s dy)^2), |d| = 1
+        let h = 2.0 * (ox * dx + oy * dy + oz * dz);
+        let k = ox * ox + oy * oy + oz * oz + big * big - small * small;
+        let r4 = 4.0 * big * big;
+        let coefficients = [
+            k * k - r4 * (ox * ox + oy * oy),
+            2.0 * h * k - 2.0 * r4 * (ox * dx + oy * dy),
+            h * h + 2.0 * k - r4 * (dx * dx + dy * dy),
+            2.0 * h,
+        ];
+        let t = solve_quartic(&coefficients)
+            .into_iter()
+            .map(|s| (s as f32 + shift) / length)
+            .filter(|t| t_range.is_contained(*t))
+            .min_by(|a, b| a.total_cmp(b))?;
+
+        let p = frame.local(&(ray.at(t) - self.center));
+        let rho = (p.x * p.x + p.y * p.y).sqrt();
+        let ring = if rho > 0.0 {
+            Vec3 { x: p.x / rho, y: p.y / rho, z: 0.0 } * self.major_radius
+        } else {
+            Vec3::new()
+        };
+        let normal = (p - ring).normalized();
+        let u = p.y.atan2(p.x) / (2.0 * PI) + 0.5;
+        let v = p.z.atan2(rho - self.major_radius) / (2.0 * PI) + 0.5;
+        Some(record(ray, t, frame.world(&normal), (u, v), self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let disk = Frame::new(&self.axis).disk_bounds(&self.center, self.major_radius);
+        Some(disk.padded(self.minor_radius + 1e-4))
+    }
+}
+
+// real roots of s^4 + c[3] s^3 + c[2] s^2 + c[1] s + c[0] by Ferrari's method,
+// each polished with a few Newton steps
+fn solve_quartic(c: &[f64; 4]) -> Vec<f64> {
+    let (a, b, cc, d) = (c[3], c[2], c[1], c[0]);
+    // depressed quartic y^4 + p y^2 + q y + r with s = y - a / 4
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = cc - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * cc / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut roots = Vec::with_capacity(4);
+    let mut push_quadratic = |b: f64, c: f64| {
+        let discriminant = b * b - 4.0 * c;
+        if discriminant >= 0.0 {
+            let sq = discriminant.sqrt();
+            roots.push((-b - sq) / 2.0);
+            roots.push((-b + sq) / 2.0);
+        }
+    };
+    if q.abs() < 1e-12 {
+        // biquadratic in y^2
+        let discriminant = p * p - 4.0 * r;
+        if discriminant >= 0.0 {
+            let sq = discriminant.sqrt();
+            for y2 in [(-p - sq) / 2.0, (-p + sq) / 2.0] {
+                if y2 >= 0.0 {
+                    push_quadratic(0.0, -y2);
+                }
+            }
+        }
+    } else {
+        // m > 0 making y^4 + p y^2 + q y + r = (y^2 + p / 2 + m)^2 - (s y - q / (2 s))^2, s = sqrt(2 m)
+        let m = largest_cubic_root(p, p * p / 4.0 - r, -q * q / 8.0);
+        if m <= 0.0 {
+            return Vec::new();
+        }
+        let s = (2.0 * m).sqrt();
+        push_quadratic(-s, p / 2.0 + m + q / (2.0 * s));
+        push_quadratic(s, p / 2.0 + m - q / (2.0 * s));
+    }
+
+    let eval = |x: f64| (((x + a) * x + b) * x + cc) * x + d;
+    let derivative = |x: f64| ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + cc;
+    roots
+        .into_iter()
+        .map(|y| {
+            let mut x = y - a / 4.0;
+            for _ in 0..3 {
+                let slope = derivative(x);
+                if slope.abs() < 1e-12 {
+                    break;
+                }
+                x -= eval(x) / slope;
+            }
+            x
+        })
+        .collect()
+}
+
+// largest real root of x^3 + a x^2 + b x + c
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // depressed cubic z^3 + p z + q with x = z - a / 3
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+    let z = if discriminant > 0.0 {
+        let sq = discriminant.sqrt();
+        (-q / 2.0 + sq).cbrt() + (-q / 2.0 - sq).cbrt()
+    } else if p == 0.0 {
+        0.0
+    } else {
+        let rho = (-p / 3.0).sqrt();
+        let phi = (-q / (2.0 * rho * rho * rho)).clamp(-1.0, 1.0).acos();
+        2.0 * rho * (phi / 3.0).cos()
+    };
+    z - a / 3.0
+}
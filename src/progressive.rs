@@ -0,0 +1,156 @@
+use std::time::{Duration, Instant};
+use super::basic::*;
+use super::tracer::*;
+use super::mesh::*;
+use super::volume::*;
+use super::renderer::*;
+
+// when a progressive render stops, every criterion that is set is checked after each pass
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StopCondition {
+    pub max_samples: Option<u32>, // samples per pixel
+    pub time_budget: Option<Duration>,
+    pub target_noise: Option<f32>, // relative RMS error, see ProgressiveRenderer::noise_estimate
+}
+
+impl StopCondition {
+    pub fn samples(n: u32) -> StopCondition {
+        StopCondition { max_samples: Some(n), ..Default::default() }
+    }
+
+    pub fn time(budget: Duration) -> StopCondition {
+        StopCondition { time_budget: Some(budget), ..Default::default() }
+    }
+
+    pub fn noise(target: f32) -> StopCondition {
+        StopCondition { target_noise: Some(target), ..Default::default() }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Samples,
+    Time,
+    Noise,
+    Unbounded, // no criterion was set, a single pass was rendered
+}
+
+// refines the whole image in passes of settings.samples_per_pixel samples per pixel
+pub struct ProgressiveRenderer<'a> {
+    camera: &'a Camera,
+    world: &'a dyn Hitable,
+    fog: Option<&'a Fog>,
+    pub settings: RenderSettings,
+    pub buffer: FrameBuffer, // every sample so far
+    pub(crate) even: FrameBuffer, // samples of the even passes, compared with the odd ones to estimate noise
+    pub(crate) odd: FrameBuffer,
+    pub passes: u32,
+    pub elapsed: Duration,
+}
+
+impl<'a> ProgressiveRenderer<'a> {
+    pub fn new(
+        camera: &'a Camera,
+        world: &'a dyn Hitable,
+        fog: Option<&'a Fog>,
+        settings: RenderSettings,
+    ) -> ProgressiveRenderer<'a> {
+        let (width, height) = (settings.width, settings.height);
+        ProgressiveRenderer {
+            camera,
+            world,
+            fog,
+            settings,
+            buffer: FrameBuffer::new(width, height),
+            even: FrameBuffer::new(width, height),
+            odd: FrameBuffer::new(width, height),
+            passes: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    // samples per pixel accumulated so far
+    pub fn samples(&self) -> u32 {
+        self.passes * self.settings.samples_per_pixel
+    }
+
+    // trace one more pass, continuing the sample indices of the previous ones
+    pub fn render_pass(&mut self) {
+        let start = Instant::now();
+        let first = self.samples();
+        let range = first..first + self.settings.samples_per_pixel;
+        let half = if self.passes.is_multiple_of(2) { &mut self.even } else { &mut self.odd };
+        let (buffer, filter) = (&mut self.buffer, self.settings.filter);
+        trace_rows(self.camera, self.world, self.fog, &self.settings, |_, _| range.clone(), |_, traced| {
+            for s in traced {
+                buffer.splat(s.x, s.y, s.color, &filter);
+                half.splat(s.x, s.y, s.color, &filter);
+            }
+        });
+        self.passes += 1;
+        self.elapsed += start.elapsed();
+    }
+
+    // relative RMS error of the image, estimated from the difference between the
+    // even and the odd passes; None before the second pass
+    pub fn noise_estimate(&self) -> Option<f32> {
+        if self.passes < 2 {
+            return None;
+        }
+        let (width, height) = (self.settings.width, self.settings.height);
+        let mut sum = 0.0f64;
+        for row in 0..height {
+            for col in 0..width {
+                let diff = luminance(&self.even.get(row, col)) - luminance(&self.odd.get(row, col));
+                let mean = luminance(&self.buffer.get(row, col));
+                // the two halves each carry about twice the variance of the whole
+                let err = 0.5 * diff / (mean.abs() + 1e-2);
+                sum += (err * err) as f64;
+            }
+        }
+        Some((sum / (width * height).max(1) as f64).sqrt() as f32)
+    }
+
+    // the current image through the output transform of the settings
+    pub fn image(&self) -> Image {
+        self.settings.output.to_image(&self.buffer)
+    }
+
+    pub fn write_snapshot(&self, filename: &str) {
+        write_p3_file(filename, &self.image());
+    }
+
+    // None before the first pass, so there is always an image to show
+    pub fn should_stop(&self, stop: &StopCondition) -> Option<StopReason> {
+        if self.passes == 0 {
+            return None;
+        }
+        if stop.max_samples.is_some_and(|n| self.samples() >= n) {
+            return Some(StopReason::Samples);
+        }
+        if stop.time_budget.is_some_and(|t| self.elapsed >= t) {
+            return Some(StopReason::Time);
+        }
+        if let (Some(target), Some(noise)) = (stop.target_noise, self.noise_estimate()) {
+            if noise <= target {
+                return Some(StopReason::Noise);
+            }
+        }
+        if *stop == StopCondition::default() {
+            return Some(StopReason::Unbounded);
+        }
+        None
+    }
+
+    // render passes until the stop condition is met, calling on_pass after each
+    // one, e.g. to write a snapshot of the image
+    pub fn run<F: FnMut(&ProgressiveRenderer)>(&mut self, stop: &StopCondition, mut on_pass: F) -> StopReason {
+        loop {
+            if let Some(reason) = self.should_stop(stop) {
+                return reason;
+            }
+            self.render_pass();
+            on_pass(self);
+        }
+    }
+}
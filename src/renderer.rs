@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use super::basic::*;
+use super::tracer::*;
+use super::mesh::*;
+use super::volume::*;
+use super::sampler::*;
+
+// HDR accumulation buffer holding weighted sums of linear radiance
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub(crate) sum: Vec<Vec3>,
+    pub(crate) weight: Vec<f32>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> FrameBuffer {
+        FrameBuffer {
+            width,
+            height,
+            sum: vec![Vec3::new(); width * height],
+            weight: vec![0.0; width * height],
+        }
+    }
+
+    #[inline]
+    pub fn add_sample(&mut self, row: usize, col: usize, color: Vec3, weight: f32) {
+        let i = row * self.width + col;
+        self.sum[i] += color * weight;
+        self.weight[i] += weight;
+    }
+
+    // weighted mean radiance of the pixel, black if it has no samples yet
+    #[inline]
+    pub fn get(&self, row: usize, col: usize) -> Vec3 {
+        let i = row * self.width + col;
+        if self.weight[i] <= 0.0 {
+            return Vec3::new();
+        }
+        self.sum[i] / self.weight[i]
+    }
+
+    // splat a sample at the continuous raster position (x, y) into every pixel
+    // whose center lies within the filter radius; pixel (row, col) is centered
+    // at (col + 0.5, row + 0.5)
+    pub fn splat(&mut self, x: f32, y: f32, color: Vec3, filter: &Filter) {
+        let r = filter.radius();
+        let col_min = (x - 0.5 - r).ceil().max(0.0) as usize;
+        let row_min = (y - 0.5 - r).ceil().max(0.0) as usize;
+        let col_max = ((x - 0.5 + r).floor() as i64).min(self.width as i64 - 1);
+        let row_max = ((y - 0.5 + r).floor() as i64).min(self.height as i64 - 1);
+        if col_max < 0 || row_max < 0 {
+            return;
+        }
+        for row in row_min..=row_max as usize {
+            for col in col_min..=col_max as usize {
+                let w = filter.eval(col as f32 + 0.5 - x, row as f32 + 0.5 - y);
+                if w != 0.0 {
+                    self.add_sample(row, col, color, w);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sum.iter_mut().for_each(|s| *s = Vec3::new());
+        self.weight.iter_mut().for_each(|w| *w = 0.0);
+    }
+}
+
+// maps HDR radiance to [0, 1]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard, // L / (1 + L) on luminance
+    ReinhardExtended(f32), // luminance mapped to white
+    Aces, // Narkowicz's fit of the ACES filmic curve, per channel
+}
+
+// transfer function from linear [0, 1] to the 8-bit output values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Linear,
+    Srgb,
+    Gamma(f32), // display gamma, e.g. 2.2
+}
+
+// output stage between the HDR FrameBuffer and the 8-bit Image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputTransform {
+    pub exposure: f32, // in EV, each stop doubles the brightness
+    pub tone_map: ToneMap,
+    pub encoding: Encoding,
+}
+
+impl OutputTransform {
+    pub fn new(exposure: f32, tone_map: ToneMap, encoding: Encoding) -> OutputTransform {
+        OutputTransform { exposure, tone_map, encoding }
+    }
+
+    // linear output, as written before the transform stage existed
+    pub fn linear() -> OutputTransform {
+        OutputTransform::new(0.0, ToneMap::Clamp, Encoding::Linear)
+    }
+
+    // display value in [0, 1] for a linear radiance
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let exposed = color * self.exposure.exp2();
+        let mapped = tone_map(exposed, self.tone_map);
+        Vec3 {
+            x: encode(mapped.x, self.encoding),
+            y: encode(mapped.y, self.encoding),
+            z: encode(mapped.z, self.encoding),
+        }
+    }
+
+    pub fn to_image(&self, buffer: &FrameBuffer) -> Image {
+        let mut image = Image::new(buffer.width, buffer.height);
+        for row in 0..buffer.height {
+            for col in 0..buffer.width {
+                image[row][col] = Pix::from_vec3(self.apply(buffer.get(row, col)));
+            }
+        }
+        image
+    }
+}
+
+impl Default for OutputTransform {
+    fn default() -> OutputTransform {
+        OutputTransform::new(0.0, ToneMap::Clamp, Encoding::Srgb)
+    }
+}
+
+// Rec. 709 relative luminance
+#[inline]
+pub fn luminance(c: &Vec3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+pub fn tone_map(color: Vec3, op: ToneMap) -> Vec3 {
+    let color = color.max(&Vec3::new());
+    let mapped = match op {
+        ToneMap::Clamp => color,
+        ToneMap::Reinhard => {
+            let l = luminance(&color);
+            color / (1.0 + l)
+        }
+        ToneMap::ReinhardExtended(white) => {
+            let l = luminance(&color);
+            if l <= 0.0 {
+                color
+            } else {
+                let l_out = l * (1.0 + l / (white * white)) / (1.0 + l);
+                color * (l_out / l)
+            }
+        }
+        ToneMap::Aces => Vec3 { x: aces(color.x), y: aces(color.y), z: aces(color.z) },
+    };
+    mapped.min(&Vec3 { x: 1.0, y: 1.0, z: 1.0 })
+}
+
+#[inline]
+fn aces(x: f32) -> f32 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+// encode a linear value in [0, 1]
+pub fn encode(v: f32, encoding: Encoding) -> f32 {
+    match encoding {
+        Encoding::Linear => v,
+        Encoding::Srgb => {
+            if v <= 0.0031308 {
+                12.92 * v
+            } else {
+                1.055 * v.powf(1.0 / 2.4) - 0.055
+            }
+        }
+        Encoding::Gamma(gamma) => v.powf(1.0 / gamma),
+    }
+}
+
+// pixel reconstruction filters, separable in x and y, radii in pixels; radii below
+// 0.5 are taken as 0.5, smaller ones would leave pixels without any sample
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Box(f32), // radius, 0.5 covers exactly one pixel
+    Tent(f32), // radius
+    Gaussian(f32, f32), // radius, falloff
+    Mitchell(f32, f32, f32), // radius, B, C; (1/3, 1/3) is the recommended pair
+}
+
+impl Filter {
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box(r) | Filter::Tent(r) | Filter::Gaussian(r, _) | Filter::Mitchell(r, _, _) => r.max(0.5),
+        }
+    }
+
+    // filter weight for a sample at offset (dx, dy) from the pixel center
+    pub fn eval(&self, dx: f32, dy: f32) -> f32 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+
+    fn eval_1d(&self, d: f32) -> f32 {
+        let (d, r) = (d.abs(), self.radius());
+        match *self {
+            Filter::Box(_) => if d <= r { 1.0 } else { 0.0 },
+            Filter::Tent(_) => (1.0 - d / r).max(0.0),
+            Filter::Gaussian(_, alpha) => ((-alpha * d * d).exp() - (-alpha * r * r).exp()).max(0.0),
+            Filter::Mitchell(_, b, c) => {
+                // the Mitchell-Netravali kernel is defined on [-2, 2]
+                let x = 2.0 * d / r;
+                if x >= 2.0 {
+                    0.0
+                } else if x >= 1.0 {
+                    ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                } else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b)) / 6.0
+                }
+            }
+        }
+    }
+}
+
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: u32,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub max_depth: u32,
+    pub output: OutputTransform,
+    pub seed: u64, // the image is a pure function of the scene, the settings and this seed
+    pub threads: usize, // 0 uses every available core
+}
+
+impl RenderSettings {
+    pub fn new(width: usize, height: usize) -> RenderSettings {
+        RenderSettings {
+            width,
+            height,
+            samples_per_pixel: 1,
+            sampler: SamplerKind::Sobol,
+            filter: Filter::Box(0.5),
+            max_depth: 50,
+            output: OutputTransform::default(),
+            seed: 0,
+            threads: 0,
+        }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        if self.threads > 0 {
+            return self.threads;
+        }
+        thread::available_parallelism().map_or(1, |n| n.get())
+    }
+}
+
+// one traced sample: continuous raster position and radiance
+#[derive(Clone, Copy, Debug)]
+pub struct PixelSample {
+    pub x: f32,
+    pub y: f32,
+    pub color: Vec3,
+}
+
+// trace the sample indices given by `samples` for every pixel, spread over the
+// worker threads one row at a time; each sample only depends on the seed and its
+// (row, col, index) so the rows handed to `consume`, always in row order, are
+// identical for any thread count or scheduling
+pub fn trace_rows<F, C>(
+    camera: &Camera,
+    world: &dyn Hitable,
+    fog: Option<&Fog>,
+    settings: &RenderSettings,
+    samples: F,
+    mut consume: C,
+) where
+    F: Fn(usize, usize) -> Range<u32> + Sync,
+    C: FnMut(usize, Vec<PixelSample>),
+{
+    let (width, height) = (settings.width, settings.height);
+    let next_row = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..settings.thread_count() {
+            let tx = tx.clone();
+            let (next_row, samples) = (&next_row, &samples);
+            scope.spawn(move || {
+                let mut sampler = settings.sampler.create(settings.samples_per_pixel, settings.seed);
+                loop {
+                    let row = next_row.fetch_add(1, Ordering::Relaxed);
+                    if row >= height {
+                        break;
+                    }
+                    let mut traced = Vec::new();
+                    for col in 0..width {
+                        for index in samples(row, col) {
+                            sampler.start_sample(row, col, index);
+                            // random numbers drawn outside the sampler, e.g. by media
+                            seed_random(hash(&[settings.seed, row as u64, col as u64, u64::MAX]), index as u64);
+                            let (sx, sy) = sampler.get_2d();
+                            let x = col as f32 + sx;
+                            let y = row as f32 + sy;
+                            let ray = camera.get_ray_lens(x / width as f32, y / height as f32, sampler.get_2d());
+                            let color = ray_color(&ray, world, fog, settings.max_depth, sampler.as_mut());
+                            traced.push(PixelSample { x, y, color });
+                        }
+                    }
+                    if tx.send((row, traced)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // hand the rows over in order so the accumulation order is fixed too
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (row, traced) in rx {
+            pending.insert(row, traced);
+            while let Some(traced) = pending.remove(&next) {
+                consume(next, traced);
+                next += 1;
+            }
+        }
+    });
+}
+
+// render the world seen by the camera into an HDR buffer
+pub fn render(camera: &Camera, world: &dyn Hitable, fog: Option<&Fog>, settings: &RenderSettings) -> FrameBuffer {
+    let mut buffer = FrameBuffer::new(settings.width, settings.height);
+    trace_rows(camera, world, fog, settings, |_, _| 0..settings.samples_per_pixel, |_, traced| {
+        for s in traced {
+            buffer.splat(s.x, s.y, s.color, &settings.filter);
+        }
+    });
+    buffer
+}
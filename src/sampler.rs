@@ -0,0 +1,432 @@
+use std::sync::OnceLock;
+use super::basic::*;
+
+// source of sample dimensions for Monte Carlo integration: every pixel sample
+// draws its pixel position, lens position, and scattering decisions in order
+pub trait Sampler {
+    // prepare the dimensions of sample `index` of pixel (row, col)
+    fn start_sample(&mut self, row: usize, col: usize, index: u32);
+    // next dimension, in [0, 1)
+    fn get_1d(&mut self) -> f32;
+    // next two dimensions, in [0, 1)^2
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol, // Owen-scrambled
+    BlueNoise,
+}
+
+impl SamplerKind {
+    // every sampler is a pure function of (seed, pixel, sample index, dimension)
+    pub fn create(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler + Send> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
+
+// random stream of one pixel sample, derived from the global seed and the sample coordinates
+#[inline]
+pub fn sample_stream(seed: u64, row: usize, col: usize, index: u32) -> Pcg32 {
+    Pcg32::new(hash(&[seed, row as u64, col as u64]), index as u64)
+}
+
+// uniform random numbers, no stratification at all
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Pcg32,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler { seed, rng: Pcg32::new(seed, 0) }
+    }
+}
+
+impl Default for IndependentSampler {
+    fn default() -> IndependentSampler {
+        IndependentSampler::new(0)
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32) {
+        self.rng = sample_stream(self.seed, row, col, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.rng.next_f32(), self.rng.next_f32())
+    }
+}
+
+// jittered strata, the samples of a pixel visit the strata of each dimension
+// in an independently shuffled order
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dim: u32,
+    rng: Pcg32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> StratifiedSampler {
+        StratifiedSampler {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            pixel: 0,
+            index: 0,
+            dim: 0,
+            rng: Pcg32::new(seed, 0),
+        }
+    }
+
+    fn stratum(&mut self, count: u32) -> u32 {
+        let seed = hash(&[self.pixel, self.dim as u64]) as u32;
+        self.dim += 1;
+        permutation_element(self.index % count, count, seed)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32) {
+        self.pixel = hash(&[self.seed, row as u64, col as u64]);
+        self.index = index;
+        self.dim = 0;
+        self.rng = sample_stream(self.seed, row, col, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let n = self.samples_per_pixel;
+        let stratum = self.stratum(n);
+        ((stratum as f32 + self.rng.next_f32()) / n as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let nx = (self.samples_per_pixel as f32).sqrt().ceil() as u32;
+        let ny = self.samples_per_pixel.div_ceil(nx);
+        let stratum = self.stratum(nx * ny);
+        let (sx, sy) = (stratum % nx, stratum / nx);
+        (
+            ((sx as f32 + self.rng.next_f32()) / nx as f32).min(ONE_MINUS_EPSILON),
+            ((sy as f32 + self.rng.next_f32()) / ny as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+// Halton sequence, one prime base per dimension, decorrelated between pixels
+// by a per pixel Cranley-Patterson rotation
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dim: u32,
+    rng: Pcg32,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler { seed, pixel: 0, index: 0, dim: 0, rng: Pcg32::new(seed, 0) }
+    }
+}
+
+impl Default for HaltonSampler {
+    fn default() -> HaltonSampler {
+        HaltonSampler::new(0)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32) {
+        self.pixel = hash(&[self.seed, row as u64, col as u64]);
+        self.index = index;
+        self.dim = 0;
+        self.rng = sample_stream(self.seed, row, col, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dim = self.dim as usize;
+        self.dim += 1;
+        if dim >= PRIMES.len() {
+            // higher dimensions of the Halton sequence are badly correlated
+            return self.rng.next_f32();
+        }
+        let shift = to_unit_float(hash(&[self.pixel, dim as u64]) as u32);
+        let v = radical_inverse(PRIMES[dim], self.index as u64) + shift;
+        (v - v.floor()).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Owen-scrambled Sobol points (Burley 2020): every pair of dimensions is a
+// scrambled (0, 2)-sequence, and pairs are decorrelated by shuffling the index
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dim: u32,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler { seed, pixel: 0, index: 0, dim: 0 }
+    }
+
+    fn next_seeds(&mut self) -> (u32, u32, u32) {
+        let seed = hash(&[self.pixel, self.dim as u64]);
+        self.dim += 1;
+        let shuffle = nested_uniform_scramble(self.index, seed as u32);
+        (shuffle, (seed >> 32) as u32, mix_bits(seed) as u32)
+    }
+}
+
+impl Default for SobolSampler {
+    fn default() -> SobolSampler {
+        SobolSampler::new(0)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32) {
+        self.pixel = hash(&[self.seed, row as u64, col as u64]);
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (index, seed_x, _) = self.next_seeds();
+        to_unit_float(nested_uniform_scramble(sobol(index, 0), seed_x))
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (index, seed_x, seed_y) = self.next_seeds();
+        (
+            to_unit_float(nested_uniform_scramble(sobol(index, 0), seed_x)),
+            to_unit_float(nested_uniform_scramble(sobol(index, 1), seed_y)),
+        )
+    }
+}
+
+const BLUE_NOISE_SIZE: usize = 32;
+
+// rank-1 lattice sequences (golden ratio and R2) whose per pixel offsets come
+// from a blue noise mask, so the error is distributed as blue noise on screen
+pub struct BlueNoiseSampler {
+    seed: u64,
+    row: usize,
+    col: usize,
+    index: u32,
+    dim: u32,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> BlueNoiseSampler {
+        BlueNoiseSampler { seed, row: 0, col: 0, index: 0, dim: 0 }
+    }
+
+    // mask value for the current pixel, toroidally shifted for every dimension
+    fn offset(&mut self) -> f32 {
+        let h = hash(&[self.seed, self.dim as u64]);
+        self.dim += 1;
+        let dx = (h as usize) % BLUE_NOISE_SIZE;
+        let dy = ((h >> 32) as usize) % BLUE_NOISE_SIZE;
+        let mask = blue_noise_mask();
+        mask[((self.row + dy) % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + (self.col + dx) % BLUE_NOISE_SIZE]
+    }
+}
+
+impl Default for BlueNoiseSampler {
+    fn default() -> BlueNoiseSampler {
+        BlueNoiseSampler::new(0)
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, row: usize, col: usize, index: u32) {
+        self.row = row;
+        self.col = col;
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        const G: f64 = 0.618_033_988_749_894_9;
+        let v = (self.index as f64 * G).fract() as f32 + self.offset();
+        (v - v.floor()).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        // R2 sequence, based on the plastic number
+        const A1: f64 = 0.754_877_666_246_692_8;
+        const A2: f64 = 0.569_840_290_998_053_3;
+        let x = (self.index as f64 * A1).fract() as f32 + self.offset();
+        let y = (self.index as f64 * A2).fract() as f32 + self.offset();
+        ((x - x.floor()).min(ONE_MINUS_EPSILON), (y - y.floor()).min(ONE_MINUS_EPSILON))
+    }
+}
+
+// tileable blue noise ranks in [0, 1), built once with the void-and-cluster
+// ranking: every new point goes into the largest remaining void
+pub fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        const N: usize = BLUE_NOISE_SIZE;
+        const SIGMA: f32 = 1.9;
+        let mut kernel = vec![0.0f32; N * N];
+        for dy in 0..N {
+            for dx in 0..N {
+                let x = dx.min(N - dx) as f32;
+                let y = dy.min(N - dy) as f32;
+                kernel[dy * N + dx] = (-(x * x + y * y) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+
+        let mut energy = vec![0.0f32; N * N];
+        let mut rank = vec![usize::MAX; N * N];
+        let mut next = 0;
+        for r in 0..N * N {
+            rank[next] = r;
+            let (px, py) = (next % N, next / N);
+            for y in 0..N {
+                for x in 0..N {
+                    let k = ((y + N - py) % N) * N + (x + N - px) % N;
+                    energy[y * N + x] += kernel[k];
+                }
+            }
+            next = (0..N * N)
+                .filter(|&i| rank[i] == usize::MAX)
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap_or(0);
+        }
+        rank.iter().map(|&r| (r as f32 + 0.5) / (N * N) as f32).collect()
+    })
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON * 0.5;
+
+#[inline]
+fn to_unit_float(v: u32) -> f32 {
+    (v >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+// digits of index in the given base mirrored about the radix point
+pub fn radical_inverse(base: u32, mut index: u64) -> f32 {
+    let base = base as u64;
+    let inv_base = 1.0 / base as f64;
+    let mut reversed = 0u64;
+    let mut inv_base_n = 1.0f64;
+    while index > 0 {
+        let next = index / base;
+        reversed = reversed * base + (index - next * base);
+        inv_base_n *= inv_base;
+        index = next;
+    }
+    ((reversed as f64 * inv_base_n) as f32).min(ONE_MINUS_EPSILON)
+}
+
+// point `index` of the first two dimensions of the Sobol sequence, as 32-bit fractions
+pub fn sobol(index: u32, dim: u32) -> u32 {
+    if dim == 0 {
+        // the first dimension is the van der Corput sequence
+        return index.reverse_bits();
+    }
+    // second dimension, primitive polynomial x + 1: v_k = v_{k-1} ^ (v_{k-1} >> 1)
+    let mut result = 0u32;
+    let mut v = 1u32 << 31;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= v;
+        }
+        v ^= v >> 1;
+        i >>= 1;
+    }
+    result
+}
+
+// Owen scrambling of the bits of x, from the most significant bit down
+pub fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+#[inline]
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+// element i of a pseudo-random permutation of 0..n chosen by the seed (Kensler 2013)
+pub fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let p = seed;
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % n
+}
+
+// 64-bit finalizer of MurmurHash3
+#[inline]
+pub fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |h, &v| mix_bits(h ^ v.wrapping_add(0x9e3779b97f4a7c15).wrapping_add(h << 6)))
+}
@@ -0,0 +1,922 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use super::basic::*;
+use super::tracer::*;
+use super::mesh::*;
+use super::volume::*;
+use super::transform::*;
+use super::renderer::*;
+use super::sampler::*;
+use super::sphere_gen::*;
+use super::graph::*;
+use super::bvh::*;
+
+// Scene files are plain text made of statements, `#` starts a comment:
+//
+//   settings { width 400 height 200 samples 16 sampler sobol filter mitchell 2 0.33 0.33 }
+//   camera { from 0 1 3 at 0 0 0 up 0 1 0 fov 40 aperture 0.1 focus 3 }
+//   material glass dielectric 1.5 absorption 0.1 0.5 0.5
+//   material lamp emissive 4 4 4
+//   fog { density 0.05 albedo 0.9 0.9 0.9 g 0.3 }
+//   sphere { name ball material glass center 0 1 0 radius 1 subdivisions 3 }
+//   group { name lights material lamp translate 0 4 0
+//       triangle { a -1 0 -1 b 1 0 -1 c 0 0 1 }
+//       mesh { file "bunny.obj" scale 2 2 2 }
+//   }
+//
+// Objects take their material from the enclosing group unless they name one (black
+// without either), and translate, rotate (axis, degrees), scale and matrix are applied
+// in the order given. Names are optional and only used by the scene graph.
+
+// parse or load error, line and column are 1-based; 0 when the error is not tied to a position
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Clone, Copy, Debug)]
+pub enum TransformOp {
+    Translate(Vec3),
+    Rotate(Vec3, f32), // axis, angle in degrees
+    Scale(Vec3),
+    Matrix(Mat4), // must be invertible
+}
+
+impl TransformOp {
+    pub fn transform(&self) -> Transform {
+        match *self {
+            TransformOp::Translate(t) => Transform::translate(t),
+            TransformOp::Rotate(axis, degrees) => Transform::rotate(axis, degrees.to_radians()),
+            TransformOp::Scale(s) => Transform::scale(s),
+            TransformOp::Matrix(m) => Transform::from_matrix(m).unwrap_or_default(),
+        }
+    }
+}
+
+// each level of a sphere has four times the triangles of the one before
+pub const MAX_SUBDIVISIONS: u32 = 8;
+
+pub enum Shape {
+    Triangle(Vec3, Vec3, Vec3),
+    Sphere(Vec3, f32, u32), // center, radius, subdivisions of the icosahedron
+    Icosahedron(Vec3, f32), // center, radius
+    Mesh(String, Vec<Triangle>), // Wavefront OBJ file from the working directory, its triangles
+    Group(Vec<Object>),
+}
+
+pub struct Object {
+    pub name: Option<String>, // node name in the scene graph
+    pub shape: Shape,
+    pub material: Option<String>, // None takes the material of the enclosing group
+    pub transform: Vec<TransformOp>, // applied in order
+}
+
+impl Object {
+    pub fn new(shape: Shape, material: Option<String>) -> Object {
+        Object { name: None, shape, material, transform: Vec::new() }
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform.iter().fold(Transform::new(), |t, op| t.then(&op.transform()))
+    }
+}
+
+pub struct Scene {
+    pub settings: RenderSettings,
+    pub camera: Camera,
+    pub materials: Vec<(String, Material)>,
+    pub fog: Option<Fog>,
+    pub objects: Vec<Object>,
+}
+
+impl Scene {
+    // parse a scene, mesh files are looked up relative to the working directory
+    pub fn parse(src: &str) -> Result<Scene, SceneError> {
+        Parser::new(src, Path::new("."))?.scene()
+    }
+
+    // load a scene file, mesh files are looked up relative to its directory
+    pub fn load(filename: &str) -> Result<Scene, SceneError> {
+        let src = fs::read_to_string(filename).map_err(|e| SceneError {
+            line: 0,
+            col: 0,
+            message: format!("cannot read {}: {}", filename, e),
+        })?;
+        let dir = Path::new(filename).parent().unwrap_or(Path::new("."));
+        Parser::new(&src, dir)?.scene()
+    }
+
+    pub fn material(&self, name: &str) -> Option<Material> {
+        self.materials.iter().find(|(n, _)| n == name).map(|(_, m)| *m)
+    }
+
+    // the objects as one Hitable, through the flattened scene graph; every node
+    // reports its index in the graph as object id
+    pub fn world(&self) -> Bvh {
+        self.graph().flatten()
+    }
+
+    pub fn render(&self) -> FrameBuffer {
+        render(&self.camera, &self.world(), self.fog.as_ref(), &self.settings)
+    }
+
+    // the objects as a scene graph; unnamed objects are named by their keyword, and
+    // meshes read from the same file share one geometry
+    pub fn graph(&self) -> SceneGraph {
+        let mut graph = SceneGraph::new();
+        for (name, material) in &self.materials {
+            graph.materials.add(name, *material);
+        }
+        let default = graph.materials.add("", Material::default());
+        let root = graph.root();
+        for object in &self.objects {
+            self.add_node(&mut graph, root, object, default);
+        }
+        graph
+    }
+
+    fn add_node(&self, graph: &mut SceneGraph, parent: NodeId, object: &Object, inherited: MaterialId) {
+        let material = match &object.material {
+            Some(name) => graph.materials.find(name).unwrap_or(inherited),
+            None => inherited,
+        };
+        let (keyword, triangles) = match &object.shape {
+            Shape::Triangle(a, b, c) => ("triangle", vec![Triangle::new(*a, *b, *c)]),
+            Shape::Sphere(center, radius, subdivisions) => {
+                ("sphere", Sphere::with_subdivisions(*center, *radius, *subdivisions).triangles)
+            }
+            Shape::Icosahedron(center, radius) => {
+                let triangles = Icosahedron::new(Vec3::new()).triangles.into_iter().map(|t| t * *radius + *center);
+                ("icosahedron", triangles.collect())
+            }
+            Shape::Mesh(_, triangles) => ("mesh", triangles.iter().map(|t| Triangle::new(t.a, t.b, t.c)).collect()),
+            Shape::Group(_) => ("group", Vec::new()),
+        };
+        let name = object.name.as_deref().unwrap_or(keyword);
+        let node = graph.add_node(parent, name, object.transform());
+        match &object.shape {
+            Shape::Group(children) => {
+                for child in children {
+                    self.add_node(graph, node, child, material);
+                }
+            }
+            Shape::Mesh(file, _) if !file.is_empty() => {
+                let geometry = match graph.find_geometry(file) {
+                    Some(geometry) => geometry,
+                    None => graph.add_geometry(file, triangles),
+                };
+                graph.set_geometry(node, geometry, material);
+            }
+            _ => {
+                let geometry = graph.add_geometry(name, triangles);
+                graph.set_geometry(node, geometry, material);
+            }
+        }
+    }
+
+    // the scene in the text format; floats are written in their shortest exact form
+    // and the camera by its screen, so parsing the text gives back the same render.
+    // Meshes loaded from a file are written as a reference to it, others inline.
+    pub fn to_text(&self) -> String {
+        self.text_in(Path::new("."))
+    }
+
+    // the text for a file in dir, with mesh files written relative to it
+    fn text_in(&self, dir: &Path) -> String {
+        let s = &self.settings;
+        let mut out = String::new();
+        out.push_str("settings {\n");
+        out.push_str(&format!("    width {} height {} samples {}\n", s.width, s.height, s.samples_per_pixel));
+        out.push_str(&format!("    max_depth {} seed {} threads {}\n", s.max_depth, s.seed, s.threads));
+        let sampler = match s.sampler {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+            SamplerKind::BlueNoise => "blue_noise",
+        };
+        let filter = match s.filter {
+            Filter::Box(r) => format!("box {}", r),
+            Filter::Tent(r) => format!("tent {}", r),
+            Filter::Gaussian(r, alpha) => format!("gaussian {} {}", r, alpha),
+            Filter::Mitchell(r, b, c) => format!("mitchell {} {} {}", r, b, c),
+        };
+        out.push_str(&format!("    sampler {} filter {}\n", sampler, filter));
+        let tone_map = match s.output.tone_map {
+            ToneMap::Clamp => "clamp".to_string(),
+            ToneMap::Reinhard => "reinhard".to_string(),
+            ToneMap::ReinhardExtended(white) => format!("reinhard_extended {}", white),
+            ToneMap::Aces => "aces".to_string(),
+        };
+        let encoding = match s.output.encoding {
+            Encoding::Linear => "linear".to_string(),
+            Encoding::Srgb => "srgb".to_string(),
+            Encoding::Gamma(gamma) => format!("gamma {}", gamma),
+        };
+        out.push_str(&format!("    exposure {} tone_map {} encoding {}\n}}\n", s.output.exposure, tone_map, encoding));
+
+        let c = &self.camera;
+        out.push_str("camera {\n");
+        out.push_str(&format!("    origin {}\n", vec3_text(&c.origin)));
+        out.push_str(&format!("    corner {}\n", vec3_text(&c.screen.start_point)));
+        out.push_str(&format!("    horizontal {}\n", vec3_text(&c.screen.horizontal)));
+        out.push_str(&format!("    vertical {}\n", vec3_text(&c.screen.vertical)));
+        out.push_str(&format!("    lens_radius {}\n}}\n", c.lens_radius));
+
+        for (name, material) in &self.materials {
+            out.push_str(&format!("material {} {}\n", name, material_text(material)));
+        }
+
+        if let Some(fog) = &self.fog {
+            let g = match fog.phase {
+                Material::HenyeyGreenstein(_, g) => format!(" g {}", g),
+                _ => String::new(),
+            };
+            out.push_str(&format!(
+                "fog {{ density {} albedo {} distance {}{} }}\n",
+                fog.density,
+                vec3_text(&fog.phase.albedo()),
+                fog.distance,
+                g
+            ));
+        }
+
+        for object in &self.objects {
+            object_text(object, 0, dir, &mut out);
+        }
+        out
+    }
+
+    // write the scene to a file, mesh files are referred to from its directory
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        let dir = Path::new(filename).parent().unwrap_or(Path::new("."));
+        fs::write(filename, self.text_in(dir))
+    }
+}
+
+fn vec3_text(v: &Vec3) -> String {
+    format!("{} {} {}", v.x, v.y, v.z)
+}
+
+fn material_text(material: &Material) -> String {
+    match *material {
+        Material::Lambertian(albedo) => format!("lambertian {}", albedo),
+        Material::Metal(albedo, fuzz) => format!("metal {} {}", albedo, fuzz),
+        Material::Dielectric(ior, absorption) => {
+            let ior = match ior {
+                Ior::Constant(n) => format!("{}", n),
+                Ior::Cauchy(a, b) => format!("cauchy {} {}", a, b),
+                Ior::Sellmeier(b, c) => format!("sellmeier {} {} {} {} {} {}", b[0], b[1], b[2], c[0], c[1], c[2]),
+            };
+            if absorption == Vec3::new() {
+                format!("dielectric {}", ior)
+            } else {
+                format!("dielectric {} absorption {}", ior, vec3_text(&absorption))
+            }
+        }
+        Material::Isotropic(albedo) => format!("isotropic {}", vec3_text(&albedo)),
+        Material::HenyeyGreenstein(albedo, g) => format!("henyey_greenstein {} {}", vec3_text(&albedo), g),
+        Material::Emissive(radiance) => format!("emissive {}", vec3_text(&radiance)),
+    }
+}
+
+fn object_text(object: &Object, depth: usize, dir: &Path, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    let mut props = Vec::new();
+    if let Some(name) = &object.name {
+        props.push(format!("name {}", name));
+    }
+    if let Some(name) = &object.material {
+        props.push(format!("material {}", name));
+    }
+    for op in &object.transform {
+        props.push(match op {
+            TransformOp::Translate(t) => format!("translate {}", vec3_text(t)),
+            TransformOp::Rotate(axis, degrees) => format!("rotate {} {}", vec3_text(axis), degrees),
+            TransformOp::Scale(s) => format!("scale {}", vec3_text(s)),
+            TransformOp::Matrix(m) => {
+                let values: Vec<String> = m.m.iter().flatten().map(|x| x.to_string()).collect();
+                format!("matrix {}", values.join(" "))
+            }
+        });
+    }
+    let keyword = match &object.shape {
+        Shape::Triangle(a, b, c) => {
+            props.push(format!("a {} b {} c {}", vec3_text(a), vec3_text(b), vec3_text(c)));
+            "triangle"
+        }
+        Shape::Sphere(center, radius, subdivisions) => {
+            props.push(format!("center {} radius {} subdivisions {}", vec3_text(center), radius, subdivisions));
+            "sphere"
+        }
+        Shape::Icosahedron(center, radius) => {
+            props.push(format!("center {} radius {}", vec3_text(center), radius));
+            "icosahedron"
+        }
+        Shape::Mesh(file, _) if !file.is_empty() => {
+            props.push(format!("file \"{}\"", path_from(dir, file).display()));
+            "mesh"
+        }
+        Shape::Mesh(_, _) | Shape::Group(_) => "group",
+    };
+    out.push_str(&format!("{}{} {{", indent, keyword));
+    for prop in &props {
+        out.push(' ');
+        out.push_str(prop);
+    }
+    match &object.shape {
+        Shape::Group(children) => {
+            out.push('\n');
+            for child in children {
+                object_text(child, depth + 1, dir, out);
+            }
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        Shape::Mesh(file, triangles) if file.is_empty() => {
+            out.push('\n');
+            for t in triangles {
+                let (a, b, c) = (vec3_text(&t.a), vec3_text(&t.b), vec3_text(&t.c));
+                out.push_str(&format!("{}    triangle {{ a {} b {} c {} }}\n", indent, a, b, c));
+            }
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        _ => out.push_str(" }\n"),
+    }
+}
+
+// path of a file, given from the working directory, as seen from dir: relative,
+// going up with .. where needed, unless the two share no root
+fn path_from(dir: &Path, file: &str) -> PathBuf {
+    let (dir, file) = (lexical_absolute(dir), lexical_absolute(Path::new(file)));
+    let (mut dir_parts, mut file_parts) = (dir.components().peekable(), file.components().peekable());
+    let mut shared = 0;
+    while dir_parts.peek().is_some() && dir_parts.peek() == file_parts.peek() {
+        dir_parts.next();
+        file_parts.next();
+        shared += 1;
+    }
+    if shared == 0 {
+        return file;
+    }
+    dir_parts.map(|_| Component::ParentDir).chain(file_parts).collect()
+}
+
+// absolute form of a path with . and .. resolved without looking at the file system
+fn lexical_absolute(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// triangles of a Wavefront OBJ file; only vertices and faces are read, polygons are
+// split into fans and negative indices count back from the last vertex
+pub fn parse_obj(src: &str) -> Result<Vec<Triangle>, String> {
+    let mut vertices: Vec<Vec3> = Vec::new();
+    let mut triangles = Vec::new();
+    for (n, line) in src.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("v") => {
+                let mut v = [0.0f32; 3];
+                for x in v.iter_mut() {
+                    *x = fields
+                        .next()
+                        .and_then(|f| f.parse().ok())
+                        .ok_or_else(|| format!("line {}: bad vertex", n + 1))?;
+                }
+                vertices.push(Vec3 { x: v[0], y: v[1], z: v[2] });
+            }
+            Some("f") => {
+                let mut face = Vec::new();
+                for field in fields {
+                    let index: i64 = field
+                        .split('/')
+                        .next()
+                        .and_then(|i| i.parse().ok())
+                        .ok_or_else(|| format!("line {}: bad face index `{}`", n + 1, field))?;
+                    let i = if index < 0 { vertices.len() as i64 + index } else { index - 1 };
+                    let v = usize::try_from(i)
+                        .ok()
+                        .and_then(|i| vertices.get(i))
+                        .ok_or_else(|| format!("line {}: face index {} out of range", n + 1, index))?;
+                    face.push(*v);
+                }
+                if face.len() < 3 {
+                    return Err(format!("line {}: face with fewer than 3 vertices", n + 1));
+                }
+                for i in 1..face.len() - 1 {
+                    triangles.push(Triangle::new(face[0], face[i], face[i + 1]));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    Open,
+    Close,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    col: usize,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match &self.kind {
+            TokenKind::Word(w) => format!("`{}`", w),
+            TokenKind::Str(s) => format!("\"{}\"", s),
+            TokenKind::Open => "`{`".to_string(),
+            TokenKind::Close => "`}`".to_string(),
+        }
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, SceneError> {
+    let mut tokens = Vec::new();
+    let (mut line, mut col) = (1, 1);
+    let mut chars = src.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_col) = (line, col);
+        let token = |kind| Token { kind, line: start_line, col: start_col };
+        match c {
+            '\n' => {
+                chars.next();
+                line += 1;
+                col = 1;
+            }
+            '#' => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '{' | '}' => {
+                chars.next();
+                col += 1;
+                tokens.push(token(if c == '{' { TokenKind::Open } else { TokenKind::Close }));
+            }
+            '"' => {
+                chars.next();
+                col += 1;
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => {
+                            return Err(SceneError { line: start_line, col: start_col, message: "unterminated string".to_string() });
+                        }
+                        Some(c) => s.push(c),
+                    }
+                    col += 1;
+                }
+                col += 1;
+                tokens.push(token(TokenKind::Str(s)));
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+                col += 1;
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && !"{}\"#".contains(c)) {
+                    word.push(c);
+                    col += 1;
+                }
+                tokens.push(token(TokenKind::Word(word)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'p> {
+    tokens: Vec<Token>,
+    pos: usize,
+    end: (usize, usize), // position after the last token, for errors at the end of the file
+    dir: &'p Path,
+}
+
+// camera statement as written, turned into a Camera once the image size is known
+#[derive(Default)]
+struct CameraDesc {
+    from: Option<Vec3>,
+    at: Option<Vec3>,
+    up: Option<Vec3>,
+    fov: Option<f32>,
+    aspect: Option<f32>,
+    aperture: Option<f32>,
+    focus: Option<f32>,
+    raw: Option<Camera>, // origin, corner, horizontal, vertical and lens_radius
+}
+
+impl<'p> Parser<'p> {
+    fn new(src: &str, dir: &'p Path) -> Result<Parser<'p>, SceneError> {
+        let line_count = src.split('\n').count();
+        let last_len = src.split('\n').next_back().map_or(0, |l| l.chars().count());
+        Ok(Parser { tokens: tokenize(src)?, pos: 0, end: (line_count, last_len + 1), dir })
+    }
+
+    fn error(&self, token: &Token, message: String) -> SceneError {
+        SceneError { line: token.line, col: token.col, message }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, SceneError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => Err(SceneError {
+                line: self.end.0,
+                col: self.end.1,
+                message: format!("unexpected end of file, expected {}", expected),
+            }),
+        }
+    }
+
+    fn word(&mut self, expected: &str) -> Result<(String, Token), SceneError> {
+        let token = self.next(expected)?;
+        match &token.kind {
+            TokenKind::Word(w) => Ok((w.clone(), token)),
+            _ => Err(self.error(&token, format!("expected {}, found {}", expected, token.describe()))),
+        }
+    }
+
+    fn string(&mut self) -> Result<(String, Token), SceneError> {
+        let token = self.next("a quoted string")?;
+        match &token.kind {
+            TokenKind::Str(s) => Ok((s.clone(), token)),
+            _ => Err(self.error(&token, format!("expected a quoted string, found {}", token.describe()))),
+        }
+    }
+
+    fn parse_word<T: std::str::FromStr>(&mut self, expected: &str) -> Result<T, SceneError> {
+        let (word, token) = self.word(expected)?;
+        word.parse().map_err(|_| self.error(&token, format!("expected {}, found `{}`", expected, word)))
+    }
+
+    fn number(&mut self) -> Result<f32, SceneError> {
+        self.parse_word("a number")
+    }
+
+    fn integer<T: std::str::FromStr>(&mut self) -> Result<T, SceneError> {
+        self.parse_word("a non-negative integer")
+    }
+
+    // width or height after its key, which errors point at
+    fn image_size(&mut self, key: &Token) -> Result<usize, SceneError> {
+        let size = self.integer()?;
+        if size == 0 {
+            return Err(self.error(key, "image size must not be zero".to_string()));
+        }
+        Ok(size)
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, SceneError> {
+        Ok(Vec3 { x: self.number()?, y: self.number()?, z: self.number()? })
+    }
+
+    fn open(&mut self) -> Result<(), SceneError> {
+        let token = self.next("`{`")?;
+        if token.kind != TokenKind::Open {
+            return Err(self.error(&token, format!("expected `{{`, found {}", token.describe())));
+        }
+        Ok(())
+    }
+
+    // true and consumed if the next token closes the block
+    fn close(&mut self) -> Result<bool, SceneError> {
+        match self.peek() {
+            Some(token) if token.kind == TokenKind::Close => {
+                self.pos += 1;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None => Err(SceneError {
+                line: self.end.0,
+                col: self.end.1,
+                message: "unexpected end of file, expected `}`".to_string(),
+            }),
+        }
+    }
+
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token { kind: TokenKind::Word(w), .. }) if w == word)
+    }
+
+    fn scene(&mut self) -> Result<Scene, SceneError> {
+        let mut settings = RenderSettings::new(400, 200);
+        let mut camera = CameraDesc::default();
+        let mut materials: Vec<(String, Material)> = Vec::new();
+        let mut fog = None;
+        let mut objects = Vec::new();
+        while self.peek().is_some() {
+            let (keyword, token) = self.word("a statement")?;
+            match keyword.as_str() {
+                "settings" => self.settings(&mut settings)?,
+                "camera" => camera = self.camera()?,
+                "material" => {
+                    let (name, name_token) = self.word("a material name")?;
+                    if materials.iter().any(|(n, _)| *n == name) {
+                        return Err(self.error(&name_token, format!("material `{}` is already defined", name)));
+                    }
+                    let material = self.material()?;
+                    materials.push((name, material));
+                }
+                "fog" => fog = Some(self.fog()?),
+                _ => objects.push(self.object(&keyword, &token, &materials, true)?),
+            }
+        }
+        let camera = self.build_camera(camera, &settings)?;
+        Ok(Scene { settings, camera, materials, fog, objects })
+    }
+
+    fn settings(&mut self, s: &mut RenderSettings) -> Result<(), SceneError> {
+        self.open()?;
+        while !self.close()? {
+            let (key, token) = self.word("a setting")?;
+            match key.as_str() {
+                "width" => s.width = self.image_size(&token)?,
+                "height" => s.height = self.image_size(&token)?,
+                "samples" => s.samples_per_pixel = self.integer()?,
+                "max_depth" => s.max_depth = self.integer()?,
+                "seed" => s.seed = self.integer()?,
+                "threads" => s.threads = self.integer()?,
+                "exposure" => s.output.exposure = self.number()?,
+                "sampler" => {
+                    let (kind, token) = self.word("a sampler")?;
+                    s.sampler = match kind.as_str() {
+                        "independent" => SamplerKind::Independent,
+                        "stratified" => SamplerKind::Stratified,
+                        "halton" => SamplerKind::Halton,
+                        "sobol" => SamplerKind::Sobol,
+                        "blue_noise" => SamplerKind::BlueNoise,
+                        _ => return Err(self.error(&token, format!("unknown sampler `{}`", kind))),
+                    };
+                }
+                "filter" => {
+                    let (kind, token) = self.word("a filter")?;
+                    s.filter = match kind.as_str() {
+                        "box" => Filter::Box(self.number()?),
+                        "tent" => Filter::Tent(self.number()?),
+                        "gaussian" => Filter::Gaussian(self.number()?, self.number()?),
+                        "mitchell" => Filter::Mitchell(self.number()?, self.number()?, self.number()?),
+                        _ => return Err(self.error(&token, format!("unknown filter `{}`", kind))),
+                    };
+                }
+                "tone_map" => {
+                    let (kind, token) = self.word("a tone map")?;
+                    s.output.tone_map = match kind.as_str() {
+                        "clamp" => ToneMap::Clamp,
+                        "reinhard" => ToneMap::Reinhard,
+                        "reinhard_extended" => ToneMap::ReinhardExtended(self.number()?),
+                        "aces" => ToneMap::Aces,
+                        _ => return Err(self.error(&token, format!("unknown tone map `{}`", kind))),
+                    };
+                }
+                "encoding" => {
+                    let (kind, token) = self.word("an encoding")?;
+                    s.output.encoding = match kind.as_str() {
+                        "linear" => Encoding::Linear,
+                        "srgb" => Encoding::Srgb,
+                        "gamma" => Encoding::Gamma(self.number()?),
+                        _ => return Err(self.error(&token, format!("unknown encoding `{}`", kind))),
+                    };
+                }
+                _ => return Err(self.error(&token, format!("unknown setting `{}`", key))),
+            }
+        }
+        Ok(())
+    }
+
+    fn camera(&mut self) -> Result<CameraDesc, SceneError> {
+        let mut desc = CameraDesc::default();
+        let mut raw = Camera::new(Vec3::new(), Screen::new(2.0, 1.0));
+        let (mut look_at, mut direct) = (None, None);
+        self.open()?;
+        while !self.close()? {
+            let (key, token) = self.word("a camera property")?;
+            let is_direct = match key.as_str() {
+                "from" => { desc.from = Some(self.vec3()?); false }
+                "at" => { desc.at = Some(self.vec3()?); false }
+                "up" => { desc.up = Some(self.vec3()?); false }
+                "fov" => { desc.fov = Some(self.number()?); false }
+                "aspect" => { desc.aspect = Some(self.number()?); false }
+                "aperture" => { desc.aperture = Some(self.number()?); false }
+                "focus" => { desc.focus = Some(self.number()?); false }
+                "origin" => { raw.origin = self.vec3()?; true }
+                "corner" => { raw.screen.start_point = self.vec3()?; true }
+                "horizontal" => { raw.screen.horizontal = self.vec3()?; true }
+                "vertical" => { raw.screen.vertical = self.vec3()?; true }
+                "lens_radius" => { raw.lens_radius = self.number()?; true }
+                _ => return Err(self.error(&token, format!("unknown camera property `{}`", key))),
+            };
+            if is_direct {
+                direct.get_or_insert(token);
+            } else {
+                look_at.get_or_insert(token);
+            }
+        }
+        if let (Some(first), Some(second)) = (&look_at, &direct) {
+            let later = if (first.line, first.col) > (second.line, second.col) { first } else { second };
+            return Err(self.error(later, "the camera mixes look-at and screen properties".to_string()));
+        }
+        if direct.is_some() {
+            desc.raw = Some(raw);
+        }
+        Ok(desc)
+    }
+
+    fn build_camera(&self, desc: CameraDesc, settings: &RenderSettings) -> Result<Camera, SceneError> {
+        if let Some(raw) = desc.raw {
+            return Ok(raw);
+        }
+        let from = desc.from.unwrap_or(Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+        let at = desc.at.unwrap_or_default();
+        let up = desc.up.unwrap_or(Vec3 { x: 0.0, y: 1.0, z: 0.0 });
+        let aspect = desc.aspect.unwrap_or(settings.width as f32 / settings.height as f32);
+        let camera = Camera::look_at(from, at, up, desc.fov.unwrap_or(90.0), aspect);
+        match desc.aperture {
+            Some(aperture) if aperture > 0.0 => {
+                Ok(camera.with_aperture(aperture, desc.focus.unwrap_or((at - from).len())))
+            }
+            _ => Ok(camera),
+        }
+    }
+
+    fn material(&mut self) -> Result<Material, SceneError> {
+        let (kind, token) = self.word("a material kind")?;
+        let material = match kind.as_str() {
+            "lambertian" => Material::Lambertian(self.number()?),
+            "metal" => Material::Metal(self.number()?, self.number()?),
+            "dielectric" => {
+                let ior = if self.peek_word("cauchy") {
+                    self.pos += 1;
+                    Ior::Cauchy(self.number()?, self.number()?)
+                } else if self.peek_word("sellmeier") {
+                    self.pos += 1;
+                    let b = [self.number()?, self.number()?, self.number()?];
+                    let c = [self.number()?, self.number()?, self.number()?];
+                    Ior::Sellmeier(b, c)
+                } else {
+                    Ior::Constant(self.number()?)
+                };
+                let mut absorption = Vec3::new();
+                if self.peek_word("absorption") {
+                    self.pos += 1;
+                    absorption = self.vec3()?;
+                }
+                Material::Dielectric(ior, absorption)
+            }
+            "isotropic" => Material::Isotropic(self.vec3()?),
+            "henyey_greenstein" => Material::HenyeyGreenstein(self.vec3()?, self.number()?),
+            "emissive" => Material::Emissive(self.vec3()?),
+            _ => return Err(self.error(&token, format!("unknown material kind `{}`", kind))),
+        };
+        Ok(material)
+    }
+
+    fn fog(&mut self) -> Result<Fog, SceneError> {
+        let mut fog = Fog::new(0.0, Vec3 { x: 1.0, y: 1.0, z: 1.0 });
+        let mut albedo = Vec3 { x: 1.0, y: 1.0, z: 1.0 };
+        let mut g = None;
+        self.open()?;
+        while !self.close()? {
+            let (key, token) = self.word("a fog property")?;
+            match key.as_str() {
+                "density" => fog.density = self.number()?,
+                "albedo" => albedo = self.vec3()?,
+                "distance" => fog.distance = self.number()?,
+                "g" => g = Some(self.number()?),
+                _ => return Err(self.error(&token, format!("unknown fog property `{}`", key))),
+            }
+        }
+        fog.phase = match g {
+            Some(g) => Material::HenyeyGreenstein(albedo, g),
+            None => Material::Isotropic(albedo),
+        };
+        Ok(fog)
+    }
+
+    // object statement whose keyword has been read
+    fn object(
+        &mut self,
+        keyword: &str,
+        keyword_token: &Token,
+        materials: &[(String, Material)],
+        top_level: bool,
+    ) -> Result<Object, SceneError> {
+        let mut shape = match keyword {
+            "triangle" => Shape::Triangle(Vec3::new(), Vec3::new(), Vec3::new()),
+            "sphere" => Shape::Sphere(Vec3::new(), 1.0, 3),
+            "icosahedron" => Shape::Icosahedron(Vec3::new(), 1.0),
+            "mesh" => Shape::Mesh(String::new(), Vec::new()),
+            "group" => Shape::Group(Vec::new()),
+            _ => {
+                let expected = if top_level { "statement" } else { "object" };
+                return Err(self.error(keyword_token, format!("unknown {} `{}`", expected, keyword)));
+            }
+        };
+        let mut object = Object::new(Shape::Group(Vec::new()), None);
+        self.open()?;
+        while !self.close()? {
+            let (key, token) = self.word("an object property")?;
+            match (key.as_str(), &mut shape) {
+                ("name", _) => object.name = Some(self.word("a name")?.0),
+                ("material", _) => {
+                    let (name, token) = self.word("a material name")?;
+                    if !materials.iter().any(|(n, _)| *n == name) {
+                        return Err(self.error(&token, format!("unknown material `{}`", name)));
+                    }
+                    object.material = Some(name);
+                }
+                ("translate", _) => object.transform.push(TransformOp::Translate(self.vec3()?)),
+                ("rotate", _) => object.transform.push(TransformOp::Rotate(self.vec3()?, self.number()?)),
+                ("scale", _) => {
+                    let s = self.vec3()?;
+                    if s.x == 0.0 || s.y == 0.0 || s.z == 0.0 {
+                        return Err(self.error(&token, "scale must not be zero".to_string()));
+                    }
+                    object.transform.push(TransformOp::Scale(s));
+                }
+                ("matrix", _) => {
+                    let mut m = Mat4::identity();
+                    for row in m.m.iter_mut() {
+                        for x in row.iter_mut() {
+                            *x = self.number()?;
+                        }
+                    }
+                    if m.inverse().is_none() {
+                        return Err(self.error(&token, "matrix is not invertible".to_string()));
+                    }
+                    object.transform.push(TransformOp::Matrix(m));
+                }
+                ("a", Shape::Triangle(a, _, _)) => *a = self.vec3()?,
+                ("b", Shape::Triangle(_, b, _)) => *b = self.vec3()?,
+                ("c", Shape::Triangle(_, _, c)) => *c = self.vec3()?,
+                ("center", Shape::Sphere(center, _, _)) | ("center", Shape::Icosahedron(center, _)) => {
+                    *center = self.vec3()?;
+                }
+                ("radius", Shape::Sphere(_, radius, _)) | ("radius", Shape::Icosahedron(_, radius)) => {
+                    *radius = self.number()?;
+                }
+                ("subdivisions", Shape::Sphere(_, _, subdivisions)) => {
+                    let at = self.pos;
+                    *subdivisions = self.integer()?;
+                    if *subdivisions > MAX_SUBDIVISIONS {
+                        let message = format!("subdivisions must be at most {}", MAX_SUBDIVISIONS);
+                        return Err(self.error(&self.tokens[at], message));
+                    }
+                }
+                ("file", Shape::Mesh(file, triangles)) => {
+                    let (name, token) = self.string()?;
+                    let path = self.dir.join(&name);
+                    let src = fs::read_to_string(&path)
+                        .map_err(|e| self.error(&token, format!("cannot read {}: {}", path.display(), e)))?;
+                    *triangles = parse_obj(&src).map_err(|e| self.error(&token, format!("{}: {}", name, e)))?;
+                    *file = path.to_string_lossy().into_owned();
+                }
+                (_, Shape::Group(children)) => children.push(self.object(&key, &token, materials, false)?),
+                _ => return Err(self.error(&token, format!("unknown {} property `{}`", keyword, key))),
+            }
+        }
+        if let Shape::Mesh(file, _) = &shape {
+            if file.is_empty() {
+                return Err(self.error(keyword_token, "mesh without a file".to_string()));
+            }
+        }
+        object.shape = shape;
+        Ok(object)
+    }
+}
@@ -0,0 +1,229 @@
+use super::basic::*;
+use super::tracer::*;
+use super::mesh::*;
+use super::renderer::*;
+use super::scene::*;
+
+// reference scenes by name, for the CLI and the tests
+pub const NAMES: [&str; 4] = ["cornell_box", "random_spheres", "material_board", "sphere_gallery"];
+
+// a reference scene with its default parameters
+pub fn by_name(name: &str, width: usize, height: usize) -> Option<Scene> {
+    match name {
+        "cornell_box" => Some(cornell_box(width, height)),
+        "random_spheres" => Some(random_spheres(width, height, 11, 0)),
+        "material_board" => Some(material_board(width, height)),
+        "sphere_gallery" => Some(sphere_gallery(width, height, 4)),
+        _ => None,
+    }
+}
+
+// the Cornell box: a 2x2x2 room open towards the camera, lit by a square lamp in
+// the ceiling, with a tall and a short block. Lambertian albedo is gray only, so
+// the side walls are a light and a dark gray instead of red and green.
+pub fn cornell_box(width: usize, height: usize) -> Scene {
+    let mut settings = RenderSettings::new(width, height);
+    settings.samples_per_pixel = 64;
+    settings.max_depth = 16;
+    let camera = Camera::look_at(
+        Vec3 { x: 0.0, y: 1.0, z: 3.8 },
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        40.0,
+        width as f32 / height as f32,
+    );
+    let materials = vec![
+        ("white".to_string(), Material::Lambertian(0.73)),
+        ("left".to_string(), Material::Lambertian(0.6)),
+        ("right".to_string(), Material::Lambertian(0.2)),
+        ("lamp".to_string(), Material::Emissive(Vec3 { x: 15.0, y: 15.0, z: 15.0 })),
+    ];
+
+    let p = |x: f32, y: f32, z: f32| Vec3 { x, y, z };
+    let walls = vec![
+        named("floor", quad(p(-1.0, 0.0, 1.0), p(1.0, 0.0, 1.0), p(1.0, 0.0, -1.0), p(-1.0, 0.0, -1.0)), "white"),
+        named("ceiling", quad(p(-1.0, 2.0, 1.0), p(-1.0, 2.0, -1.0), p(1.0, 2.0, -1.0), p(1.0, 2.0, 1.0)), "white"),
+        named("back", quad(p(-1.0, 0.0, -1.0), p(1.0, 0.0, -1.0), p(1.0, 2.0, -1.0), p(-1.0, 2.0, -1.0)), "white"),
+        named("left", quad(p(-1.0, 0.0, 1.0), p(-1.0, 0.0, -1.0), p(-1.0, 2.0, -1.0), p(-1.0, 2.0, 1.0)), "left"),
+        named("right", quad(p(1.0, 0.0, -1.0), p(1.0, 0.0, 1.0), p(1.0, 2.0, 1.0), p(1.0, 2.0, -1.0)), "right"),
+    ];
+    let mut room = Object::new(Shape::Group(walls), None);
+    room.name = Some("room".to_string());
+
+    let lamp = named("lamp", quad(p(-0.25, 1.999, -0.25), p(0.25, 1.999, -0.25), p(0.25, 1.999, 0.25), p(-0.25, 1.999, 0.25)), "lamp");
+    let mut tall = named("tall_block", block(p(0.6, 1.2, 0.6)), "white");
+    tall.transform.push(TransformOp::Rotate(p(0.0, 1.0, 0.0), 15.0));
+    tall.transform.push(TransformOp::Translate(p(-0.35, 0.0, -0.35)));
+    let mut short = named("short_block", block(p(0.6, 0.6, 0.6)), "white");
+    short.transform.push(TransformOp::Rotate(p(0.0, 1.0, 0.0), -18.0));
+    short.transform.push(TransformOp::Translate(p(0.35, 0.0, 0.3)));
+
+    Scene { settings, camera, materials, fog: None, objects: vec![room, lamp, tall, short] }
+}
+
+// the cover of "Ray Tracing in One Weekend": small spheres on a grid from -n to n
+// with random offsets and materials, around three large ones
+pub fn random_spheres(width: usize, height: usize, n: i32, seed: u64) -> Scene {
+    let mut settings = RenderSettings::new(width, height);
+    settings.samples_per_pixel = 16;
+    settings.seed = seed;
+    let camera = Camera::look_at(
+        Vec3 { x: 13.0, y: 2.0, z: 3.0 },
+        Vec3::new(),
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        20.0,
+        width as f32 / height as f32,
+    ).with_aperture(0.1, 10.0);
+    let mut materials = vec![
+        ("ground".to_string(), Material::Lambertian(0.5)),
+        ("glass".to_string(), Material::dielectric(1.5)),
+        ("matte".to_string(), Material::Lambertian(0.4)),
+        ("mirror".to_string(), Material::Metal(0.7, 0.0)),
+    ];
+    let mut objects = vec![ground(50.0)];
+
+    let mut rng = Pcg32::new(seed, 0x5eed);
+    for a in -n..n {
+        for b in -n..n {
+            let center = Vec3 {
+                x: a as f32 + 0.9 * rng.next_f32(),
+                y: 0.2,
+                z: b as f32 + 0.9 * rng.next_f32(),
+            };
+            let choice = rng.next_f32();
+            let (a1, a2) = (rng.next_f32(), rng.next_f32());
+            if (center - Vec3 { x: 4.0, y: 0.2, z: 0.0 }).len() <= 0.9 {
+                continue;
+            }
+            let name = format!("sphere_{}", materials.len() - 4);
+            let material = if choice < 0.8 {
+                Material::Lambertian(a1 * a2)
+            } else if choice < 0.95 {
+                Material::Metal(0.5 + 0.5 * a1, 0.5 * a2)
+            } else {
+                Material::dielectric(1.5)
+            };
+            materials.push((name.clone(), material));
+            let mut sphere = Object::new(Shape::Sphere(center, 0.2, 2), Some(name.clone()));
+            sphere.name = Some(name);
+            objects.push(sphere);
+        }
+    }
+    for (name, x) in [("glass", 0.0), ("matte", -4.0), ("mirror", 4.0)] {
+        let mut sphere = Object::new(Shape::Sphere(Vec3 { x, y: 1.0, z: 0.0 }, 1.0, 4), Some(name.to_string()));
+        sphere.name = Some(format!("big_{}", name));
+        objects.push(sphere);
+    }
+    Scene { settings, camera, materials, fog: None, objects }
+}
+
+// one row of spheres per Material variant, sweeping its main parameter from
+// left to right; the dielectric row ends with a Cauchy and a Sellmeier glass
+pub fn material_board(width: usize, height: usize) -> Scene {
+    let mut settings = RenderSettings::new(width, height);
+    settings.samples_per_pixel = 32;
+    let camera = Camera::look_at(
+        Vec3 { x: 0.0, y: 3.0, z: 12.0 },
+        Vec3 { x: 0.0, y: 2.5, z: 0.0 },
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        35.0,
+        width as f32 / height as f32,
+    );
+    let gray = |v: f32| Vec3 { x: v, y: v, z: v };
+    let rows: [(&str, [Material; 5]); 6] = [
+        ("lambertian", [0.1, 0.3, 0.5, 0.7, 0.9].map(Material::Lambertian)),
+        ("metal", [0.0, 0.1, 0.25, 0.5, 1.0].map(|fuzz| Material::Metal(0.8, fuzz))),
+        ("dielectric", [
+            Material::dielectric(1.1),
+            Material::dielectric(1.5),
+            Material::Dielectric(Ior::Constant(1.5), Vec3 { x: 0.1, y: 0.8, z: 0.8 }),
+            Material::Dielectric(Ior::Cauchy(1.5, 0.02), Vec3::new()),
+            Material::Dielectric(Ior::Sellmeier([1.0396, 0.2318, 1.0105], [0.0060, 0.0200, 103.56]), Vec3::new()),
+        ]),
+        ("isotropic", [0.1, 0.3, 0.5, 0.7, 0.9].map(|v| Material::Isotropic(gray(v)))),
+        ("henyey_greenstein", [-0.8, -0.4, 0.0, 0.4, 0.8].map(|g| Material::HenyeyGreenstein(gray(0.8), g))),
+        ("emissive", [0.25, 0.5, 1.0, 2.0, 4.0].map(|v| Material::Emissive(gray(v)))),
+    ];
+
+    let mut materials = vec![("ground".to_string(), Material::Lambertian(0.5))];
+    let mut objects = vec![ground(20.0)];
+    for (row, (kind, sweep)) in rows.iter().enumerate() {
+        let mut spheres = Vec::new();
+        for (col, material) in sweep.iter().enumerate() {
+            let name = format!("{}_{}", kind, col);
+            materials.push((name.clone(), *material));
+            let center = Vec3 { x: (col as f32 - 2.0) * 1.1, y: 0.5 + row as f32, z: -(row as f32) * 0.5 };
+            let mut sphere = Object::new(Shape::Sphere(center, 0.45, 3), Some(name.clone()));
+            sphere.name = Some(name);
+            spheres.push(sphere);
+        }
+        let mut group = Object::new(Shape::Group(spheres), None);
+        group.name = Some(kind.to_string());
+        objects.push(group);
+    }
+    Scene { settings, camera, materials, fog: None, objects }
+}
+
+// the icosahedron next to spheres of 0 to max_subdivisions subdivisions, showing
+// how the tessellation converges to the sphere
+pub fn sphere_gallery(width: usize, height: usize, max_subdivisions: u32) -> Scene {
+    let mut settings = RenderSettings::new(width, height);
+    settings.samples_per_pixel = 16;
+    let count = max_subdivisions as f32 + 2.0;
+    let camera = Camera::look_at(
+        Vec3 { x: 0.0, y: 1.5, z: 2.0 * count },
+        Vec3 { x: 0.0, y: 0.5, z: 0.0 },
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        30.0,
+        width as f32 / height as f32,
+    );
+    let materials = vec![
+        ("ground".to_string(), Material::Lambertian(0.5)),
+        ("clay".to_string(), Material::Lambertian(0.7)),
+        ("chrome".to_string(), Material::Metal(0.9, 0.05)),
+    ];
+    let x = |i: u32| (i as f32 - (count - 1.0) * 0.5) * 1.2;
+    let mut objects = vec![ground(50.0)];
+    let mut icosahedron = Object::new(Shape::Icosahedron(Vec3 { x: x(0), y: 0.5, z: 0.0 }, 0.5), Some("clay".to_string()));
+    icosahedron.name = Some("icosahedron".to_string());
+    objects.push(icosahedron);
+    for s in 0..=max_subdivisions {
+        let material = if s % 2 == 0 { "chrome" } else { "clay" };
+        let center = Vec3 { x: x(s + 1), y: 0.5, z: 0.0 };
+        let mut sphere = Object::new(Shape::Sphere(center, 0.5, s), Some(material.to_string()));
+        sphere.name = Some(format!("sphere_{}", s));
+        objects.push(sphere);
+    }
+    Scene { settings, camera, materials, fog: None, objects }
+}
+
+// square floor of the given half size at y = 0
+fn ground(size: f32) -> Object {
+    let p = |x: f32, z: f32| Vec3 { x, y: 0.0, z };
+    named("ground", quad(p(-size, size), p(size, size), p(size, -size), p(-size, -size)), "ground")
+}
+
+fn named(name: &str, triangles: Vec<Triangle>, material: &str) -> Object {
+    let mut object = Object::new(Shape::Mesh(String::new(), triangles), Some(material.to_string()));
+    object.name = Some(name.to_string());
+    object
+}
+
+// two triangles, the corners in order around the quad
+fn quad(a: Vec3, b: Vec3, c: Vec3, d: Vec3) -> Vec<Triangle> {
+    vec![Triangle::new(a, b, c), Triangle::new(a, c, d)]
+}
+
+// box standing on the origin, centered on the y axis
+fn block(size: Vec3) -> Vec<Triangle> {
+    let (x, y, z) = (size.x * 0.5, size.y, size.z * 0.5);
+    let p = |px: f32, py: f32, pz: f32| Vec3 { x: px, y: py, z: pz };
+    let mut triangles = Vec::with_capacity(12);
+    triangles.extend(quad(p(-x, 0.0, z), p(x, 0.0, z), p(x, y, z), p(-x, y, z)));
+    triangles.extend(quad(p(x, 0.0, -z), p(-x, 0.0, -z), p(-x, y, -z), p(x, y, -z)));
+    triangles.extend(quad(p(-x, 0.0, -z), p(-x, 0.0, z), p(-x, y, z), p(-x, y, -z)));
+    triangles.extend(quad(p(x, 0.0, z), p(x, 0.0, -z), p(x, y, -z), p(x, y, z)));
+    triangles.extend(quad(p(-x, y, z), p(x, y, z), p(x, y, -z), p(-x, y, -z)));
+    triangles.extend(quad(p(-x, 0.0, -z), p(x, 0.0, -z), p(x, 0.0, z), p(-x, 0.0, z)));
+    triangles
+}
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+use super::tracer::*;
+use super::basic::*;
+use super::mesh::*;
+use super::transform::*;
+use super::bvh::*;
+
+// Signed distance fields: negative inside, and never more than the distance to
+// the surface, so a ray can always advance by the value without crossing it. The
+// shapes and operators below build fields from others; Sdf renders one.
+
+pub type DistanceFn = Arc<dyn Fn(&Vec3) -> f32 + Send + Sync>;
+
+// surface of a distance field inside bounds, found by sphere tracing
+pub struct Sdf {
+    pub distance: DistanceFn,
+    pub bounds: Aabb,
+    pub material: Material,
+    pub max_steps: u32,
+    pub epsilon: f32, // distance at which the surface counts as hit
+}
+
+impl Sdf {
+    pub fn new(distance: DistanceFn, bounds: Aabb, material: Material) -> Sdf {
+        Sdf { distance, bounds, material, max_steps: 512, epsilon: 1e-4 }
+    }
+
+    // the gradient of the field, by central differences
+    pub fn normal(&self, p: &Vec3) -> Vec3 {
+        let h = self.epsilon * 10.0;
+        let mut gradient = Vec3::new();
+        for axis in 0..3 {
+            let (mut a, mut b) = (*p, *p);
+            a[axis] += h;
+            b[axis] -= h;
+            gradient[axis] = (self.distance)(&a) - (self.distance)(&b);
+        }
+        if gradient.near_zero() {
+            gradient
+        } else {
+            gradient.normalized()
+        }
+    }
+
+    fn record(&self, ray: &Ray, t: f32) -> Hitrecord {
+        let p = ray.at(t);
+        let normal = self.normal(&p);
+        Hitrecord {
+            t,
+            p,
+            normal,
+            front_face: dot(&ray.direction, &normal) < 0.0,
+            material: self.material,
+            object_id: None,
+            primitive_id: None,
+            uv: (0.0, 0.0), // fields have no surface parametrization
+        }
+    }
+}
+
+impl Hitable for Sdf {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let (t0, t1) = self.bounds.clip(ray, t_range.t_min, t_range.t_max)?;
+        let len = ray.direction.len();
+        let eps = self.epsilon;
+        let f = |t: f32| (self.distance)(&ray.at(t));
+
+        let mut t = t0;
+        let mut d = f(t);
+        // entering bounds that touch the surface
+        if t0 > t_range.t_min && d.abs() < eps {
+            return Some(self.record(ray, t));
+        }
+        // a ray starting on the surface, as after a previous hit, is on the side it heads into
+        let side = if d.abs() < eps {
+            if f(t + eps / len) < d { -1.0 } else { 1.0 }
+        } else {
+            d.signum()
+        };
+        // only a surface reached after leaving the starting one counts
+        let mut away = side * d >= eps;
+        for _ in 0..self.max_steps {
+            // the last step stops at the exit from the bounds, which may touch the surface too
+            t = (t + (side * d).max(eps) / len).min(t1);
+            d = f(t);
+            if side * d >= eps {
+                away = true;
+            } else if away && t < t_range.t_max {
+                return Some(self.record(ray, t));
+            }
+            if t >= t1 {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+pub fn sphere(center: Vec3, radius: f32) -> DistanceFn {
+    Arc::new(move |p| (*p - center).len() - radius)
+}
+
+// axis-aligned box of the given half size
+pub fn cuboid(center: Vec3, half_size: Vec3) -> DistanceFn {
+    Arc::new(move |p| {
+        let q = (*p - center).abs() - half_size;
+        q.max(&Vec3::new()).len() + q.max_component().min(0.0)
+    })
+}
+
+pub fn rounded_box(center: Vec3, half_size: Vec3, radius: f32) -> DistanceFn {
+    rounded(cuboid(center, half_size), radius)
+}
+
+// torus in the xz plane
+pub fn torus(center: Vec3, major: f32, minor: f32) -> DistanceFn {
+    Arc::new(move |p| {
+        let q = *p - center;
+        let ring = (q.x * q.x + q.z * q.z).sqrt() - major;
+        (ring * ring + q.y * q.y).sqrt() - minor
+    })
+}
+
+// segment from a to b thickened by radius
+pub fn capsule(a: Vec3, b: Vec3, radius: f32) -> DistanceFn {
+    Arc::new(move |p| {
+        let (pa, ba) = (*p - a, b - a);
+        let h = (dot(&pa, &ba) / dot(&ba, &ba)).clamp(0.0, 1.0);
+        (pa - ba * h).len() - radius
+    })
+}
+
+// capped cylinder along y
+pub fn cylinder(center: Vec3, radius: f32, half_height: f32) -> DistanceFn {
+    Arc::new(move |p| {
+        let q = *p - center;
+        let dx = (q.x * q.x + q.z * q.z).sqrt() - radius;
+        let dy = q.y.abs() - half_height;
+        dx.max(dy).min(0.0) + (dx.max(0.0) * dx.max(0.0) + dy.max(0.0) * dy.max(0.0)).sqrt()
+    })
+}
+
+// half-space below the plane through point with the given normal
+pub fn plane(point: Vec3, normal: Vec3) -> DistanceFn {
+    let normal = normal.normalized();
+    Arc::new(move |p| dot(&(*p - point), &normal))
+}
+
+pub fn union(a: DistanceFn, b: DistanceFn) -> DistanceFn {
+    Arc::new(move |p| a(p).min(b(p)))
+}
+
+pub fn intersection(a: DistanceFn, b: DistanceFn) -> DistanceFn {
+    Arc::new(move |p| a(p).max(b(p)))
+}
+
+// a with b cut away
+pub fn difference(a: DistanceFn, b: DistanceFn) -> DistanceFn {
+    Arc::new(move |p| a(p).max(-b(p)))
+}
+
+// weight of a in the polynomial smooth minimum of radius k
+fn smooth_weight(a: f32, b: f32, k: f32) -> f32 {
+    (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0)
+}
+
+// union with the seam filled in over about k
+pub fn smooth_union(a: DistanceFn, b: DistanceFn, k: f32) -> DistanceFn {
+    Arc::new(move |p| {
+        let (da, db) = (a(p), b(p));
+        let h = smooth_weight(da, db, k);
+        db * (1.0 - h) + da * h - k * h * (1.0 - h)
+    })
+}
+
+pub fn smooth_intersection(a: DistanceFn, b: DistanceFn, k: f32) -> DistanceFn {
+    Arc::new(move |p| {
+        let (da, db) = (a(p), b(p));
+        let h = smooth_weight(-da, -db, k);
+        db * (1.0 - h) + da * h + k * h * (1.0 - h)
+    })
+}
+
+pub fn smooth_difference(a: DistanceFn, b: DistanceFn, k: f32) -> DistanceFn {
+    smooth_intersection(a, Arc::new(move |p| -b(p)), k)
+}
+
+// morph between a at 0 and b at 1
+pub fn blend(a: DistanceFn, b: DistanceFn, t: f32) -> DistanceFn {
+    Arc::new(move |p| a(p) * (1.0 - t) + b(p) * t)
+}
+
+// the surface grown outwards by radius, rounding its edges
+pub fn rounded(a: DistanceFn, radius: f32) -> DistanceFn {
+    Arc::new(move |p| a(p) - radius)
+}
+
+// hollow shell of the given thickness around the surface
+pub fn shell(a: DistanceFn, thickness: f32) -> DistanceFn {
+    Arc::new(move |p| a(p).abs() - thickness)
+}
+
+// the field moved by a rotation and translation; scaling would change distances, see scaled
+pub fn transformed(a: DistanceFn, transform: &Transform) -> DistanceFn {
+    let inverse = transform.inverse();
+    Arc::new(move |p| a(&inverse.point(p)))
+}
+
+pub fn scaled(a: DistanceFn, factor: f32) -> DistanceFn {
+    Arc::new(move |p| a(&(*p / factor)) * factor)
+}
+
+// distance estimate of the Mandelbulb of the given power (8 is the classic one),
+// within a radius of about 1.2 around the origin
+pub fn mandelbulb(power: f32, iterations: u32) -> DistanceFn {
+    Arc::new(move |p| {
+        let mut z = *p;
+        let (mut dr, mut r) = (1.0f32, z.len());
+        for _ in 0..iterations {
+            if !(1e-12..=2.0).contains(&r) {
+                break;
+            }
+            let theta = (z.z / r).acos() * power;
+            let phi = z.y.atan2(z.x) * power;
+            dr = r.powf(power - 1.0) * power * dr + 1.0;
+            z = Vec3 { x: theta.sin() * phi.cos(), y: theta.sin() * phi.sin(), z: theta.cos() } * r.powf(power) + *p;
+            r = z.len();
+        }
+        if r < 1e-12 {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    })
+}
+
+// Menger sponge filling the cube from -1 to 1
+pub fn menger_sponge(iterations: u32) -> DistanceFn {
+    let cube = cuboid(Vec3::new(), Vec3 { x: 1.0, y: 1.0, z: 1.0 });
+    Arc::new(move |p| {
+        let mut d = cube(p);
+        let mut s = 1.0;
+        for _ in 0..iterations {
+            // the cross cut out of every cell at this level
+            let a = Vec3 {
+                x: (p.x * s).rem_euclid(2.0) - 1.0,
+                y: (p.y * s).rem_euclid(2.0) - 1.0,
+                z: (p.z * s).rem_euclid(2.0) - 1.0,
+            };
+            s *= 3.0;
+            let r = Vec3 { x: 1.0, y: 1.0, z: 1.0 } - a.abs() * 3.0;
+            let r = r.abs();
+            let cross = r.x.max(r.y).min(r.y.max(r.z)).min(r.z.max(r.x));
+            d = d.max((cross - 1.0) / s);
+        }
+        d
+    })
+}
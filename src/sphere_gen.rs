@@ -1,5 +1,9 @@
+use super::basic::*;
+use super::mesh::*;
+
+// regular icosahedron with its vertices on the unit sphere around center
 pub struct Icosahedron {
-    pub mesh: Mesh,
+    pub triangles: Vec<Triangle>,
 }
 
 impl Icosahedron {
@@ -7,92 +11,97 @@ impl Icosahedron {
         let mut vertices: Vec<Vec3> = Vec::new();
         let phi = (1.0 + 5.0f32.sqrt()) / 2.0;
         let a = 1.0f32;
-        let b = 1.0f32/phi;
-        vertices.push(center.clone());
-        vertices.push(Vec3 {x: 0., y: b, z: -a});
-        vertices.push(Vec3 {x: b, y: a, z: 0.});
-        vertices.push(Vec3 {x: -b, y: a, z: 0.});
-        vertices.push(Vec3 {x: 0., y: b, z: a});
-        vertices.push(Vec3 {x: 0., y: -b, z: a});
-        vertices.push(Vec3 {x: -a, y: 0., z: b});
-        vertices.push(Vec3 {x: 0., y: -b, z: -a});
-        vertices.push(Vec3 {x: a, y: 0., z: -b});
-        vertices.push(Vec3 {x: a, y: 0., z: b});
-        vertices.push(Vec3 {x: -a, y: 0., z: -b});
-        vertices.push(Vec3 {x: b, y: -a, z: 0.});
-        vertices.push(Vec3 {x: -b, y: -a, z: 0.});
-        
-        for v in vertices.iter_mut() {
+        let b = 1.0f32 / phi;
+        vertices.push(center);
+        vertices.push(Vec3 { x: 0., y: b, z: -a });
+        vertices.push(Vec3 { x: b, y: a, z: 0. });
+        vertices.push(Vec3 { x: -b, y: a, z: 0. });
+        vertices.push(Vec3 { x: 0., y: b, z: a });
+        vertices.push(Vec3 { x: 0., y: -b, z: a });
+        vertices.push(Vec3 { x: -a, y: 0., z: b });
+        vertices.push(Vec3 { x: 0., y: -b, z: -a });
+        vertices.push(Vec3 { x: a, y: 0., z: -b });
+        vertices.push(Vec3 { x: a, y: 0., z: b });
+        vertices.push(Vec3 { x: -a, y: 0., z: -b });
+        vertices.push(Vec3 { x: b, y: -a, z: 0. });
+        vertices.push(Vec3 { x: -b, y: -a, z: 0. });
+
+        for v in vertices.iter_mut().skip(1) {
             v.to_unit_len();
-        }
-        for v in vertices.iter_mut() {
             *v += center;
         }
 
-        let mut mesh: Mesh = Mesh::new();
-        mesh.add_triangle(Triangle::new(vertices[3], vertices[2], vertices[1]));
-        mesh.add_triangle(Triangle::new(vertices[2], vertices[3], vertices[4]));
-        mesh.add_triangle(Triangle::new(vertices[6], vertices[5], vertices[4]));
-        mesh.add_triangle(Triangle::new(vertices[5], vertices[9], vertices[4]));
-        mesh.add_triangle(Triangle::new(vertices[8], vertices[7], vertices[1]));
-        mesh.add_triangle(Triangle::new(vertices[7], vertices[10], vertices[1]));
-        mesh.add_triangle(Triangle::new(vertices[12], vertices[11], vertices[5]));
-        mesh.add_triangle(Triangle::new(vertices[11], vertices[12], vertices[7]));
-        mesh.add_triangle(Triangle::new(vertices[10], vertices[6], vertices[3]));
-        mesh.add_triangle(Triangle::new(vertices[6], vertices[10], vertices[12]));
-        mesh.add_triangle(Triangle::new(vertices[9], vertices[8], vertices[2]));
-        mesh.add_triangle(Triangle::new(vertices[8], vertices[9], vertices[11]));
-        mesh.add_triangle(Triangle::new(vertices[3], vertices[6], vertices[4]));
-        mesh.add_triangle(Triangle::new(vertices[9], vertices[2], vertices[4]));
-        mesh.add_triangle(Triangle::new(vertices[10], vertices[3], vertices[1]));
-        mesh.add_triangle(Triangle::new(vertices[2], vertices[8], vertices[1]));
-        mesh.add_triangle(Triangle::new(vertices[12], vertices[10], vertices[7]));
-        mesh.add_triangle(Triangle::new(vertices[8], vertices[11], vertices[7]));
-        mesh.add_triangle(Triangle::new(vertices[6], vertices[12], vertices[5]));
-        mesh.add_triangle(Triangle::new(vertices[11], vertices[9], vertices[5]));
-    
-        Icosahedron { mesh }
+        let faces = [
+            (3, 2, 1), (2, 3, 4), (6, 5, 4), (5, 9, 4), (8, 7, 1),
+            (7, 10, 1), (12, 11, 5), (11, 12, 7), (10, 6, 3), (6, 10, 12),
+            (9, 8, 2), (8, 9, 11), (3, 6, 4), (9, 2, 4), (10, 3, 1),
+            (2, 8, 1), (12, 10, 7), (8, 11, 7), (6, 12, 5), (11, 9, 5),
+        ];
+        let triangles = faces
+            .iter()
+            .map(|&(i, j, k)| Triangle::new(vertices[i], vertices[j], vertices[k]))
+            .collect();
+        Icosahedron { triangles }
+    }
+
+    pub fn to_mesh(&self, material: Material) -> Mesh {
+        triangle_mesh(&self.triangles, material)
     }
 }
 
+// sphere approximated by a subdivided icosahedron, 20 * 4^subdivisions triangles
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
-    pub mesh: Mesh,
+    pub triangles: Vec<Triangle>,
 }
 
 impl Sphere {
     pub fn new(center: Vec3, radius: f32) -> Sphere {
-        let ico = Icosahedron::new(Vec3::new());
-        let mut mesh = ico.mesh;
-        let n: i32 = 3;
-        for _ in 0..n {
-            mesh = tri_segment_mesh(mesh);
+        Sphere::with_subdivisions(center, radius, 3)
+    }
+
+    pub fn with_subdivisions(center: Vec3, radius: f32, subdivisions: u32) -> Sphere {
+        let mut triangles = Icosahedron::new(Vec3::new()).triangles;
+        for _ in 0..subdivisions {
+            triangles = tri_segment_mesh(&triangles);
         }
-        for triangle in mesh.triangles.iter_mut() {
-            *triangle *= radius; // this is stupid, why Rust do this dereferemce?
+        for triangle in triangles.iter_mut() {
+            *triangle *= radius;
             *triangle += center;
         }
-        Sphere { center, radius, mesh }
+        Sphere { center, radius, triangles }
+    }
+
+    pub fn to_mesh(&self, material: Material) -> Mesh {
+        triangle_mesh(&self.triangles, material)
     }
 }
 
-fn tri_segment_mesh(mesh: Mesh) -> Mesh {
-    let mut new_mesh = Mesh::new();
-    for triangle in mesh.triangles.iter() {
+// split every triangle of the unit sphere in four, pushing the new vertices onto the sphere
+fn tri_segment_mesh(triangles: &[Triangle]) -> Vec<Triangle> {
+    let mut new_triangles = Vec::with_capacity(triangles.len() * 4);
+    for triangle in triangles.iter() {
         let a = triangle.a;
         let b = triangle.b;
         let c = triangle.c;
-        let mut ab = (a + b) * 0.5;
-        let mut bc = (b + c) * 0.5;
-        let mut ca = (c + a) * 0.5;
-        ab.to_unit_len();
-        bc.to_unit_len();
-        ca.to_unit_len();
-        new_mesh.add_triangle(Triangle::new(a, ab, ca));
-        new_mesh.add_triangle(Triangle::new(ab, b, bc));
-        new_mesh.add_triangle(Triangle::new(bc, c, ca));
-        new_mesh.add_triangle(Triangle::new(ab, bc, ca));
+        let ab = ((a + b) * 0.5).normalized();
+        let bc = ((b + c) * 0.5).normalized();
+        let ca = ((c + a) * 0.5).normalized();
+        new_triangles.push(Triangle::new(a, ab, ca));
+        new_triangles.push(Triangle::new(ab, b, bc));
+        new_triangles.push(Triangle::new(bc, c, ca));
+        new_triangles.push(Triangle::new(ab, bc, ca));
+    }
+    new_triangles
+}
+
+fn triangle_mesh(triangles: &[Triangle], material: Material) -> Mesh {
+    let mut mesh = Mesh::new();
+    for triangle in triangles {
+        let mut triangle = *triangle;
+        triangle.material = material;
+        mesh.add_triangle(Box::new(triangle));
     }
-    return new_mesh;
-}
\ No newline at end of file
+    mesh
+}
@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use super::basic::*;
+use super::mesh_gen::*;
+
+// Subdivision surfaces. Edges with a single face (the boundary), edges shared by
+// more than two faces and the crease edges given by their two vertex indices are
+// sharp: they follow the cubic B-spline of the curve they lie on instead of the
+// surface. A vertex on two sharp edges moves along them, one on three or more is
+// a corner and stays where it is.
+//
+// Vertices are matched by index, so meshes with repeated seam vertices, like the
+// generated ones, should be welded (IndexedMesh::weld) first or the seams
+// subdivide as boundaries.
+// uv coordinates are interpolated linearly and the normals recomputed.
+
+// polygon mesh for Catmull-Clark subdivision, faces list their vertices counter-clockwise
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PolyMesh {
+    pub positions: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub faces: Vec<Vec<u32>>,
+}
+
+impl PolyMesh {
+    pub fn new() -> PolyMesh {
+        PolyMesh::default()
+    }
+
+    pub fn add_vertex(&mut self, position: Vec3, uv: (f32, f32)) -> u32 {
+        self.positions.push(position);
+        self.uvs.push(uv);
+        self.positions.len() as u32 - 1
+    }
+
+    pub fn add_face(&mut self, vertices: &[u32]) {
+        self.faces.push(vertices.to_vec());
+    }
+
+    // triangle fans of the faces, with normals
+    pub fn to_indexed(&self) -> IndexedMesh {
+        let mut mesh = IndexedMesh {
+            positions: self.positions.clone(),
+            normals: Vec::new(),
+            uvs: self.uvs.clone(),
+            faces: Vec::new(),
+        };
+        for f in &self.faces {
+            for i in 1..f.len().saturating_sub(1) {
+                mesh.add_face(f[0], f[i], f[i + 1]);
+            }
+        }
+        mesh.recompute_normals();
+        mesh
+    }
+}
+
+impl From<&IndexedMesh> for PolyMesh {
+    fn from(mesh: &IndexedMesh) -> PolyMesh {
+        PolyMesh {
+            positions: mesh.positions.clone(),
+            uvs: mesh.uvs.clone(),
+            faces: mesh.faces.iter().map(|f| f.to_vec()).collect(),
+        }
+    }
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+fn mid_uv(a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    ((a.0 + b.0) * 0.5, (a.1 + b.1) * 0.5)
+}
+
+// edges of the faces with the faces on them, in the order first seen
+struct Edges {
+    keys: Vec<(u32, u32)>,
+    faces: HashMap<(u32, u32), Vec<usize>>,
+    sharp: HashSet<(u32, u32)>,
+}
+
+impl Edges {
+    fn new<'f>(faces: impl Iterator<Item = &'f [u32]>, creases: &[[u32; 2]]) -> Edges {
+        let mut keys = Vec::new();
+        let mut edge_faces: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        for (i, f) in faces.enumerate() {
+            for j in 0..f.len() {
+                let key = edge_key(f[j], f[(j + 1) % f.len()]);
+                let list = edge_faces.entry(key).or_default();
+                if list.is_empty() {
+                    keys.push(key);
+                }
+                list.push(i);
+            }
+        }
+        let creases: HashSet<(u32, u32)> = creases.iter().map(|c| edge_key(c[0], c[1])).collect();
+        let sharp = keys
+            .iter()
+            .filter(|k| edge_faces[*k].len() != 2 || creases.contains(*k))
+            .copied()
+            .collect();
+        Edges { keys, faces: edge_faces, sharp }
+    }
+
+    // for every vertex, the other ends of its edges and of its sharp edges
+    fn neighbors(&self, vertex_count: usize) -> (Vec<Vec<u32>>, Vec<Vec<u32>>) {
+        let mut all = vec![Vec::new(); vertex_count];
+        let mut sharp = vec![Vec::new(); vertex_count];
+        for &(a, b) in &self.keys {
+            all[a as usize].push(b);
+            all[b as usize].push(a);
+            if self.sharp.contains(&(a, b)) {
+                sharp[a as usize].push(b);
+                sharp[b as usize].push(a);
+            }
+        }
+        (all, sharp)
+    }
+
+    // the sharp edges split in two at their new midpoint vertices
+    fn split_creases(&self, midpoint: &HashMap<(u32, u32), u32>) -> Vec<[u32; 2]> {
+        let mut creases = Vec::new();
+        for key in &self.keys {
+            if self.sharp.contains(key) {
+                let m = midpoint[key];
+                creases.push([key.0, m]);
+                creases.push([m, key.1]);
+            }
+        }
+        creases
+    }
+}
+
+// position of a vertex on sharp edges: moved along a crease, kept at a corner;
+// None if the vertex takes the smooth rule
+fn sharp_vertex(p: &Vec3, sharp: &[u32], positions: &[Vec3]) -> Option<Vec3> {
+    match sharp.len() {
+        0 | 1 => None,
+        2 => Some(*p * 0.75 + (positions[sharp[0] as usize] + positions[sharp[1] as usize]) * 0.125),
+        _ => Some(*p),
+    }
+}
+
+// one step of Loop subdivision: every triangle split in four, returns the mesh and its
+// sharp edges, which include the boundary, so later steps keep them sharp
+pub fn loop_step(mesh: &IndexedMesh, creases: &[[u32; 2]]) -> (IndexedMesh, Vec<[u32; 2]>) {
+    let p = &mesh.positions;
+    let edges = Edges::new(mesh.faces.iter().map(|f| &f[..]), creases);
+    let (neighbors, sharp) = edges.neighbors(p.len());
+
+    let mut out = IndexedMesh::new();
+    for (v, position) in p.iter().enumerate() {
+        let moved = sharp_vertex(position, &sharp[v], p).unwrap_or_else(|| {
+            let ring = &neighbors[v];
+            let n = ring.len() as f32;
+            if ring.is_empty() {
+                return *position;
+            }
+            let c = 0.375 + 0.25 * (2.0 * PI / n).cos();
+            let beta = (0.625 - c * c) / n;
+            let sum = ring.iter().fold(Vec3::new(), |s, &i| s + p[i as usize]);
+            *position * (1.0 - n * beta) + sum * beta
+        });
+        out.add_vertex(moved, Vec3::new(), mesh.uvs[v]);
+    }
+
+    let mut midpoint = HashMap::new();
+    for key in &edges.keys {
+        let (a, b) = (p[key.0 as usize], p[key.1 as usize]);
+        let position = if edges.sharp.contains(key) {
+            (a + b) * 0.5
+        } else {
+            // the vertices opposite the edge in its two triangles
+            let opposite = edges.faces[key].iter().fold(Vec3::new(), |s, &f| {
+                let c = mesh.faces[f].iter().find(|&&i| i != key.0 && i != key.1).copied().unwrap_or(key.0);
+                s + p[c as usize]
+            });
+            (a + b) * 0.375 + opposite * 0.125
+        };
+        let uv = mid_uv(mesh.uvs[key.0 as usize], mesh.uvs[key.1 as usize]);
+        midpoint.insert(*key, out.add_vertex(position, Vec3::new(), uv));
+    }
+
+    for &[a, b, c] in &mesh.faces {
+        let (ab, bc, ca) = (midpoint[&edge_key(a, b)], midpoint[&edge_key(b, c)], midpoint[&edge_key(c, a)]);
+        out.add_face(a, ab, ca);
+        out.add_face(ab, b, bc);
+        out.add_face(ca, bc, c);
+        out.add_face(ab, bc, ca);
+    }
+    out.recompute_normals();
+    let creases = edges.split_creases(&midpoint);
+    (out, creases)
+}
+
+// the triangle mesh after the given number of Loop subdivision steps
+pub fn loop_subdivision(mesh: &IndexedMesh, creases: &[[u32; 2]], levels: u32) -> IndexedMesh {
+    let mut mesh = mesh.clone();
+    let mut creases = creases.to_vec();
+    for _ in 0..levels {
+        (mesh, creases) = loop_step(&mesh, &creases);
+    }
+    mesh
+}
+
+// one step of Catmull-Clark subdivision: an n-gon becomes n quads, returns the
+// mesh and its sharp edges as loop_step does
+pub fn catmull_clark_step(mesh: &PolyMesh, creases: &[[u32; 2]]) -> (PolyMesh, Vec<[u32; 2]>) {
+    let p = &mesh.positions;
+    let edges = Edges::new(mesh.faces.iter().map(|f| &f[..]), creases);
+    let (neighbors, sharp) = edges.neighbors(p.len());
+
+    let face_points: Vec<(Vec3, (f32, f32))> = mesh
+        .faces
+        .iter()
+        .map(|f| {
+            let n = f.len() as f32;
+            let position = f.iter().fold(Vec3::new(), |s, &i| s + p[i as usize]) / n;
+            let uv = f.iter().fold((0.0, 0.0), |s, &i| (s.0 + mesh.uvs[i as usize].0, s.1 + mesh.uvs[i as usize].1));
+            (position, (uv.0 / n, uv.1 / n))
+        })
+        .collect();
+    let mut vertex_faces = vec![Vec::new(); p.len()];
+    for (i, f) in mesh.faces.iter().enumerate() {
+        for &v in f {
+            vertex_faces[v as usize].push(i);
+        }
+    }
+
+    let mut out = PolyMesh::new();
+    for (v, position) in p.iter().enumerate() {
+        let faces = &vertex_faces[v];
+        let ring = &neighbors[v];
+        let moved = sharp_vertex(position, &sharp[v], p).unwrap_or_else(|| {
+            if faces.is_empty() || ring.is_empty() {
+                return *position;
+            }
+            // (F + 2 R + (n - 3) P) / n with F the mean face point and R the mean edge midpoint
+            let n = faces.len() as f32;
+            let f = faces.iter().fold(Vec3::new(), |s, &i| s + face_points[i].0) / n;
+            let r = ring.iter().fold(Vec3::new(), |s, &i| s + (*position + p[i as usize]) * 0.5) / ring.len() as f32;
+            (f + r * 2.0 + *position * (n - 3.0)) / n
+        });
+        out.add_vertex(moved, mesh.uvs[v]);
+    }
+    let first_face_point = out.positions.len() as u32;
+    for (position, uv) in &face_points {
+        out.add_vertex(*position, *uv);
+    }
+
+    let mut midpoint = HashMap::new();
+    for key in &edges.keys {
+        let (a, b) = (p[key.0 as usize], p[key.1 as usize]);
+        let position = if edges.sharp.contains(key) {
+            (a + b) * 0.5
+        } else {
+            let faces = &edges.faces[key];
+            (a + b + faces.iter().fold(Vec3::new(), |s, &f| s + face_points[f].0)) / (2.0 + faces.len() as f32)
+        };
+        let uv = mid_uv(mesh.uvs[key.0 as usize], mesh.uvs[key.1 as usize]);
+        midpoint.insert(*key, out.add_vertex(position, uv));
+    }
+
+    for (i, f) in mesh.faces.iter().enumerate() {
+        let center = first_face_point + i as u32;
+        let n = f.len();
+        for j in 0..n {
+            let (prev, v, next) = (f[(j + n - 1) % n], f[j], f[(j + 1) % n]);
+            out.add_face(&[v, midpoint[&edge_key(v, next)], center, midpoint[&edge_key(prev, v)]]);
+        }
+    }
+    let creases = edges.split_creases(&midpoint);
+    (out, creases)
+}
+
+// the quad mesh after the given number of Catmull-Clark subdivision steps
+pub fn catmull_clark_subdivision(mesh: &PolyMesh, creases: &[[u32; 2]], levels: u32) -> PolyMesh {
+    let mut mesh = mesh.clone();
+    let mut creases = creases.to_vec();
+    for _ in 0..levels {
+        (mesh, creases) = catmull_clark_step(&mesh, &creases);
+    }
+    mesh
+}
@@ -1,10 +1,30 @@
-use crate::tracer::*;
-use crate::basic::*;
-use crate::mesh::*;
-
 #[cfg(test)]
 pub mod unittests {
-    use super::*;
+    use crate::tracer::*;
+    use crate::basic::*;
+    use crate::mesh::*;
+    use crate::volume::*;
+    use crate::transform::*;
+    use crate::renderer::*;
+    use crate::sampler::*;
+    use crate::progressive::*;
+    use crate::adaptive::*;
+    use crate::aov::*;
+    use crate::denoise::*;
+    use crate::sphere_gen::*;
+    use crate::scene::*;
+    use crate::bvh::*;
+    use crate::graph::*;
+    use crate::scenes;
+    use crate::primitives::*;
+    use crate::mesh_gen::*;
+    use crate::subdivision::*;
+    use crate::mesh_ops::*;
+    use crate::csg::*;
+    use crate::sdf;
+    use crate::sdf::Sdf;
+    use std::time::Duration;
+    use std::sync::Arc;
 
     #[test]
     fn test_vec3_0() {
@@ -63,6 +83,56 @@ pub mod unittests {
     }
 
 
+    #[test]
+    fn test_vec3_6() {
+        let v = Vec3 { x: 1.0, y: -2.0, z: 4.0 };
+        let w = Vec3 { x: 2.0, y: 2.0, z: -1.0 };
+        assert_eq!(-v, Vec3 { x: -1.0, y: 2.0, z: -4.0 });
+        assert_eq!(v * w, Vec3 { x: 2.0, y: -4.0, z: -4.0 });
+        assert_eq!(v / 2.0, Vec3 { x: 0.5, y: -1.0, z: 2.0 });
+        assert_eq!(v / w, Vec3 { x: 0.5, y: -1.0, z: -4.0 });
+        assert_eq!(v.min(&w), Vec3 { x: 1.0, y: -2.0, z: -1.0 });
+        assert_eq!(v.max(&w), Vec3 { x: 2.0, y: 2.0, z: 4.0 });
+        assert_eq!(v.abs().max_component(), 4.0);
+        assert_eq!(v.lerp(&w, 0.5), Vec3 { x: 1.5, y: 0.0, z: 1.5 });
+        assert_eq!(v[1], -2.0);
+        let mut u = v;
+        u[2] = 0.0;
+        assert_eq!(u.z, 0.0);
+    }
+
+    #[test]
+    fn test_vec3_7() {
+        // normalizing the zero vector does not produce NaNs
+        let mut zero = Vec3::new();
+        zero.to_unit_len();
+        assert!(zero.near_zero());
+        assert_eq!(Vec3 { x: 0.0, y: 3.0, z: 4.0 }.normalized().y, 0.6);
+
+        let n = Vec3 { x: 0.0, y: 1.0, z: 0.0 };
+        for _ in 0..100 {
+            assert!(Vec3::random_in_unit_sphere().len() < 1.0);
+            assert!((Vec3::random_unit_vector().len() - 1.0).abs() < 1e-5);
+            assert!(dot(&Vec3::random_on_hemisphere(&n), &n) >= 0.0);
+        }
+    }
+
+    #[test]
+    fn test_reflect_refract_0() {
+        let v = Vec3 { x: 1.0, y: -1.0, z: 0.0 }.normalized();
+        let n = Vec3 { x: 0.0, y: 1.0, z: 0.0 };
+        let r = reflect(&v, &n);
+        assert!((r.x - v.x).abs() < 1e-6 && (r.y + v.y).abs() < 1e-6);
+        // index-matched refraction goes straight through
+        let t = refract(&v, &n, 1.0).unwrap();
+        assert!((t - v).len() < 1e-6);
+        // grazing exit from a dense medium is totally reflected
+        assert!(refract(&v, &n, 1.5).is_none());
+        // Snell's law
+        let t = refract(&v, &n, 1.0 / 1.5).unwrap();
+        assert!((t.x * 1.5 - v.x).abs() < 1e-5);
+    }
+
     #[test]
     fn test_pix_0() {
         let mut pix = Pix::new();
@@ -86,6 +156,43 @@ pub mod unittests {
         assert_eq!(pix3.to_string(), "255 255 255\n");
     }
 
+    #[test]
+    fn test_pix_3() {
+        // bright pixels saturate instead of overflowing
+        let bright = Pix { r: 200u8, g: 100u8, b: 255u8 };
+        assert_eq!(bright + bright, Pix { r: 255u8, g: 200u8, b: 255u8 });
+        let mut p = bright;
+        p -= Pix { r: 255u8, g: 50u8, b: 0u8 };
+        assert_eq!(p, Pix { r: 0u8, g: 50u8, b: 255u8 });
+        assert_eq!(bright.scale(2.0), Pix { r: 255u8, g: 200u8, b: 255u8 });
+    }
+
+    #[test]
+    fn test_pix_4() {
+        let mut pix = Pix::new();
+        pix.set_float(-0.5, 0.5, 7.0);
+        assert_eq!(pix, Pix { r: 0u8, g: 128u8, b: 255u8 });
+        pix.set_float(f32::NAN, f32::INFINITY, 0.2);
+        assert_eq!(pix, Pix { r: 0u8, g: 255u8, b: 51u8 });
+    }
+
+    #[test]
+    fn test_pix_5() {
+        let black = Pix::new();
+        let white = Pix { r: 255u8, g: 255u8, b: 255u8 };
+        assert_eq!(black.blend(&white, 0.5), Pix { r: 128u8, g: 128u8, b: 128u8 });
+        assert_eq!(white.over(&black, 0.0), black);
+        assert_eq!(white.over(&black, 1.0), white);
+        assert_eq!(Pix::average(&[white, white, black]), Pix { r: 170u8, g: 170u8, b: 170u8 });
+
+        let mut a = Image::new(1, 1);
+        let mut b = Image::new(1, 1);
+        a[0][0] = white;
+        b[0][0] = white;
+        assert_eq!(Image::average(&[a, b]).unwrap()[0][0], white);
+        assert!(Image::average(&[Image::new(1, 1), Image::new(2, 1)]).is_none());
+    }
+
     #[test]
     fn test_image_0() {
         let image = Image::new(2, 2);
@@ -257,4 +364,1802 @@ pub mod unittests {
         let interv = Interval::new();
         assert_eq!(mesh.is_hit(&ray, &interv).unwrap().t, 1.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_ior_0() {
+        let glass = Ior::Cauchy(1.5046, 0.0042);
+        assert!(glass.at(400.0) > glass.at(700.0));
+        assert!(glass.is_dispersive());
+        let constant = Ior::Constant(1.5);
+        assert_eq!(constant.at(400.0), constant.at(700.0));
+        assert!(!constant.is_dispersive());
+        // BK7
+        let bk7 = Ior::Sellmeier([1.0396, 0.2318, 1.0105], [0.0060, 0.0200, 103.56]);
+        assert!((bk7.at(587.6) - 1.5168).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_dielectric_0() {
+        // entering an index-matched medium head on: no reflection, no absorption
+        let ray = Ray {
+            origin: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+            direction: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+        };
+        let mut triangle = Triangle::new(
+            Vec3 { x: -1.0, y: 1.0, z: 1.0 },
+            Vec3 { x: 1.0, y: 1.0, z: 1.0 },
+            Vec3 { x: 0.0, y: -2.0, z: 1.0 },
+        );
+        triangle.material = Material::Dielectric(Ior::Constant(1.0), Vec3 { x: 1.0, y: 1.0, z: 1.0 });
+        let rec = triangle.is_hit(&ray, &Interval::new()).unwrap();
+        assert!(rec.front_face);
+        let (attenuation, scattered) = rec.material.scatter(&ray, &rec, &mut None, &mut IndependentSampler::new(0)).unwrap();
+        assert_eq!(attenuation.x, 1.0);
+        assert!((scattered.direction.z - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_dielectric_1() {
+        // leaving the medium after travelling 2 units inside it
+        let ray = Ray {
+            origin: Vec3 { x: 0.0, y: 0.0, z: -1.0 },
+            direction: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+        };
+        let mut triangle = Triangle::new(
+            Vec3 { x: -1.0, y: 1.0, z: 1.0 },
+            Vec3 { x: 0.0, y: -2.0, z: 1.0 },
+            Vec3 { x: 1.0, y: 1.0, z: 1.0 },
+        );
+        triangle.material = Material::Dielectric(Ior::Constant(1.0), Vec3 { x: 0.5, y: 0.0, z: 1.0 });
+        let rec = triangle.is_hit(&ray, &Interval::new()).unwrap();
+        assert!(!rec.front_face);
+        let (attenuation, _) = rec.material.scatter(&ray, &rec, &mut None, &mut IndependentSampler::new(0)).unwrap();
+        assert!((attenuation.x - (-1.0f32).exp()).abs() < 1e-6);
+        assert_eq!(attenuation.y, 1.0);
+        assert!((attenuation.z - (-2.0f32).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_dielectric_2() {
+        // a dispersive material traces one channel at a time
+        let ray = Ray {
+            origin: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+            direction: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+        };
+        let mut triangle = Triangle::new(
+            Vec3 { x: -1.0, y: 1.0, z: 1.0 },
+            Vec3 { x: 1.0, y: 1.0, z: 1.0 },
+            Vec3 { x: 0.0, y: -2.0, z: 1.0 },
+        );
+        triangle.material = Material::Dielectric(Ior::Cauchy(1.5046, 0.0042), Vec3::new());
+        let rec = triangle.is_hit(&ray, &Interval::new()).unwrap();
+        let mut sampler = IndependentSampler::new(0);
+        for _ in 0..16 {
+            // the first dispersive interface picks the path's channel and weights it
+            let mut channel = None;
+            let (attenuation, _) = rec.material.scatter(&ray, &rec, &mut channel, &mut sampler).unwrap();
+            let c = channel.unwrap();
+            assert_eq!(attenuation[c], 3.0);
+            assert_eq!(attenuation.x + attenuation.y + attenuation.z, 3.0);
+            // later ones keep it, unweighted
+            for _ in 0..4 {
+                let (attenuation, _) = rec.material.scatter(&ray, &rec, &mut channel, &mut sampler).unwrap();
+                assert_eq!(channel, Some(c));
+                assert_eq!(attenuation[c], 1.0);
+                assert_eq!(attenuation.x + attenuation.y + attenuation.z, 1.0);
+            }
+        }
+    }
+
+    // two parallel triangles at z = 1 and z = 2 bounding a slab around the z axis
+    fn slab() -> Mesh {
+        let mut mesh = Mesh::new();
+        for z in [1.0, 2.0] {
+            mesh.add_triangle(Box::new(Triangle::new(
+                Vec3 { x: -1.0, y: -1.0, z },
+                Vec3 { x: 2.0, y: -1.0, z },
+                Vec3 { x: -1.0, y: 2.0, z },
+            )));
+        }
+        mesh
+    }
+
+    #[test]
+    fn test_constant_medium_0() {
+        let ray = Ray {
+            origin: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+            direction: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+        };
+        let albedo = Vec3 { x: 0.5, y: 0.5, z: 0.5 };
+        let empty = ConstantMedium::new(Box::new(slab()), 0.0, Material::Isotropic(albedo));
+        assert!(empty.is_hit(&ray, &Interval::new()).is_none());
+
+        let dense = ConstantMedium::new(Box::new(slab()), 1e4, Material::Isotropic(albedo));
+        let rec = dense.is_hit(&ray, &Interval::new()).unwrap();
+        assert!(rec.t >= 1.0 && rec.t < 1.01);
+        let (attenuation, scattered) = rec.material.scatter(&ray, &rec, &mut None, &mut IndependentSampler::new(0)).unwrap();
+        assert_eq!(attenuation.x, 0.5);
+        assert!((scattered.direction.len() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_constant_medium_1() {
+        // the medium is clipped by the ray interval
+        let ray = Ray {
+            origin: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+            direction: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+        };
+        let phase = Material::HenyeyGreenstein(Vec3 { x: 1.0, y: 1.0, z: 1.0 }, 0.5);
+        let dense = ConstantMedium::new(Box::new(slab()), 1e4, phase);
+        assert!(dense.is_hit(&ray, &Interval::initialize(0.0, 0.9)).is_none());
+        let rec = dense.is_hit(&ray, &Interval::initialize(1.5, f32::MAX)).unwrap();
+        assert!(rec.t >= 1.5 && rec.t < 1.51);
+    }
+
+    #[test]
+    fn test_henyey_greenstein_0() {
+        let direction = Vec3 { x: 0.0, y: 0.0, z: 2.0 };
+        let mut forward = 0.0;
+        for _ in 0..1000 {
+            let d = sample_henyey_greenstein(&direction, 0.9, (random_f32(), random_f32()));
+            assert!((d.len() - 1.0).abs() < 1e-4);
+            forward += d.z;
+        }
+        // the mean cosine of the Henyey-Greenstein distribution is g
+        assert!((forward / 1000.0 - 0.9).abs() < 0.05);
+        assert!(henyey_greenstein(1.0, 0.9) > henyey_greenstein(-1.0, 0.9));
+    }
+
+    #[test]
+    fn test_ray_color_0() {
+        let ray = Ray {
+            origin: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+            direction: Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        };
+        let world = Mesh::new();
+        let sky = ray_color(&ray, &world, None, 8, &mut IndependentSampler::new(0));
+        assert_eq!(sky.z, ray.gradient_color().z);
+
+        // an opaque black fog right in front of the camera
+        let fog = Fog::new(1e4, Vec3::new());
+        let fogged = ray_color(&ray, &world, Some(&fog), 8, &mut IndependentSampler::new(0));
+        assert_eq!(fogged.len(), 0.0);
+    }
+
+    #[test]
+    fn test_mat4_0() {
+        let m = Mat4::translation(Vec3 { x: 1.0, y: 2.0, z: 3.0 })
+            * Mat4::rotation(Vec3 { x: 1.0, y: 1.0, z: 0.0 }, 0.7)
+            * Mat4::scaling(Vec3 { x: 2.0, y: 0.5, z: 3.0 });
+        let id = m * m.inverse().unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((id.m[i][j] - expected).abs() < 1e-5);
+            }
+        }
+        assert!(Mat4::scaling(Vec3 { x: 1.0, y: 0.0, z: 1.0 }).inverse().is_none());
+    }
+
+    #[test]
+    fn test_transform_0() {
+        let rot = Transform::rotate(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, std::f32::consts::FRAC_PI_2);
+        let t = rot.then(&Transform::translate(Vec3 { x: 0.0, y: 0.0, z: 1.0 }));
+        let p = t.point(&Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+        assert!(p.x.abs() < 1e-6 && p.y.abs() < 1e-6 && p.z.abs() < 1e-6);
+        let back = t.inverse().point(&p);
+        assert!((back.x - 1.0).abs() < 1e-6);
+        // directions ignore the translation
+        let v = t.vector(&Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+        assert!((v.z + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_instance_0() {
+        // a tilted triangle, stretched along z and moved away from the camera
+        let triangle = Triangle::new(
+            Vec3 { x: -1.0, y: -1.0, z: 0.0 },
+            Vec3 { x: 2.0, y: -1.0, z: 1.0 },
+            Vec3 { x: -1.0, y: 2.0, z: 0.0 },
+        );
+        let transform = Transform::scale(Vec3 { x: 1.0, y: 1.0, z: 2.0 })
+            .then(&Transform::translate(Vec3 { x: 0.0, y: 0.0, z: 3.0 }));
+        let object: Arc<dyn Hitable> = Arc::new(triangle.transformed(&Transform::new()));
+        let instance = Instance::new(object, transform);
+        let ray = Ray {
+            origin: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+            direction: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+        };
+        let rec = instance.is_hit(&ray, &Interval::new()).unwrap();
+
+        // the same triangle with its vertices transformed directly
+        let baked = triangle.transformed(&transform);
+        let expected = baked.is_hit(&ray, &Interval::new()).unwrap();
+        assert!((rec.t - expected.t).abs() < 1e-5);
+        assert!((rec.p.z - expected.p.z).abs() < 1e-5);
+        assert!((dot(&rec.normal, &expected.normal) - 1.0).abs() < 1e-5);
+        assert_eq!(rec.front_face, expected.front_face);
+    }
+
+    #[test]
+    fn test_mat3_0() {
+        let m = Mat3::rotation(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, 0.3) * Mat3::scaling(Vec3 { x: 2.0, y: 3.0, z: 4.0 });
+        assert!((m.determinant() - 24.0).abs() < 1e-4);
+        let id = m * m.inverse().unwrap();
+        for i in 0..3 {
+            for j in 0..3 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((id.m[i][j] - expected).abs() < 1e-5);
+            }
+        }
+        // normals stay perpendicular to transformed surfaces
+        let mat = Mat4::scaling(Vec3 { x: 1.0, y: 4.0, z: 1.0 });
+        let tangent = Vec3 { x: 1.0, y: -1.0, z: 0.0 }.transform_vector(&mat);
+        let normal = Vec3 { x: 1.0, y: 1.0, z: 0.0 }.transform_normal(&mat);
+        assert!(dot(&tangent, &normal).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_quat_0() {
+        let axis = Vec3 { x: 1.0, y: 2.0, z: -1.0 };
+        let q = Quat::from_axis_angle(axis, 1.2);
+        let v = Vec3 { x: 0.3, y: -0.5, z: 2.0 };
+        let a = q.rotate(&v);
+        let b = v.transform_vector(&Mat4::rotation(axis, 1.2));
+        assert!((a - b).len() < 1e-5);
+        let (_, angle) = q.to_axis_angle();
+        assert!((angle - 1.2).abs() < 1e-5);
+        let back = q.inverse().rotate(&a);
+        assert!((back - v).len() < 1e-5);
+        // composing two quarter turns gives a half turn
+        let quarter = Quat::from_axis_angle(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, std::f32::consts::FRAC_PI_2);
+        let half = (quarter * quarter).rotate(&Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+        assert!((half.x + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_quat_slerp_0() {
+        let z = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+        let a = Quat::identity();
+        let b = Quat::from_axis_angle(z, std::f32::consts::FRAC_PI_2);
+        let mid = Quat::slerp(&a, &b, 0.5);
+        let (_, angle) = mid.to_axis_angle();
+        assert!((angle - std::f32::consts::FRAC_PI_4).abs() < 1e-5);
+        let end = Quat::slerp(&a, &b, 1.0);
+        assert!((end.dot(&b) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_look_at_0() {
+        let eye = Vec3 { x: 1.0, y: 2.0, z: 3.0 };
+        let target = Vec3 { x: 1.0, y: 2.0, z: -3.0 };
+        let view = Mat4::look_at(eye, target, Vec3 { x: 0.0, y: 1.0, z: 0.0 });
+        let e = eye.transform_point(&view);
+        assert!(e.len() < 1e-6);
+        let t = target.transform_point(&view);
+        assert!((t.z + 6.0).abs() < 1e-5);
+        // points on the near and far planes map to -1 and 1
+        let proj = Mat4::perspective(1.0, 2.0, 0.5, 10.0);
+        assert!((Vec3 { x: 0.0, y: 0.0, z: -0.5 }.transform_point(&proj).z + 1.0).abs() < 1e-5);
+        assert!((Vec3 { x: 0.0, y: 0.0, z: -10.0 }.transform_point(&proj).z - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_camera_look_at_0() {
+        let look_from = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+        let look_at = Vec3 { x: 3.0, y: 0.0, z: 1.0 };
+        let camera = Camera::look_at(look_from, look_at, Vec3 { x: 0.0, y: 1.0, z: 0.0 }, 90.0, 2.0);
+        let mut center = camera.get_ray(0.5, 0.5).direction;
+        center.to_unit_len();
+        assert!((center.x - 1.0).abs() < 1e-5);
+        // the top left corner is up and to the left of the view direction
+        let corner = camera.get_ray(0.0, 0.0).direction;
+        assert!(corner.y > 0.0 && corner.z < 0.0);
+
+        // looking straight down with y up still gives a square screen around the view direction
+        let up = Vec3 { x: 0.0, y: 1.0, z: 0.0 };
+        let camera = Camera::look_at(Vec3 { x: 0.0, y: 5.0, z: 0.0 }, Vec3::new(), up, 90.0, 1.0);
+        assert!((camera.view_direction() + up).len() < 1e-5);
+        let (h, v) = (camera.screen.horizontal, camera.screen.vertical);
+        assert!((h.len() - 2.0).abs() < 1e-5 && (v.len() - 2.0).abs() < 1e-5);
+        assert!(dot(&h, &v).abs() < 1e-5 && dot(&h, &up).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_encoding_0() {
+        assert_eq!(encode(0.0, Encoding::Srgb), 0.0);
+        assert!((encode(1.0, Encoding::Srgb) - 1.0).abs() < 1e-6);
+        assert!((encode(0.5, Encoding::Srgb) - 0.7354).abs() < 1e-4);
+        assert!((encode(0.001, Encoding::Srgb) - 0.01292).abs() < 1e-6);
+        assert!((encode(0.25, Encoding::Gamma(2.0)) - 0.5).abs() < 1e-6);
+        assert_eq!(encode(0.25, Encoding::Linear), 0.25);
+    }
+
+    #[test]
+    fn test_tone_map_0() {
+        let grey = Vec3 { x: 1.0, y: 1.0, z: 1.0 };
+        assert!((tone_map(grey, ToneMap::Reinhard).x - 0.5).abs() < 1e-5);
+        // the white point maps to one
+        let white = grey * 4.0;
+        assert!((tone_map(white, ToneMap::ReinhardExtended(4.0)).x - 1.0).abs() < 1e-5);
+        assert_eq!(tone_map(Vec3::new(), ToneMap::Aces).x, 0.0);
+        assert!(tone_map(grey * 100.0, ToneMap::Aces).x > 0.99);
+        assert_eq!(tone_map(grey * 2.0, ToneMap::Clamp).x, 1.0);
+    }
+
+    #[test]
+    fn test_output_transform_0() {
+        let mut buffer = FrameBuffer::new(2, 1);
+        buffer.add_sample(0, 0, Vec3 { x: 0.25, y: 0.25, z: 0.25 }, 1.0);
+        buffer.add_sample(0, 1, Vec3 { x: 0.2, y: 0.2, z: 0.2 }, 1.0);
+        buffer.add_sample(0, 1, Vec3 { x: 0.3, y: 0.3, z: 0.3 }, 1.0);
+        assert!((buffer.get(0, 1).x - 0.25).abs() < 1e-6);
+
+        // one stop of exposure doubles the linear value
+        let linear = OutputTransform::new(1.0, ToneMap::Clamp, Encoding::Linear);
+        let image = linear.to_image(&buffer);
+        assert_eq!(image[0][0], Pix { r: 128u8, g: 128u8, b: 128u8 });
+        assert_eq!(image[0][1], image[0][0]);
+
+        let srgb = OutputTransform::default().to_image(&buffer);
+        assert_eq!(srgb[0][0].r, 137u8);
+    }
+
+    #[test]
+    fn test_filter_0() {
+        let box_filter = Filter::Box(0.5);
+        assert_eq!(box_filter.eval(0.2, -0.4), 1.0);
+        assert_eq!(box_filter.eval(0.6, 0.0), 0.0);
+        assert_eq!(box_filter.eval(0.5, -0.5), 1.0);
+        let tent = Filter::Tent(1.0);
+        assert_eq!(tent.eval(0.0, 0.0), 1.0);
+        assert_eq!(tent.eval(0.5, 0.0), 0.5);
+        let gaussian = Filter::Gaussian(1.5, 2.0);
+        assert!(gaussian.eval(0.0, 0.0) > gaussian.eval(1.0, 0.0));
+        assert_eq!(gaussian.eval(1.5, 0.0), 0.0);
+        // Mitchell-Netravali has a negative lobe and vanishes at its radius
+        let mitchell = Filter::Mitchell(2.0, 1.0 / 3.0, 1.0 / 3.0);
+        assert!(mitchell.eval(0.0, 0.0) > 0.0);
+        assert!(mitchell.eval(1.5, 0.0) < 0.0);
+        assert!(mitchell.eval(2.0, 0.0).abs() < 1e-6);
+        // radii too small to cover a pixel are widened to half a pixel
+        assert_eq!(Filter::Tent(0.0).radius(), 0.5);
+        assert_eq!(Filter::Tent(0.0).eval(0.0, 0.0), 1.0);
+        assert_eq!(Filter::Box(0.1).eval(0.4, 0.4), 1.0);
+    }
+
+    #[test]
+    fn test_filter_1() {
+        // every pixel gets samples, whatever the radius
+        let camera = Camera::new(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, Screen::new(4.0, 2.0));
+        let mut settings = RenderSettings::new(8, 4);
+        for filter in [Filter::Box(0.0), Filter::Box(0.2), Filter::Tent(0.0), Filter::Gaussian(0.1, 2.0)] {
+            settings.filter = filter;
+            let buffer = render(&camera, &Mesh::new(), None, &settings);
+            for row in 0..4 {
+                for col in 0..8 {
+                    let color = buffer.get(row, col);
+                    assert!(color.x.is_finite() && color.len() > 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_splat_0() {
+        let white = Vec3 { x: 1.0, y: 1.0, z: 1.0 };
+        // a box filter keeps the sample inside its pixel
+        let mut buffer = FrameBuffer::new(3, 3);
+        buffer.splat(1.9, 1.1, white, &Filter::Box(0.5));
+        assert_eq!(buffer.get(1, 1).x, 1.0);
+        assert_eq!(buffer.get(1, 2).x, 0.0);
+        // a sample on the border between two pixels counts for both
+        buffer.splat(2.0, 0.5, white, &Filter::Box(0.5));
+        assert_eq!((buffer.get(0, 1).x, buffer.get(0, 2).x), (1.0, 1.0));
+        // a wider filter spreads it across pixel boundaries
+        let mut buffer = FrameBuffer::new(3, 3);
+        buffer.splat(1.9, 1.1, white, &Filter::Tent(1.0));
+        assert_eq!(buffer.get(1, 2).x, 1.0);
+        assert_eq!(buffer.get(0, 0).x, 0.0);
+        // samples near the border are clipped to the image
+        buffer.splat(0.1, 0.1, white, &Filter::Tent(2.0));
+        assert_eq!(buffer.get(0, 0).x, 1.0);
+    }
+
+    #[test]
+    fn test_stratified_sampler_0() {
+        let mut sampler = StratifiedSampler::new(16, 0);
+        // one sample per stratum of the 4 x 4 grid, and of the 16 1D strata
+        let mut strata_2d = [false; 16];
+        let mut strata_1d = [false; 16];
+        for index in 0..16 {
+            sampler.start_sample(3, 5, index);
+            let (x, y) = sampler.get_2d();
+            strata_2d[(y * 4.0) as usize * 4 + (x * 4.0) as usize] = true;
+            strata_1d[(sampler.get_1d() * 16.0) as usize] = true;
+        }
+        assert!(strata_2d.iter().all(|s| *s));
+        assert!(strata_1d.iter().all(|s| *s));
+    }
+
+    #[test]
+    fn test_low_discrepancy_0() {
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 3), 0.75);
+        assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() < 1e-6);
+        // every power of two prefix of the Sobol (0, 2)-sequence is stratified
+        let mut cells = [false; 16];
+        for i in 0..16 {
+            let x = sobol(i, 0) >> 30;
+            let y = sobol(i, 1) >> 30;
+            cells[(y * 4 + x) as usize] = true;
+        }
+        assert!(cells.iter().all(|c| *c));
+        // Owen scrambling keeps that property
+        let mut sampler = SobolSampler::new(0);
+        let mut cells = [false; 16];
+        for index in 0..16 {
+            sampler.start_sample(7, 2, index);
+            let (x, y) = sampler.get_2d();
+            cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] = true;
+        }
+        assert!(cells.iter().all(|c| *c));
+        let mut seen = [false; 10];
+        for i in 0..10 {
+            seen[permutation_element(i, 10, 1234) as usize] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+    }
+
+    #[test]
+    fn test_samplers_0() {
+        let kinds = [
+            SamplerKind::Independent,
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+            SamplerKind::BlueNoise,
+        ];
+        for kind in kinds {
+            let mut sampler = kind.create(8, 0);
+            let mut mean = 0.0;
+            for index in 0..64 {
+                sampler.start_sample(1, 2, index);
+                for _ in 0..40 {
+                    let u = sampler.get_1d();
+                    let (x, y) = sampler.get_2d();
+                    assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                }
+                sampler.start_sample(1, 2, index);
+                mean += sampler.get_1d();
+            }
+            assert!((mean / 64.0 - 0.5).abs() < 0.1);
+        }
+        // the blue noise mask is a permutation of its ranks
+        let mask = blue_noise_mask();
+        let mut sorted: Vec<f32> = mask.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        for (i, v) in sorted.iter().enumerate() {
+            assert!((v * mask.len() as f32 - (i as f32 + 0.5)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_render_0() {
+        // an empty scene renders the sky gradient at every pixel center
+        let camera = Camera::new(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, Screen::new(4.0, 2.0));
+        let mut settings = RenderSettings::new(8, 4);
+        settings.samples_per_pixel = 4;
+        settings.filter = Filter::Mitchell(2.0, 1.0 / 3.0, 1.0 / 3.0);
+        let buffer = render(&camera, &Mesh::new(), None, &settings);
+        let center = camera.get_ray(4.5 / 8.0, 2.5 / 4.0).gradient_color();
+        assert!((buffer.get(2, 4) - center).len() < 0.05);
+    }
+
+    #[test]
+    fn test_camera_lens_0() {
+        let camera = Camera::look_at(
+            Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+            Vec3 { x: 0.0, y: 0.0, z: -1.0 },
+            Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+            60.0,
+            1.5,
+        )
+        .with_aperture(0.5, 4.0);
+        // every lens position sees the same point on the focus plane
+        for lens in [(0.1, 0.9), (0.5, 0.5), (0.95, 0.2)] {
+            let ray = camera.get_ray_lens(0.3, 0.6, lens);
+            assert!(ray.origin.len() <= 0.25 + 1e-6);
+            let focus = ray.at(1.0);
+            let pinhole = camera.get_ray(0.3, 0.6).at(1.0);
+            assert!((focus - pinhole).len() < 1e-5);
+        }
+        assert!((camera.get_ray(0.5, 0.5).at(1.0).z + 4.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_pcg32_0() {
+        let mut a = Pcg32::new(42, 54);
+        let mut b = Pcg32::new(42, 54);
+        let mut c = Pcg32::new(42, 55);
+        let xs: Vec<u32> = (0..8).map(|_| a.next_u32()).collect();
+        let ys: Vec<u32> = (0..8).map(|_| b.next_u32()).collect();
+        let zs: Vec<u32> = (0..8).map(|_| c.next_u32()).collect();
+        assert_eq!(xs, ys);
+        assert_ne!(xs, zs);
+        // reference output of the PCG32 demo program
+        assert_eq!(xs[0], 0xa15c02b7);
+        assert_eq!(xs[1], 0x7b47f409);
+
+        seed_random(7, 1);
+        let first = random_f32();
+        seed_random(7, 1);
+        assert_eq!(random_f32(), first);
+    }
+
+    // a diffuse triangle in front of the camera, inside a light fog
+    fn determinism_scene(seed: u64, threads: usize) -> FrameBuffer {
+        let mut world = Mesh::new();
+        let mut triangle = Triangle::new(
+            Vec3 { x: -2.0, y: -1.0, z: -2.0 },
+            Vec3 { x: 2.0, y: -1.0, z: -2.0 },
+            Vec3 { x: 0.0, y: 2.0, z: -2.0 },
+        );
+        triangle.material = Material::Lambertian(0.5);
+        world.add_triangle(Box::new(triangle));
+        let albedo = Vec3 { x: 0.8, y: 0.8, z: 0.8 };
+        world.add_triangle(Box::new(ConstantMedium::new(Box::new(slab()), 0.5, Material::Isotropic(albedo))));
+        let fog = Fog::new(0.05, albedo);
+        let camera = Camera::new(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, Screen::new(4.0, 2.0)).with_aperture(0.1, 3.0);
+        let mut settings = RenderSettings::new(16, 8);
+        settings.samples_per_pixel = 4;
+        settings.filter = Filter::Gaussian(1.5, 2.0);
+        settings.seed = seed;
+        settings.threads = threads;
+        render(&camera, &world, Some(&fog), &settings)
+    }
+
+    #[test]
+    fn test_render_deterministic_0() {
+        let single = determinism_scene(1, 1);
+        assert_eq!(single, determinism_scene(1, 1));
+        assert_eq!(single, determinism_scene(1, 4));
+        assert_ne!(single, determinism_scene(2, 4));
+    }
+
+    // a diffuse triangle lit by the sky, noisy at low sample counts
+    fn progressive_scene() -> (Camera, Mesh) {
+        let mut world = Mesh::new();
+        let mut triangle = Triangle::new(
+            Vec3 { x: -3.0, y: -1.0, z: -2.0 },
+            Vec3 { x: 3.0, y: -1.0, z: -2.0 },
+            Vec3 { x: 0.0, y: 3.0, z: -2.0 },
+        );
+        triangle.material = Material::Lambertian(0.7);
+        world.add_triangle(Box::new(triangle));
+        let mut floor = Triangle::new(
+            Vec3 { x: -9.0, y: -1.0, z: 5.0 },
+            Vec3 { x: 9.0, y: -1.0, z: 5.0 },
+            Vec3 { x: 0.0, y: -1.0, z: -9.0 },
+        );
+        floor.material = Material::Lambertian(0.5);
+        world.add_triangle(Box::new(floor));
+        (Camera::new(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, Screen::new(4.0, 2.0)), world)
+    }
+
+    #[test]
+    fn test_progressive_0() {
+        let (camera, world) = progressive_scene();
+        let mut settings = RenderSettings::new(12, 6);
+        settings.samples_per_pixel = 2;
+        let mut progressive = ProgressiveRenderer::new(&camera, &world, None, settings);
+        let mut snapshots = 0;
+        let reason = progressive.run(&StopCondition::samples(8), |r| {
+            assert_eq!(r.samples(), 2 * r.passes);
+            snapshots += 1;
+        });
+        assert_eq!(reason, StopReason::Samples);
+        assert_eq!(snapshots, 4);
+        assert_eq!(progressive.samples(), 8);
+
+        // with a box filter the passes add up to exactly the one-shot render
+        let mut settings = RenderSettings::new(12, 6);
+        settings.samples_per_pixel = 8;
+        assert_eq!(progressive.buffer, render(&camera, &world, None, &settings));
+        progressive.write_snapshot("./tests/test_progressive_0.ppm");
+    }
+
+    #[test]
+    fn test_progressive_1() {
+        let (camera, world) = progressive_scene();
+        let mut settings = RenderSettings::new(12, 6);
+        settings.sampler = SamplerKind::Independent;
+        let mut progressive = ProgressiveRenderer::new(&camera, &world, None, settings);
+        assert_eq!(progressive.run(&StopCondition::time(Duration::ZERO), |_| {}), StopReason::Time);
+        assert_eq!(progressive.passes, 1);
+        assert!(progressive.noise_estimate().is_none());
+
+        progressive.run(&StopCondition::samples(2), |_| {});
+        let early = progressive.noise_estimate().unwrap();
+        assert!(early > 0.0);
+        let reason = progressive.run(&StopCondition::noise(early * 0.5), |_| {});
+        assert_eq!(reason, StopReason::Noise);
+        assert!(progressive.noise_estimate().unwrap() <= early * 0.5);
+        assert!(progressive.passes > 2);
+
+        assert_eq!(progressive.run(&StopCondition::default(), |_| {}), StopReason::Unbounded);
+    }
+
+    #[test]
+    fn test_adaptive_0() {
+        let values = [0.5, 1.5, 2.0, 0.0, 1.0];
+        let mut stats = PixelStats::new(2, 1);
+        for v in values {
+            stats.add(0, 1, v);
+        }
+        let mean = values.iter().sum::<f32>() / 5.0;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / 4.0;
+        assert_eq!(stats.count(0, 1), 5);
+        assert!((stats.mean(0, 1) - mean).abs() < 1e-6);
+        assert!((stats.variance(0, 1) - variance).abs() < 1e-6);
+        assert!((stats.error(0, 1, 1.96) - 1.96 * (variance / 5.0).sqrt()).abs() < 1e-6);
+        assert_eq!(stats.count(0, 0), 0);
+        assert_eq!(stats.error(0, 0, 1.96), f32::INFINITY);
+    }
+
+    #[test]
+    fn test_adaptive_1() {
+        // only the sky, resolved by the minimum sample count
+        let world = Mesh::new();
+        let camera = Camera::new(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, Screen::new(4.0, 2.0));
+        let settings = RenderSettings::new(24, 12);
+        let mut adaptive = AdaptiveRenderer::new(&camera, &world, None, settings, AdaptiveSettings::new(4, 64, 0.05));
+        adaptive.run();
+        assert_eq!(adaptive.total_samples(), 4 * 24 * 12);
+        assert_eq!(adaptive.rounds, 1);
+    }
+
+    #[test]
+    fn test_adaptive_2() {
+        let (camera, world) = progressive_scene();
+        let mut settings = RenderSettings::new(24, 12);
+        settings.samples_per_pixel = 4;
+        let mut adaptive = AdaptiveRenderer::new(&camera, &world, None, settings, AdaptiveSettings::new(4, 64, 0.05));
+        adaptive.run();
+        let (mut min, mut max) = (u32::MAX, 0);
+        for row in 0..12 {
+            for col in 0..24 {
+                let n = adaptive.stats.count(row, col);
+                assert!(adaptive.is_converged(row, col));
+                min = min.min(n);
+                max = max.max(n);
+            }
+        }
+        // the sky stops early, the diffuse surfaces get more samples
+        assert_eq!(min, 4);
+        assert!(max > 4 && max <= 64);
+        assert!(adaptive.total_samples() < 64 * 24 * 12);
+
+        let map = adaptive.sample_map();
+        assert_eq!(map[0][0], Pix::from_vec3(Vec3 { x: 4.0 / 64.0, y: 4.0 / 64.0, z: 4.0 / 64.0 }));
+        write_p3_file("./tests/test_adaptive_2.ppm", &adaptive.image());
+        write_p3_file("./tests/test_adaptive_2_samples.ppm", &map);
+    }
+
+    #[test]
+    fn test_checkpoint_0() {
+        let (camera, world) = progressive_scene();
+        let settings = || {
+            let mut settings = RenderSettings::new(12, 6);
+            settings.samples_per_pixel = 2;
+            settings.sampler = SamplerKind::Stratified;
+            settings.filter = Filter::Mitchell(2.0, 1.0 / 3.0, 1.0 / 3.0);
+            settings.output = OutputTransform::new(0.5, ToneMap::ReinhardExtended(4.0), Encoding::Gamma(2.2));
+            settings.seed = 7;
+            settings
+        };
+        let mut full = ProgressiveRenderer::new(&camera, &world, None, settings());
+        full.run(&StopCondition::samples(8), |_| {});
+
+        // killed after two passes, then resumed from the checkpoint
+        let path = "./tests/test_checkpoint_0.ckpt";
+        let mut killed = ProgressiveRenderer::new(&camera, &world, None, settings());
+        killed.run_checkpointed(&StopCondition::samples(4), path, 1).unwrap();
+        let mut resumed = ProgressiveRenderer::resume(&camera, &world, None, path).unwrap();
+        assert_eq!(resumed.passes, 2);
+        assert_eq!(resumed.settings.output, settings().output);
+        assert_eq!(resumed.settings.filter, settings().filter);
+        resumed.run(&StopCondition::samples(8), |_| {});
+        assert_eq!(resumed.buffer, full.buffer);
+        assert_eq!(resumed.noise_estimate(), full.noise_estimate());
+        assert_eq!(resumed.image().get_p3(), full.image().get_p3());
+
+        // another renderer's checkpoint is rejected
+        assert!(AdaptiveRenderer::resume(&camera, &world, None, path).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_1() {
+        let (camera, world) = progressive_scene();
+        let adaptive = AdaptiveSettings::new(4, 32, 0.05);
+        let mut full = AdaptiveRenderer::new(&camera, &world, None, RenderSettings::new(12, 6), adaptive);
+        full.run();
+
+        let path = "./tests/test_checkpoint_1.ckpt";
+        let mut killed = AdaptiveRenderer::new(&camera, &world, None, RenderSettings::new(12, 6), adaptive);
+        killed.render_round();
+        killed.render_round();
+        killed.save_checkpoint(path).unwrap();
+        let mut resumed = AdaptiveRenderer::resume(&camera, &world, None, path).unwrap();
+        assert_eq!(resumed.stats, killed.stats);
+        resumed.run_checkpointed(path, 2).unwrap();
+        assert_eq!(resumed.buffer, full.buffer);
+        assert_eq!(resumed.stats, full.stats);
+        assert_eq!(resumed.rounds, full.rounds);
+    }
+
+    #[test]
+    fn test_checkpoint_2() {
+        let (camera, world) = progressive_scene();
+        let path = "./tests/test_checkpoint_2.ckpt";
+        let mut renderer = ProgressiveRenderer::new(&camera, &world, None, RenderSettings::new(4, 2));
+        renderer.render_pass();
+        renderer.save_checkpoint(path).unwrap();
+        let bytes = std::fs::read(path).unwrap();
+
+        let mut corrupt = bytes.clone();
+        corrupt[8] = 99; // version
+        std::fs::write(path, &corrupt).unwrap();
+        let err = ProgressiveRenderer::resume(&camera, &world, None, path).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        std::fs::write(path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(ProgressiveRenderer::resume(&camera, &world, None, path).is_err());
+        std::fs::write(path, b"P3\n4 2\n255\n").unwrap();
+        assert!(ProgressiveRenderer::resume(&camera, &world, None, path).is_err());
+    }
+
+    // a small metal triangle (object 2) in front of a large diffuse one (object 1)
+    fn aov_scene() -> (Camera, Mesh) {
+        let mut back = Mesh::with_id(1);
+        let mut triangle = Triangle::new(
+            Vec3 { x: -3.0, y: -1.5, z: -2.0 },
+            Vec3 { x: 3.0, y: -1.5, z: -2.0 },
+            Vec3 { x: 0.0, y: 1.5, z: -2.0 },
+        );
+        triangle.material = Material::Lambertian(0.7);
+        back.add_triangle(Box::new(triangle));
+        let mut front = Mesh::with_id(2);
+        let mut triangle = Triangle::new(
+            Vec3 { x: -0.5, y: -0.5, z: -1.0 },
+            Vec3 { x: 0.5, y: -0.5, z: -1.0 },
+            Vec3 { x: 0.0, y: 0.5, z: -1.0 },
+        );
+        triangle.material = Material::Metal(0.3, 0.0);
+        front.add_triangle(Box::new(triangle));
+        let mut world = Mesh::new();
+        world.add_triangle(Box::new(back));
+        world.add_triangle(Box::new(front));
+        (Camera::new(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, Screen::new(4.0, 2.0)), world)
+    }
+
+    #[test]
+    fn test_aov_0() {
+        let (camera, world) = aov_scene();
+        let mut settings = RenderSettings::new(21, 11);
+        settings.samples_per_pixel = 4;
+        let aovs = render_aovs(&camera, &world, &settings);
+
+        // the center pixel sees the front triangle
+        let center = 5 * 21 + 10;
+        assert_eq!(aovs.object_id[center], Some(2));
+        assert_eq!(aovs.primitive_id[center], Some(0));
+        assert_eq!(aovs.mask[center], 1.0);
+        assert!((aovs.depth[center] - 2.0).abs() < 1e-4);
+        assert!((aovs.normal[center] - Vec3 { x: 0.0, y: 0.0, z: 1.0 }).len() < 1e-4);
+        assert_eq!(aovs.albedo[center], Vec3 { x: 0.3, y: 0.3, z: 0.3 });
+
+        // below it only the back triangle
+        let below = 7 * 21 + 10;
+        assert_eq!(aovs.object_id[below], Some(1));
+        assert!((aovs.depth[below] - 3.0).abs() < 1e-4);
+        assert_eq!(aovs.albedo[below], Vec3 { x: 0.7, y: 0.7, z: 0.7 });
+
+        // the top left corner is sky
+        assert_eq!(aovs.mask[0], 0.0);
+        assert_eq!(aovs.object_id[0], None);
+        assert!(aovs.image(Aov::Depth)[0][0] == Pix::new());
+        assert!(aovs.image(Aov::ObjectId)[5][10] != aovs.image(Aov::ObjectId)[7][10]);
+
+        // meshes without an id take the one of the mesh around them, if any
+        let ray = Ray { origin: Vec3 { x: 0.0, y: 0.0, z: 5.0 }, direction: Vec3 { x: 0.0, y: 0.0, z: -1.0 } };
+        let mut world = Mesh::new();
+        world.add_triangle(Box::new(uv_sphere(1.0, 8, 4).to_mesh(Material::Lambertian(0.5))));
+        assert_eq!(world.is_hit(&ray, &Interval::new()).unwrap().object_id, None);
+        let mut placed = Mesh::with_id(4);
+        placed.add_triangle(Box::new(world));
+        let hit = placed.is_hit(&ray, &Interval::new()).unwrap();
+        // the primitive id is the index in that mesh too, whatever lies around it
+        assert_eq!((hit.object_id, hit.primitive_id), (Some(4), Some(0)));
+        let mut outer = Mesh::new();
+        outer.add_triangle(Box::new(Triangle::default()));
+        outer.add_triangle(Box::new(placed));
+        let hit = outer.is_hit(&ray, &Interval::new()).unwrap();
+        assert_eq!((hit.object_id, hit.primitive_id), (Some(4), Some(0)));
+        let hit = Bvh::new(vec![Box::new(Triangle::default()), Box::new(outer)]).is_hit(&ray, &Interval::new()).unwrap();
+        assert_eq!((hit.object_id, hit.primitive_id), (Some(4), Some(0)));
+    }
+
+    #[test]
+    fn test_aov_1() {
+        let (camera, world) = aov_scene();
+        let settings = RenderSettings::new(21, 11);
+        let aovs = render_aovs(&camera, &world, &settings);
+        let beauty = settings.output.to_image(&render(&camera, &world, None, &settings));
+        aovs.write_files("./tests/test_aov_1");
+        aovs.write_layers("./tests/test_aov_1_layers.ppm", &beauty).unwrap();
+        let layers = std::fs::read_to_string("./tests/test_aov_1_layers.ppm").unwrap();
+        assert!(layers.starts_with("P3\n# beauty\n21 11\n255\n"));
+        assert_eq!(layers.matches("P3\n").count(), 1 + Aov::ALL.len());
+        assert!(layers.contains("P3\n# primitive_id\n"));
+    }
+
+    fn buffer_mse(a: &FrameBuffer, b: &FrameBuffer) -> f32 {
+        let mut sum = 0.0;
+        for row in 0..a.height {
+            for col in 0..a.width {
+                sum += (a.get(row, col) - b.get(row, col)).len_squared();
+            }
+        }
+        sum / (a.width * a.height) as f32
+    }
+
+    #[test]
+    fn test_denoise_0() {
+        let (camera, world) = progressive_scene();
+        let mut settings = RenderSettings::new(48, 24);
+        settings.samples_per_pixel = 256;
+        let reference = render(&camera, &world, None, &settings);
+        settings.samples_per_pixel = 4;
+        settings.seed = 1;
+        let noisy = render(&camera, &world, None, &settings);
+        let aovs = render_aovs(&camera, &world, &settings);
+        let denoised = denoise(&noisy, &aovs, &DenoiseSettings::default());
+        assert!(buffer_mse(&denoised, &reference) < 0.5 * buffer_mse(&noisy, &reference));
+        write_p3_file("./tests/test_denoise_0_noisy.ppm", &settings.output.to_image(&noisy));
+        write_p3_file("./tests/test_denoise_0.ppm", &settings.output.to_image(&denoised));
+    }
+
+    #[test]
+    fn test_denoise_1() {
+        // a flat image stays flat, the sky and the triangle edge are kept apart
+        let (camera, world) = aov_scene();
+        let settings = RenderSettings::new(21, 11);
+        let aovs = render_aovs(&camera, &world, &settings);
+        let mut flat = FrameBuffer::new(21, 11);
+        let mut edge = FrameBuffer::new(21, 11);
+        for row in 0..11 {
+            for col in 0..21 {
+                let i = row * 21 + col;
+                let albedo = if aovs.mask[i] > 0.0 { aovs.albedo[i] } else { Vec3 { x: 1.0, y: 1.0, z: 1.0 } };
+                flat.add_sample(row, col, albedo * 0.5, 1.0);
+                let v = if aovs.albedo[i].x == 0.3 { 1.0 } else { 0.0 };
+                edge.add_sample(row, col, Vec3 { x: v, y: v, z: v }, 1.0);
+            }
+        }
+        assert!(buffer_mse(&denoise(&flat, &aovs, &DenoiseSettings::default()), &flat) < 1e-10);
+        let denoised = denoise(&edge, &aovs, &DenoiseSettings::default());
+        assert!((denoised.get(5, 10).x - 1.0).abs() < 1e-3);
+        assert!(denoised.get(7, 10).x.abs() < 1e-3);
+        assert!(denoised.get(0, 0).x.abs() < 1e-3);
+    }
+
+    #[test]
+    #[should_panic(expected = "same size")]
+    fn test_denoise_2() {
+        let aovs = AovBuffer::new(21, 11);
+        denoise(&FrameBuffer::new(11, 21), &aovs, &DenoiseSettings::default());
+    }
+
+    #[test]
+    fn test_sphere_gen_0() {
+        let center = Vec3 { x: 1.0, y: 2.0, z: 3.0 };
+        let ico = Icosahedron::new(center);
+        assert_eq!(ico.triangles.len(), 20);
+        let sphere = Sphere::with_subdivisions(center, 2.0, 2);
+        assert_eq!(sphere.triangles.len(), 20 * 16);
+        for t in ico.triangles.iter().chain(sphere.triangles.iter()) {
+            // counter-clockwise seen from outside
+            let centroid = (t.a + t.b + t.c) / 3.0;
+            assert!(dot(&t.normal, &(centroid - center)) > 0.0);
+        }
+        for v in [sphere.triangles[0].a, sphere.triangles[100].b] {
+            assert!(((v - center).len() - 2.0).abs() < 1e-5);
+        }
+    }
+
+    const TEST_SCENE: &str = "
+# every kind of statement
+settings {
+    width 8 height 4 samples 2 max_depth 8 seed 3
+    sampler stratified filter gaussian 1.5 2
+    exposure -1 tone_map reinhard_extended 4 encoding gamma 2.2
+}
+camera { from 0 0 3 at 0 0 0 fov 60 aperture 0.05 }
+material floor lambertian 0.5
+material mirror metal 0.9 0.1
+material prism dielectric sellmeier 1.03 0.23 1.01 0.006 0.02 103.5 absorption 0.1 0 0
+material lamp emissive 4 4 4
+material smoke henyey_greenstein 0.8 0.8 0.8 0.3
+fog { density 0.01 albedo 0.9 0.9 0.9 g 0.2 }
+sphere { material prism center 0 0 -1 radius 0.5 subdivisions 1 }
+group {
+    material floor
+    translate 0 -1 0
+    rotate 0 1 0 45
+    triangle { a -5 0 5 b 5 0 5 c 0 0 -5 }
+    icosahedron { material lamp center 0 2 0 radius 0.3 scale 2 1 1 }
+}
+mesh { material mirror file \"test_scene_0.obj\" }
+";
+
+    #[test]
+    fn test_scene_0() {
+        std::fs::write("./tests/test_scene_0.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        std::fs::write("./tests/test_scene_0.scene", TEST_SCENE).unwrap();
+        let scene = Scene::load("./tests/test_scene_0.scene").unwrap();
+
+        assert_eq!((scene.settings.width, scene.settings.height, scene.settings.samples_per_pixel), (8, 4, 2));
+        assert_eq!(scene.settings.sampler, SamplerKind::Stratified);
+        assert_eq!(scene.settings.filter, Filter::Gaussian(1.5, 2.0));
+        assert_eq!(scene.settings.output, OutputTransform::new(-1.0, ToneMap::ReinhardExtended(4.0), Encoding::Gamma(2.2)));
+        assert_eq!(scene.camera.origin, Vec3 { x: 0.0, y: 0.0, z: 3.0 });
+        assert!((scene.camera.lens_radius - 0.025).abs() < 1e-7);
+        assert_eq!(scene.materials.len(), 5);
+        assert!(matches!(scene.material("lamp"), Some(Material::Emissive(e)) if e.x == 4.0));
+        assert!(matches!(scene.material("prism"), Some(Material::Dielectric(Ior::Sellmeier(_, _), a)) if a.x == 0.1));
+        assert!(matches!(scene.fog.unwrap().phase, Material::HenyeyGreenstein(_, g) if g == 0.2));
+        assert_eq!(scene.objects.len(), 3);
+        match &scene.objects[1].shape {
+            Shape::Group(children) => {
+                assert_eq!(children.len(), 2);
+                assert_eq!(children[0].material, None);
+                assert_eq!(children[1].material.as_deref(), Some("lamp"));
+            }
+            _ => panic!("expected a group"),
+        }
+        assert_eq!(scene.objects[1].transform.len(), 2);
+        match &scene.objects[2].shape {
+            Shape::Mesh(file, triangles) => {
+                assert_eq!(std::path::Path::new(file), std::path::Path::new("./tests/test_scene_0.obj"));
+                assert_eq!(triangles.len(), 2);
+            }
+            _ => panic!("expected a mesh"),
+        }
+
+        // the group transform moves the floor triangle down by one
+        let world = scene.world();
+        let down = Ray { origin: Vec3 { x: 0.0, y: 5.0, z: 0.0 }, direction: Vec3 { x: 0.0, y: -1.0, z: 0.0 } };
+        let hit = world.is_hit(&down, &Interval::initialize(1e-3, f32::MAX)).unwrap();
+        assert_eq!(hit.object_id, scene.graph().find_path("group/icosahedron").map(|n| n.0));
+        assert!(matches!(hit.material, Material::Emissive(_)));
+        let floor = Ray { origin: Vec3 { x: 3.0, y: 5.0, z: 0.0 }, direction: Vec3 { x: 0.0, y: -1.0, z: 0.0 } };
+        let hit = world.is_hit(&floor, &Interval::initialize(1e-3, f32::MAX)).unwrap();
+        assert!((hit.p.y + 1.0).abs() < 1e-5);
+        let buffer = scene.render();
+        assert_eq!((buffer.width, buffer.height), (8, 4));
+
+        // saved elsewhere the mesh file is still found through a relative path, as it is next to it
+        std::fs::create_dir_all("./tests/saved").unwrap();
+        scene.save("./tests/saved/test_scene_0.scene").unwrap();
+        assert!(std::fs::read_to_string("./tests/saved/test_scene_0.scene").unwrap().contains("file \"../test_scene_0.obj\""));
+        let moved = Scene::load("./tests/saved/test_scene_0.scene").unwrap();
+        assert_eq!(moved.render(), buffer);
+        scene.save("./tests/test_scene_0_saved.scene").unwrap();
+        assert!(std::fs::read_to_string("./tests/test_scene_0_saved.scene").unwrap().contains("file \"test_scene_0.obj\""));
+        let reloaded = Scene::load("./tests/test_scene_0_saved.scene").unwrap();
+        assert_eq!(reloaded.to_text(), scene.to_text());
+    }
+
+    #[test]
+    fn test_scene_1() {
+        // an emissive triangle filling the view is seen with its radiance
+        let scene = Scene::parse("
+            settings { width 4 height 2 encoding linear }
+            camera { origin 0 0 1 corner -2 1 0 horizontal 4 0 0 vertical 0 2 0 }
+            material lamp emissive 0.25 0.5 0.75
+            triangle { material lamp a -9 -9 -1 b 9 -9 -1 c 0 9 -1 }
+        ").unwrap();
+        assert_eq!(scene.camera.screen.start_point, Vec3 { x: -2.0, y: 1.0, z: 0.0 });
+        let buffer = scene.render();
+        assert_eq!(buffer.get(1, 2), Vec3 { x: 0.25, y: 0.5, z: 0.75 });
+    }
+
+    #[test]
+    fn test_scene_2() {
+        let error = |src: &str| Scene::parse(src).err().unwrap();
+        let e = error("settings { width 4 }\ncamera { fov wide }");
+        assert_eq!((e.line, e.col), (2, 14));
+        assert_eq!(e.message, "expected a number, found `wide`");
+        let e = error("material a lambertian 0.5\n  triangle { material b }");
+        assert_eq!((e.line, e.col, e.message.as_str()), (2, 23, "unknown material `b`"));
+        let e = error("light { }");
+        assert_eq!((e.line, e.col, e.message.as_str()), (1, 1, "unknown statement `light`"));
+        let e = error("group {\n  cube { }\n}");
+        assert_eq!((e.line, e.col, e.message.as_str()), (2, 3, "unknown object `cube`"));
+        let e = error("settings {\n  width 4 height 0\n  samples 2\n}\nsphere { }");
+        assert_eq!((e.line, e.col, e.message.as_str()), (2, 11, "image size must not be zero"));
+        let e = error("sphere {\n  subdivisions 30 }");
+        assert_eq!((e.line, e.col, e.message.as_str()), (2, 16, "subdivisions must be at most 8"));
+        assert!(Scene::parse("sphere { subdivisions 8 }").is_ok());
+        let e = error("sphere { radius 1\n");
+        assert_eq!((e.line, e.col, e.message.as_str()), (2, 1, "unexpected end of file, expected `}`"));
+        let e = error("mesh { file \"x.obj }");
+        assert_eq!((e.line, e.col, e.message.as_str()), (1, 13, "unterminated string"));
+        let e = error("material a metal 1 0\nmaterial a metal 1 0");
+        assert_eq!((e.line, e.col), (2, 10));
+        let e = error("camera { from 0 0 1 origin 0 0 1 }");
+        assert_eq!((e.line, e.col), (1, 21));
+        assert!(error("mesh { file \"missing.obj\" }").message.contains("missing.obj"));
+        assert_eq!(e.to_string(), "1:21: the camera mixes look-at and screen properties");
+
+        assert!(parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3").err().unwrap().contains("line 3"));
+        assert_eq!(parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3/1 -2/2 -1/3").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_scene_3() {
+        std::fs::write("./tests/test_scene_3.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        let scene = Scene::parse(&TEST_SCENE.replace("test_scene_0.obj", "tests/test_scene_3.obj")).unwrap();
+        let text = scene.to_text();
+        let reloaded = Scene::parse(&text).unwrap();
+        assert_eq!(reloaded.to_text(), text);
+        assert_eq!(reloaded.render(), scene.render());
+    }
+
+    #[test]
+    fn test_scene_4() {
+        // a generated scene: inline mesh, matrix transform, every kind of material
+        let mut settings = RenderSettings::new(6, 4);
+        settings.samples_per_pixel = 3;
+        settings.output = OutputTransform::new(0.1, ToneMap::Aces, Encoding::Srgb);
+        let camera = Camera::look_at(
+            Vec3 { x: 0.3, y: 0.7, z: 2.9 },
+            Vec3::new(),
+            Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+            47.0,
+            1.5,
+        ).with_aperture(0.1, 2.5);
+        let materials = vec![
+            ("clay".to_string(), Material::Lambertian(0.3)),
+            ("glass".to_string(), Material::Dielectric(Ior::Cauchy(1.5, 0.0042), Vec3 { x: 0.2, y: 0.0, z: 0.1 })),
+            ("light".to_string(), Material::Emissive(Vec3 { x: 3.0, y: 2.5, z: 2.0 })),
+            ("mist".to_string(), Material::Isotropic(Vec3 { x: 0.5, y: 0.6, z: 0.7 })),
+        ];
+        let sphere = Sphere::with_subdivisions(Vec3 { x: 0.1, y: 0.2, z: -0.3 }, 0.7, 1);
+        let mut mesh = Object::new(Shape::Mesh(String::new(), sphere.triangles), Some("glass".to_string()));
+        mesh.transform.push(TransformOp::Matrix(Mat4::rotation(Vec3 { x: 1.0, y: 1.0, z: 0.0 }, 0.3)));
+        let mut floor = Object::new(
+            Shape::Triangle(Vec3 { x: -4.0, y: -1.0, z: 4.0 }, Vec3 { x: 4.0, y: -1.0, z: 4.0 }, Vec3 { x: 0.0, y: -1.0, z: -4.0 }),
+            Some("clay".to_string()),
+        );
+        floor.transform.push(TransformOp::Rotate(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, 33.3));
+        let lamp = Object::new(Shape::Icosahedron(Vec3 { x: 1.0, y: 1.5, z: -1.0 }, 0.4), Some("light".to_string()));
+        let scene = Scene {
+            settings,
+            camera,
+            materials,
+            fog: Some(Fog::new(0.02, Vec3 { x: 0.9, y: 0.8, z: 0.7 })),
+            objects: vec![mesh, floor, lamp],
+        };
+
+        scene.save("./tests/test_scene_4.scene").unwrap();
+        let reloaded = Scene::load("./tests/test_scene_4.scene").unwrap();
+        assert_eq!(reloaded.camera.origin, scene.camera.origin);
+        assert_eq!(reloaded.camera.lens_radius, scene.camera.lens_radius);
+        assert_eq!(reloaded.render(), scene.render());
+        assert_eq!(reloaded.to_text(), scene.to_text());
+    }
+
+    #[test]
+    fn test_bvh_0() {
+        let b = Aabb::from_points(&[Vec3 { x: -1.0, y: -1.0, z: -1.0 }, Vec3 { x: 1.0, y: 1.0, z: 1.0 }]);
+        let ray = Ray { origin: Vec3 { x: 0.0, y: 0.0, z: 5.0 }, direction: Vec3 { x: 0.0, y: 0.0, z: -1.0 } };
+        assert!(b.hit(&ray, 0.0, f32::MAX));
+        assert!(!b.hit(&ray, 0.0, 3.9));
+        assert!(!b.hit(&Ray { origin: Vec3 { x: 2.0, y: 0.0, z: 5.0 }, direction: Vec3 { x: 0.0, y: 0.0, z: -1.0 } }, 0.0, f32::MAX));
+        let moved = b.transformed(&Transform::translate(Vec3 { x: 3.0, y: 0.0, z: 0.0 }));
+        assert_eq!(moved.min, Vec3 { x: 2.0, y: -1.0, z: -1.0 });
+        assert_eq!(moved.max, Vec3 { x: 4.0, y: 1.0, z: 1.0 });
+        let rotated = b.transformed(&Transform::rotate(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, std::f32::consts::FRAC_PI_4));
+        assert!((rotated.max.x - 2.0f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_bvh_1() {
+        // the BVH finds the same closest hits as testing every triangle
+        let sphere = Sphere::with_subdivisions(Vec3 { x: 0.2, y: -0.1, z: 0.0 }, 1.0, 2);
+        let mut objects: Vec<Box<dyn Hitable>> = Vec::new();
+        let mut mesh = Mesh::new();
+        for t in &sphere.triangles {
+            objects.push(Box::new(*t));
+            mesh.add_triangle(Box::new(*t));
+        }
+        let bvh = Bvh::new(objects);
+        assert_eq!(bvh.len(), sphere.triangles.len());
+        let bounds = bvh.bounding_box().unwrap();
+        assert!(bounds.min.x < -0.79 && bounds.max.x > 1.19);
+
+        let mut hits = 0;
+        for i in 0..500 {
+            // rays from inside and outside aimed at the sphere, a few miss it
+            let origin = Vec3::random_in_unit_sphere() * if i % 2 == 0 { 4.0 } else { 0.5 };
+            let ray = Ray { origin, direction: Vec3::random_in_unit_sphere() * 1.2 - origin };
+            let range = Interval { t_min: 1e-3, t_max: f32::MAX };
+            let (a, b) = (bvh.is_hit(&ray, &range), mesh.is_hit(&ray, &range));
+            assert_eq!(a.is_some(), b.is_some());
+            if let (Some(a), Some(b)) = (a, b) {
+                assert_eq!(a.t, b.t);
+                assert_eq!(a.normal, b.normal);
+                let id = a.primitive_id.unwrap() as usize;
+                assert!(sphere.triangles[id].is_hit(&ray, &range).is_some());
+                hits += 1;
+            }
+        }
+        assert!(hits > 300);
+    }
+
+    #[test]
+    fn test_graph_0() {
+        let mut graph = SceneGraph::new();
+        let gold = graph.materials.add("gold", Material::Metal(0.8, 0.1));
+        let clay = graph.materials.add("clay", Material::Lambertian(0.5));
+        assert_eq!(graph.materials.add("gold", Material::Metal(0.9, 0.0)), gold);
+        assert_eq!(graph.materials.find("clay"), Some(clay));
+        assert!(matches!(graph.materials.get(gold), Material::Metal(x, _) if x == 0.9));
+
+        let quad = graph.add_geometry("quad", vec![
+            Triangle::new(Vec3 { x: -0.5, y: -0.5, z: 0.0 }, Vec3 { x: 0.5, y: -0.5, z: 0.0 }, Vec3 { x: 0.5, y: 0.5, z: 0.0 }),
+            Triangle::new(Vec3 { x: -0.5, y: -0.5, z: 0.0 }, Vec3 { x: 0.5, y: 0.5, z: 0.0 }, Vec3 { x: -0.5, y: 0.5, z: 0.0 }),
+        ]);
+        let root = graph.root();
+        let shelf = graph.add_node(root, "shelf", Transform::translate(Vec3 { x: 0.0, y: 0.0, z: -2.0 }));
+        let left = graph.add_node(shelf, "left", Transform::translate(Vec3 { x: -1.0, y: 0.0, z: 0.0 }));
+        let right = graph.add_node(shelf, "right", Transform::translate(Vec3 { x: 1.0, y: 0.0, z: 0.0 }));
+        graph.set_geometry(left, quad, gold);
+        graph.set_geometry(right, quad, clay);
+        assert_eq!(graph.find("right"), Some(right));
+        assert_eq!(graph.find_path("shelf/left"), Some(left));
+        assert_eq!(graph.find_path("left"), None);
+        assert_eq!(graph.find_geometry("quad"), Some(quad));
+        assert_eq!(graph.node(left).parent, Some(shelf));
+        let p = graph.world_transform(right).point(&Vec3::new());
+        assert_eq!(p, Vec3 { x: 1.0, y: 0.0, z: -2.0 });
+
+        let world = graph.flatten();
+        let range = Interval { t_min: 1e-3, t_max: f32::MAX };
+        let down = |x: f32| Ray { origin: Vec3 { x, y: 0.0, z: 0.0 }, direction: Vec3 { x: 0.0, y: 0.0, z: -1.0 } };
+        let hit = world.is_hit(&down(-1.2), &range).unwrap();
+        assert_eq!((hit.t, hit.object_id), (2.0, Some(left.0)));
+        assert!(matches!(hit.material, Material::Metal(..)));
+        let hit = world.is_hit(&down(0.9), &range).unwrap();
+        assert_eq!(hit.object_id, Some(right.0));
+        assert!(matches!(hit.material, Material::Lambertian(_)));
+        assert!(world.is_hit(&down(0.0), &range).is_none());
+    }
+
+    #[test]
+    fn test_graph_1() {
+        std::fs::write("./tests/test_graph_1.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\n f 1 2 3\n").unwrap();
+        let src = TEST_SCENE.replace("test_scene_0.obj", "tests/test_graph_1.obj").replace("group {", "group { name base")
+            + "mesh { name copy file \"tests/test_graph_1.obj\" translate 0 0 -1 }\n";
+        let scene = Scene::parse(&src).unwrap();
+        assert!(scene.to_text().contains("group { name base material floor"));
+        let graph = scene.graph();
+        let base = graph.find("base").unwrap();
+        assert_eq!(graph.node(base).children.len(), 2);
+        assert_eq!(graph.find_path("base/icosahedron"), Some(graph.node(base).children[1]));
+        let (mesh, copy) = (graph.find("mesh").unwrap(), graph.find("copy").unwrap());
+        assert_eq!(graph.node(mesh).geometry.unwrap().0, graph.node(copy).geometry.unwrap().0);
+        let lamp = graph.node(graph.find("icosahedron").unwrap()).geometry.unwrap().1;
+        assert_eq!(graph.materials.name(lamp), "lamp");
+
+        let flat = render(&scene.camera, &graph.flatten(), scene.fog.as_ref(), &scene.settings);
+        assert_eq!(flat, scene.render());
+        assert_eq!(render(&scene.camera, &scene.world(), scene.fog.as_ref(), &scene.settings), flat);
+    }
+
+    #[test]
+    fn test_scenes_0() {
+        // every reference scene survives the text format and names its nodes
+        for name in scenes::NAMES {
+            let scene = scenes::by_name(name, 8, 6).unwrap();
+            assert_eq!((scene.settings.width, scene.settings.height), (8, 6));
+            let text = scene.to_text();
+            assert_eq!(Scene::parse(&text).unwrap().to_text(), text);
+            assert!(scene.graph().find("ground").is_some() || name == "cornell_box");
+        }
+        assert!(scenes::by_name("teapot", 8, 6).is_none());
+
+        let graph = scenes::cornell_box(8, 6).graph();
+        assert!(graph.find_path("room/left").is_some());
+        assert!(matches!(graph.materials.get(graph.node(graph.find("lamp").unwrap()).geometry.unwrap().1), Material::Emissive(_)));
+
+        let board = scenes::material_board(8, 6);
+        for variant in 0..6 {
+            assert!(board.materials.iter().any(|(_, m)| variant == match m {
+                Material::Lambertian(_) => 0,
+                Material::Metal(_, _) => 1,
+                Material::Dielectric(_, _) => 2,
+                Material::Isotropic(_) => 3,
+                Material::HenyeyGreenstein(_, _) => 4,
+                Material::Emissive(_) => 5,
+            }));
+        }
+        assert_eq!(scenes::sphere_gallery(8, 6, 2).objects.len(), 5);
+    }
+
+    #[test]
+    fn test_scenes_1() {
+        let a = scenes::random_spheres(8, 6, 2, 7);
+        let b = scenes::random_spheres(8, 6, 2, 7);
+        let c = scenes::random_spheres(8, 6, 2, 8);
+        assert_eq!(a.to_text(), b.to_text());
+        assert_ne!(a.to_text(), c.to_text());
+        assert!(a.objects.len() > 10);
+
+        // the lamp shows in the top of the Cornell box, the floor below is lit by it
+        let mut scene = scenes::cornell_box(16, 16);
+        scene.settings.samples_per_pixel = 4;
+        let buffer = scene.render();
+        let lamp = (0..5).map(|row| luminance(&buffer.get(row, 8))).fold(0.0, f32::max);
+        assert!(lamp > 5.0);
+        assert!(luminance(&buffer.get(14, 8)) > 0.05);
+    }
+
+    #[test]
+    fn test_primitives_0() {
+        let m = Material::Lambertian(0.5);
+        let range = Interval { t_min: 1e-3, t_max: f32::MAX };
+        let down = |x: f32, z: f32| Ray { origin: Vec3 { x, y: 5.0, z }, direction: Vec3 { x: 0.0, y: -1.0, z: 0.0 } };
+        let up = Vec3 { x: 0.0, y: 1.0, z: 0.0 };
+
+        let plane = Plane::new(Vec3::new(), up, m);
+        let h = plane.is_hit(&down(10.25, -3.5), &range).unwrap();
+        assert_eq!((h.t, h.normal, h.front_face), (5.0, up, true));
+        assert!(plane.bounding_box().is_none());
+        assert!(!plane.is_hit(&Ray { origin: Vec3 { x: 0.0, y: -1.0, z: 0.0 }, direction: up }, &range).unwrap().front_face);
+
+        let disk = Disk::new(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, up, 2.0, m);
+        let h = disk.is_hit(&down(1.0, 0.0), &range).unwrap();
+        assert_eq!(h.t, 4.0);
+        assert!((h.uv.1 - 0.5).abs() < 1e-6);
+        assert!(disk.is_hit(&down(1.5, 1.5), &range).is_none());
+        let b = disk.bounding_box().unwrap();
+        assert!((b.max.x - 2.0).abs() < 1e-3 && b.max.y - 1.0 < 1e-3);
+
+        let quad = Quad::new(Vec3::new(), Vec3 { x: 0.0, y: 0.0, z: 2.0 }, Vec3 { x: 4.0, y: 0.0, z: 0.0 }, m);
+        let h = quad.is_hit(&down(1.0, 1.5), &range).unwrap();
+        assert_eq!(h.normal, up);
+        assert!((h.uv.0 - 0.75).abs() < 1e-6 && (h.uv.1 - 0.25).abs() < 1e-6);
+        assert!(quad.is_hit(&down(-0.1, 1.0), &range).is_none());
+
+        let cuboid = Cuboid::new(Vec3 { x: 1.0, y: 1.0, z: 1.0 }, Vec3 { x: -1.0, y: 0.0, z: -1.0 }, m);
+        let h = cuboid.is_hit(&down(0.5, 0.0), &range).unwrap();
+        assert_eq!((h.t, h.normal, h.front_face), (4.0, up, true));
+        assert!((h.uv.0 - 0.5).abs() < 1e-6 && (h.uv.1 - 0.75).abs() < 1e-6);
+        // from inside the exit is hit, facing away from the ray
+        let inside = Ray { origin: Vec3 { x: 0.0, y: 0.5, z: 0.0 }, direction: Vec3 { x: 1.0, y: 0.0, z: 0.0 } };
+        let h = cuboid.is_hit(&inside, &range).unwrap();
+        assert_eq!((h.t, h.normal, h.front_face), (1.0, Vec3 { x: 1.0, y: 0.0, z: 0.0 }, false));
+        assert!(cuboid.is_hit(&down(1.5, 0.0), &range).is_none());
+
+        let rotation = Transform::rotate(up, std::f32::consts::FRAC_PI_4);
+        let oriented = OrientedBox::new(Vec3::new(), Vec3 { x: 2.0, y: 2.0, z: 2.0 }, &rotation, m);
+        let side = Ray { origin: Vec3 { x: 5.0, y: 0.0, z: 0.0 }, direction: Vec3 { x: -1.0, y: 0.0, z: 0.0 } };
+        let h = oriented.is_hit(&side, &range).unwrap();
+        assert!((h.t - (5.0 - 2.0f32.sqrt())).abs() < 1e-5);
+        assert!(h.front_face && (h.normal.len() - 1.0).abs() < 1e-5 && h.normal.x > 0.7);
+        assert!((oriented.bounding_box().unwrap().max.x - 2.0f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_primitives_1() {
+        let m = Material::Lambertian(0.5);
+        let range = Interval { t_min: 1e-3, t_max: f32::MAX };
+        let up = Vec3 { x: 0.0, y: 1.0, z: 0.0 };
+        let side = |y: f32| Ray { origin: Vec3 { x: 5.0, y, z: 0.0 }, direction: Vec3 { x: -1.0, y: 0.0, z: 0.0 } };
+        let down = |x: f32| Ray { origin: Vec3 { x, y: 5.0, z: 0.0 }, direction: Vec3 { x: 0.0, y: -1.0, z: 0.0 } };
+
+        let cylinder = Cylinder::new(Vec3::new(), up, 1.0, 2.0, m);
+        let h = cylinder.is_hit(&side(0.5), &range).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-5 && (h.normal.x - 1.0).abs() < 1e-5);
+        assert!((h.uv.1 - 0.25).abs() < 1e-5);
+        let h = cylinder.is_hit(&down(0.5), &range).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-5 && (h.normal.y - 1.0).abs() < 1e-5);
+        assert!(cylinder.is_hit(&side(2.5), &range).is_none());
+        let b = cylinder.bounding_box().unwrap();
+        assert!((b.max.y - 2.0).abs() < 1e-3 && (b.min.x + 1.0).abs() < 1e-3);
+
+        let cone = Cone::new(Vec3::new(), up, 1.0, 2.0, m);
+        let h = cone.is_hit(&side(1.0), &range).unwrap();
+        assert!((h.t - 4.5).abs() < 1e-5);
+        let slope = Vec3 { x: 2.0, y: 1.0, z: 0.0 }.normalized();
+        assert!((h.normal - slope).len() < 1e-5);
+        let h = cone.is_hit(&Ray { origin: Vec3 { x: 0.5, y: -3.0, z: 0.0 }, direction: up }, &range).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-5 && (h.normal.y + 1.0).abs() < 1e-5);
+        assert!(cone.is_hit(&side(2.1), &range).is_none());
+
+        let torus = Torus::new(Vec3::new(), up, 2.0, 0.5, m);
+        let h = torus.is_hit(&side(0.0), &range).unwrap();
+        assert!((h.t - 2.5).abs() < 1e-4 && (h.normal.x - 1.0).abs() < 1e-4);
+        // straight down through the tube and the hole
+        let h = torus.is_hit(&down(2.0), &range).unwrap();
+        assert!((h.t - 4.5).abs() < 1e-4 && (h.normal.y - 1.0).abs() < 1e-4);
+        assert!((h.uv.1 - 0.75).abs() < 1e-4);
+        assert!(torus.is_hit(&down(0.0), &range).is_none());
+        // from inside the tube
+        let inside = Ray { origin: Vec3 { x: 2.0, y: 0.0, z: 0.0 }, direction: Vec3 { x: 1.0, y: 0.0, z: 0.0 } };
+        let h = torus.is_hit(&inside, &range).unwrap();
+        assert!((h.t - 0.5).abs() < 1e-4 && !h.front_face);
+    }
+
+    #[test]
+    fn test_primitives_2() {
+        // random hits lie in the bounding boxes and the BVH finds the same hits
+        let m = Material::Lambertian(0.5);
+        let axis = Vec3 { x: 0.3, y: 1.0, z: -0.2 };
+        let rotation = Transform::rotate(axis, 0.7);
+        let shapes = || -> Vec<Box<dyn Hitable>> {
+            vec![
+                Box::new(Disk::new(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, axis, 0.8, m)),
+                Box::new(Quad::new(Vec3 { x: -2.0, y: 0.0, z: 0.0 }, Vec3 { x: 1.0, y: 0.2, z: 0.0 }, Vec3 { x: 0.0, y: 0.3, z: 1.0 }, m)),
+                Box::new(Cuboid::new(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, Vec3 { x: 0.5, y: 1.5, z: 0.8 }, m)),
+                Box::new(OrientedBox::new(Vec3 { x: 0.0, y: -1.5, z: 0.0 }, Vec3 { x: 1.0, y: 0.5, z: 0.3 }, &rotation, m)),
+                Box::new(Cylinder::new(Vec3 { x: 1.5, y: 1.0, z: 0.0 }, axis, 0.4, 0.9, m)),
+                Box::new(Cone::new(Vec3 { x: -1.5, y: 1.0, z: 0.5 }, -axis, 0.5, 1.0, m)),
+                Box::new(Torus::new(Vec3 { x: 0.0, y: 0.0, z: -1.5 }, axis, 0.7, 0.2, m)),
+            ]
+        };
+        let range = Interval { t_min: 1e-3, t_max: f32::MAX };
+        let rays: Vec<Ray> = (0..2000)
+            .map(|_| {
+                let origin = Vec3::random_in_unit_sphere() * 6.0;
+                Ray { origin, direction: Vec3::random_in_unit_sphere() * 2.0 - origin }
+            })
+            .collect();
+        let mut hits = vec![0; 7];
+        for (i, shape) in shapes().iter().enumerate() {
+            let b = shape.bounding_box().unwrap().padded(1e-3);
+            for ray in &rays {
+                if let Some(h) = shape.is_hit(ray, &range) {
+                    assert!(h.p.min(&b.min) == b.min && h.p.max(&b.max) == b.max);
+                    assert!((h.normal.len() - 1.0).abs() < 1e-3);
+                    assert!((0.0..=1.0).contains(&h.uv.0) && (0.0..=1.0).contains(&h.uv.1));
+                    assert_eq!(h.front_face, dot(&ray.direction, &h.normal) < 0.0);
+                    hits[i] += 1;
+                }
+            }
+        }
+        assert!(hits.iter().all(|&n| n > 10), "{:?}", hits);
+
+        let bvh = Bvh::new(shapes());
+        let mut mesh = Mesh::new();
+        for shape in shapes() {
+            mesh.add_triangle(shape);
+        }
+        for ray in &rays {
+            let (a, b) = (bvh.is_hit(ray, &range), mesh.is_hit(ray, &range));
+            assert_eq!(a.map(|h| (h.t, h.primitive_id)), b.map(|h| (h.t, h.primitive_id)));
+        }
+    }
+
+    fn signed_volume(mesh: &IndexedMesh) -> f32 {
+        mesh.triangles().iter().map(|t| dot(&t.a, &cross(&t.b, &t.c)) / 6.0).sum()
+    }
+
+    #[test]
+    fn test_mesh_gen_0() {
+        // closed meshes wind outwards and enclose about the analytic volume
+        let pi = std::f32::consts::PI;
+        let shapes = [
+            (uv_sphere(1.0, 32, 16), 4.0 / 3.0 * pi),
+            (cube(2.0, 3), 8.0),
+            (cylinder(1.0, 2.0, 32, true), 2.0 * pi),
+            (cone(1.0, 3.0, 32, true), pi),
+            (torus(2.0, 0.5, 48, 24), 2.0 * pi * pi * 2.0 * 0.25),
+        ];
+        for (mesh, volume) in shapes.iter() {
+            assert!((signed_volume(mesh) - volume).abs() < 0.03 * volume, "{} {}", signed_volume(mesh), volume);
+            assert_eq!(mesh.normals.len(), mesh.vertex_count());
+            for (t, f) in mesh.triangles().iter().zip(&mesh.faces) {
+                for &i in f {
+                    let n = mesh.normals[i as usize];
+                    assert!((n.len() - 1.0).abs() < 1e-5);
+                    assert!(dot(&n, &t.normal) > 0.0);
+                    let (u, v) = mesh.uvs[i as usize];
+                    assert!((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v));
+                }
+            }
+        }
+        // the poles have one triangle per segment
+        assert_eq!(uv_sphere(1.0, 8, 4).face_count(), 8 * 2 * 2 + 8 * 2);
+        assert_eq!(cube(1.0, 2).face_count(), 6 * 2 * 2 * 2);
+        assert_eq!(cone(1.0, 1.0, 8, false).face_count(), 8);
+        let g = grid(4.0, 2.0, 4, 2);
+        assert_eq!((g.vertex_count(), g.face_count()), (15, 16));
+        assert!(g.normals.iter().all(|n| *n == Vec3 { x: 0.0, y: 1.0, z: 0.0 }));
+
+        let moved = cube(1.0, 1).transformed(&Transform::translate(Vec3 { x: 0.0, y: 5.0, z: 0.0 }));
+        assert!(moved.positions.iter().all(|p| p.y >= 4.5 && p.y <= 5.5));
+        let mut both = cube(1.0, 1);
+        both.append(&moved);
+        assert_eq!((both.vertex_count(), both.face_count()), (48, 24));
+        assert_eq!(both.faces[12], [24, 25, 27]);
+    }
+
+    #[test]
+    fn test_mesh_gen_1() {
+        // a ramp rising along x: its normals lean back against the slope
+        let ramp = heightfield(2.0, 2.0, 4, 4, |u, _| u);
+        assert!((ramp.positions[4].y - 1.0).abs() < 1e-6 && ramp.positions[4].x == 1.0);
+        let expected = Vec3 { x: -0.5, y: 1.0, z: 0.0 }.normalized();
+        assert!(ramp.normals.iter().all(|n| (*n - expected).len() < 1e-5));
+
+        let mut image = Image::new(3, 2);
+        image[0][2].set(255, 255, 255);
+        let field = heightfield_from_image(&image, 2.0, 1.0, 0.5);
+        assert_eq!(field.vertex_count(), 6);
+        // the top right pixel is the far right corner
+        let peak = field.positions.iter().copied().fold(Vec3::new(), |a, p| if p.y > a.y { p } else { a });
+        assert_eq!(peak, Vec3 { x: 1.0, y: 0.5, z: -0.5 });
+
+        // exported positions read back as the same triangles
+        let sphere = uv_sphere(0.5, 6, 4);
+        let triangles = parse_obj(&sphere.to_obj()).unwrap();
+        assert_eq!(triangles.len(), sphere.face_count());
+        for (a, b) in triangles.iter().zip(sphere.triangles()) {
+            assert_eq!((a.a, a.b, a.c), (b.a, b.b, b.c));
+        }
+        assert!(sphere.to_obj().contains("\nvn 0 -1 0\n"));
+    }
+
+    #[test]
+    fn test_mesh_gen_2() {
+        // the smooth sphere is shaded with the normal of the true sphere
+        let mesh = uv_sphere(1.0, 24, 12).to_mesh(Material::Lambertian(0.5));
+        let range = Interval { t_min: 1e-3, t_max: f32::MAX };
+        let ray = Ray { origin: Vec3 { x: 0.3, y: 0.4, z: 5.0 }, direction: Vec3 { x: 0.0, y: 0.0, z: -1.0 } };
+        let h = mesh.is_hit(&ray, &range).unwrap();
+        assert!(h.front_face);
+        assert!((h.normal - h.p.normalized()).len() < 0.01);
+        let (u, v) = h.uv;
+        // u from the angle around y, v from the height
+        assert!((v - (h.p.y.asin() / std::f32::consts::PI + 0.5)).abs() < 0.01);
+        assert!((u - (-h.p.z).atan2(h.p.x).rem_euclid(2.0 * std::f32::consts::PI) / (2.0 * std::f32::consts::PI)).abs() < 0.01);
+    }
+
+    fn octahedron() -> IndexedMesh {
+        let mut mesh = IndexedMesh::new();
+        let axes = [(1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0), (-1.0, 0.0, 0.0), (0.0, -1.0, 0.0), (0.0, 0.0, -1.0)];
+        for (x, y, z) in axes {
+            mesh.add_vertex(Vec3 { x, y, z }, Vec3::new(), (0.0, 0.0));
+        }
+        for [a, b, c] in [[0, 1, 2], [2, 1, 3], [3, 1, 5], [5, 1, 0], [2, 4, 0], [3, 4, 2], [5, 4, 3], [0, 4, 5]] {
+            mesh.add_face(a, b, c);
+        }
+        mesh
+    }
+
+    fn cube_cage() -> PolyMesh {
+        let mut cage = PolyMesh::new();
+        for i in 0..8 {
+            let c = |bit: i32| if i & bit == 0 { -1.0 } else { 1.0 };
+            cage.add_vertex(Vec3 { x: c(1), y: c(2), z: c(4) }, (0.0, 0.0));
+        }
+        for f in [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]] {
+            cage.add_face(&f);
+        }
+        cage
+    }
+
+    #[test]
+    fn test_subdivision_0() {
+        // Loop on a closed octahedron rounds it off towards a sphere
+        let octahedron = octahedron();
+        assert!(signed_volume(&octahedron) > 0.0);
+        let (once, creases) = loop_step(&octahedron, &[]);
+        assert_eq!((once.vertex_count(), once.face_count()), (6 + 12, 32));
+        assert!(creases.is_empty());
+        // valence 4: beta = (5/8 - (3/8 + 1/4 cos(pi/2))^2) / 4 = 31/256
+        assert!((once.positions[0].x - (1.0 - 4.0 * 31.0 / 256.0)).abs() < 1e-6);
+        // edge vertex: 3/8 of each end and 1/8 of each opposite vertex
+        assert!((once.positions[6] - Vec3 { x: 0.375, y: 0.375, z: 0.0 }).len() < 1e-6);
+
+        let smooth = loop_subdivision(&octahedron, &[], 4);
+        assert_eq!(smooth.face_count(), 8 * 4usize.pow(4));
+        let radii: Vec<f32> = smooth.positions.iter().map(|p| p.len()).collect();
+        let (min, max) = radii.iter().fold((f32::MAX, 0.0f32), |(a, b), r| (a.min(*r), b.max(*r)));
+        assert!(min > 0.38 && max < 0.47 && max - min < 0.05, "{} {}", min, max);
+        assert!(smooth.normals.iter().zip(&smooth.positions).all(|(n, p)| dot(n, p) > 0.0));
+
+        // a crease around the equator keeps its edges straight between the new vertices
+        let equator = [[0, 2], [2, 3], [3, 5], [5, 0]];
+        let (creased, creases) = loop_step(&octahedron, &equator);
+        assert_eq!(creases.len(), 8);
+        assert!(creased.positions.iter().filter(|p| p.y == 0.0).count() >= 8);
+        let edge = creased.positions[6..].iter().find(|p| p.y == 0.0 && p.x > 0.0 && p.z > 0.0).unwrap();
+        assert_eq!(*edge, (octahedron.positions[0] + octahedron.positions[2]) * 0.5);
+        assert!(creased.positions[0].x > once.positions[0].x);
+    }
+
+    #[test]
+    fn test_subdivision_1() {
+        // a flat grid stays flat, its boundary follows the boundary rule
+        let flat = loop_subdivision(&grid(2.0, 2.0, 2, 2), &[], 2);
+        assert!(flat.positions.iter().all(|p| p.y == 0.0));
+        assert!(flat.positions.iter().any(|p| p.x == -1.0));
+        // the corner lies on two boundary edges and moves along them: 3/4 of itself
+        // and 1/8 of each neighbor on the boundary, -1 -> -0.875 -> -0.84375
+        assert_eq!(flat.positions[0], Vec3 { x: -0.84375, y: 0.0, z: 0.84375 });
+
+        // Catmull-Clark on a cube: the corner goes to (F + 2 R) / 3 = 5/9
+        let cage = cube_cage();
+        let (once, _) = catmull_clark_step(&cage, &[]);
+        assert_eq!((once.positions.len(), once.faces.len()), (8 + 6 + 12, 24));
+        assert!(once.faces.iter().all(|f| f.len() == 4));
+        let corner = 5.0 / 9.0;
+        assert!((once.positions[7] - Vec3 { x: corner, y: corner, z: corner }).len() < 1e-6);
+        let mesh = catmull_clark_subdivision(&cage, &[], 3).to_indexed();
+        assert_eq!(mesh.face_count(), 6 * 64 * 2);
+        assert!(signed_volume(&mesh) > 0.0 && signed_volume(&mesh) < 8.0);
+
+        // with every edge creased the corners stay and the cube keeps its faces flat
+        let mut creases = Vec::new();
+        for f in &cage.faces {
+            for i in 0..4 {
+                creases.push([f[i], f[(i + 1) % 4]]);
+            }
+        }
+        let sharp = catmull_clark_subdivision(&cage, &creases, 2);
+        assert_eq!(sharp.positions[7], Vec3 { x: 1.0, y: 1.0, z: 1.0 });
+        assert!(sharp.positions.iter().all(|p| p.abs().max_component() == 1.0));
+
+        // triangles subdivide into quads too
+        let quads = catmull_clark_subdivision(&PolyMesh::from(&octahedron()), &[], 1);
+        assert_eq!(quads.faces.len(), 8 * 3);
+    }
+
+    #[test]
+    fn test_mesh_ops_0() {
+        // the generated sphere repeats its seam and poles, welding closes it
+        let mut sphere = uv_sphere(1.0, 16, 8);
+        let before = sphere.stats();
+        assert!(!before.is_watertight());
+        assert!(before.boundary_edges > 0);
+        assert_eq!(sphere.weld(1e-5), before.vertices - (2 + 16 * 7));
+        assert_eq!(sphere.remove_degenerate(), 0);
+        let after = sphere.stats();
+        assert!(after.is_watertight() && sphere.boundary_edges().is_empty());
+        assert_eq!((after.vertices, after.faces), (2 + 16 * 7, before.faces));
+        assert!(after.surface_area < 4.0 * std::f32::consts::PI && after.surface_area > 12.0);
+        assert!((after.bounds.max - Vec3 { x: 1.0, y: 1.0, z: 1.0 }).len() < 0.01);
+
+        // a loaded cube: triangles without shared vertices
+        let mut cube = IndexedMesh::from_triangles(&cube(2.0, 1).triangles());
+        assert_eq!((cube.vertex_count(), cube.boundary_edges().len()), (36, 36));
+        cube.weld(0.0);
+        assert_eq!(cube.vertex_count(), 8);
+        assert!(cube.is_watertight());
+        assert!((cube.volume() - 8.0).abs() < 1e-5 && (cube.surface_area() - 24.0).abs() < 1e-5);
+
+        // at a corner every face meets at a right angle, so the angle weighted normal
+        // is the diagonal whichever way the faces are split into triangles
+        cube.set_normals(NormalMode::AngleWeighted);
+        let corner = cube.positions.iter().position(|p| *p == Vec3 { x: 1.0, y: 1.0, z: 1.0 }).unwrap();
+        let diagonal = Vec3 { x: 1.0, y: 1.0, z: 1.0 }.normalized();
+        assert!((cube.normals[corner] - diagonal).len() < 1e-6);
+        cube.set_normals(NormalMode::Flat);
+        assert_eq!((cube.vertex_count(), cube.face_count()), (36, 12));
+        assert!(cube.triangles().iter().zip(cube.faces.iter()).all(|(t, f)| f.iter().all(|&i| cube.normals[i as usize] == t.normal)));
+
+        cube.flip();
+        assert!((cube.volume() + 8.0).abs() < 1e-5);
+        assert!(cube.triangles().iter().zip(cube.faces.iter()).all(|(t, f)| cube.normals[f[0] as usize] == t.normal));
+    }
+
+    #[test]
+    fn test_mesh_ops_1() {
+        let mut mesh = IndexedMesh::from_triangles(&[
+            Triangle::new(Vec3::new(), Vec3 { x: 1.0, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 1.0, z: 0.0 }),
+            // corners on a line
+            Triangle::new(Vec3::new(), Vec3 { x: 1.0, y: 1.0, z: 0.0 }, Vec3 { x: 2.0, y: 2.0, z: 0.0 }),
+            // too small to be hit
+            Triangle::new(Vec3::new(), Vec3 { x: 1e-4, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 1e-4, z: 0.0 }),
+        ]);
+        // uses the edge of the first triangle twice more
+        mesh.add_face(0, 0, 1);
+        let stats = mesh.stats();
+        assert_eq!((stats.degenerate_faces, stats.non_manifold_edges), (3, 1));
+        let ray = Ray { origin: Vec3 { x: 2e-5, y: 2e-5, z: 1.0 }, direction: Vec3 { x: 0.0, y: 0.0, z: -1.0 } };
+        assert!(mesh.triangles()[2].is_hit(&ray, &Interval::new()).is_none());
+
+        assert_eq!(mesh.remove_degenerate(), 3);
+        assert_eq!(mesh.remove_unused_vertices(), 6);
+        assert_eq!((mesh.vertex_count(), mesh.faces[0]), (3, [0, 1, 2]));
+        assert_eq!(mesh.boundary_edges(), vec![[0, 1], [1, 2], [2, 0]]);
+
+        // the same edge twice in the same direction
+        mesh.add_face(0, 1, 2);
+        assert_eq!(mesh.stats().non_manifold_edges, 3);
+        assert!(!mesh.is_watertight());
+    }
+
+    #[test]
+    fn test_csg_0() {
+        let m = Material::Lambertian(0.5);
+        let line = Interval { t_min: -f32::MAX, t_max: f32::MAX };
+        let along = |origin: Vec3, direction: Vec3| Ray { origin, direction };
+        let x = Vec3 { x: 1.0, y: 0.0, z: 0.0 };
+        let z = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+        let unit_box = || Box::new(Cuboid::new(Vec3 { x: -1.0, y: -1.0, z: -1.0 }, Vec3 { x: 1.0, y: 1.0, z: 1.0 }, m));
+
+        // both sides of closed objects
+        let ray = along(Vec3 { x: -5.0, y: 0.5, z: 0.5 }, x);
+        let hits = unit_box().all_hits(&ray, &Interval::new());
+        assert_eq!(hits.iter().map(|h| (h.t, h.front_face)).collect::<Vec<_>>(), vec![(4.0, true), (6.0, false)]);
+        let mesh = cube(2.0, 2).to_mesh(m);
+        assert_eq!(mesh.all_hits(&ray, &Interval::new()).len(), 2);
+        assert_eq!(mesh.all_hits(&ray, &Interval { t_min: 5.0, t_max: f32::MAX }).len(), 1);
+        // both sides of a thin wall along a long direction vector
+        let wall = Cuboid::new(Vec3 { x: 0.0, y: -1.0, z: -1.0 }, Vec3 { x: 0.01, y: 1.0, z: 1.0 }, m);
+        let long = along(Vec3 { x: -5.0, y: 0.0, z: 0.0 }, x * 1000.0);
+        assert_eq!(wall.all_hits(&long, &Interval::new()).len(), 2);
+
+        // a box with a hole drilled along x
+        let drill = Box::new(Cylinder::new(Vec3 { x: -2.0, y: 0.0, z: 0.0 }, x, 0.5, 4.0, m));
+        let part = Csg::difference(unit_box(), drill);
+        assert!(part.is_hit(&along(Vec3 { x: -5.0, y: 0.0, z: 0.0 }, x), &Interval::new()).is_none());
+        let hits = part.all_hits(&along(Vec3 { x: 0.0, y: 0.0, z: 5.0 }, -z), &Interval::new());
+        let found: Vec<_> = hits.iter().map(|h| (h.t, h.normal, h.front_face)).collect();
+        assert_eq!(found, vec![(4.0, z, true), (4.5, -z, false), (5.5, z, true), (6.0, -z, false)]);
+        assert_eq!(part.bounding_box().unwrap().max, Vec3 { x: 1.0, y: 1.0, z: 1.0 });
+        // inside the box, with its faces beyond the range, the hole is still found
+        let short = |t_max: f32| Interval { t_min: 0.0, t_max };
+        let inside = along(Vec3 { x: 0.0, y: 0.0, z: 0.8 }, -z);
+        let hits = part.all_hits(&inside, &short(0.5));
+        assert_eq!(hits.len(), 1);
+        assert!((hits[0].t - 0.3).abs() < 1e-5 && !hits[0].front_face);
+        assert!(part.is_hit(&inside, &short(0.2)).is_none());
+
+        // the overlap of two boxes, and their union without the faces inside it
+        let shifted = || Box::new(Cuboid::new(Vec3 { x: 0.0, y: -1.0, z: -1.0 }, Vec3 { x: 2.0, y: 1.0, z: 1.0 }, Material::Metal(0.9, 0.0)));
+        let ray = along(Vec3 { x: -5.0, y: 0.0, z: 0.0 }, x);
+        let overlap = Csg::intersection(unit_box(), shifted());
+        let hits = overlap.all_hits(&ray, &line);
+        assert_eq!(hits.iter().map(|h| h.t).collect::<Vec<_>>(), vec![5.0, 6.0]);
+        assert!(matches!(hits[0].material, Material::Metal(..)) && matches!(hits[1].material, Material::Lambertian(..)));
+        assert_eq!(overlap.bounding_box().unwrap().min.x, 0.0);
+        let joined = Csg::union(unit_box(), shifted());
+        assert_eq!(joined.all_hits(&ray, &line).iter().map(|h| h.t).collect::<Vec<_>>(), vec![4.0, 7.0]);
+        // from inside the first hit leaves the solid
+        let h = joined.is_hit(&along(Vec3::new(), x), &Interval::new()).unwrap();
+        assert_eq!((h.t, h.front_face), (2.0, false));
+    }
+
+    #[test]
+    fn test_csg_1() {
+        let m = Material::Lambertian(0.5);
+        let x = Vec3 { x: 1.0, y: 0.0, z: 0.0 };
+        let unit_box = || Box::new(Cuboid::new(Vec3 { x: -1.0, y: -1.0, z: -1.0 }, Vec3 { x: 1.0, y: 1.0, z: 1.0 }, m));
+
+        // a box with a sphere mesh carved out of it keeps only its corners
+        let mut sphere = uv_sphere(1.2, 48, 24);
+        sphere.weld(1e-5);
+        sphere.remove_degenerate();
+        let carved = Csg::difference(unit_box(), Box::new(sphere.to_mesh(m)));
+        let ray = |y: f32| Ray { origin: Vec3 { x: -5.0, y, z: y }, direction: x };
+        assert!(carved.is_hit(&ray(0.0), &Interval::new()).is_none());
+        assert_eq!(carved.all_hits(&ray(0.9), &Interval::new()).len(), 2);
+        let hits = carved.all_hits(&ray(0.7), &Interval::new());
+        assert_eq!(hits.iter().map(|h| h.front_face).collect::<Vec<_>>(), vec![true, false, true, false]);
+        assert!((hits[1].p.x + 0.678).abs() < 0.01 && (hits[2].p.x - 0.678).abs() < 0.01);
+        assert!(hits[1].normal.x > 0.5 && hits[2].normal.x < -0.5);
+
+        // nested and moved: the carved box with a slab cut off its top, shifted up
+        let slab = Box::new(Cuboid::new(Vec3 { x: -2.0, y: 0.5, z: -2.0 }, Vec3 { x: 2.0, y: 2.0, z: 2.0 }, m));
+        let part = Instance::new(Arc::new(Csg::difference(Box::new(carved), slab)), Transform::translate(Vec3 { x: 0.0, y: 3.0, z: 0.0 }));
+        let down = Ray { origin: Vec3 { x: 0.9, y: 10.0, z: 0.9 }, direction: Vec3 { x: 0.0, y: -1.0, z: 0.0 } };
+        let h = part.is_hit(&down, &Interval::new()).unwrap();
+        assert!((h.t - 6.5).abs() < 1e-5 && h.front_face && h.normal.y > 0.99);
+        assert_eq!(part.all_hits(&down, &Interval::new()).len(), 2);
+        assert!(part.is_hit(&Ray { origin: Vec3 { x: 0.1, y: 10.0, z: 0.1 }, ..down }, &Interval::new()).is_none());
+    }
+
+    #[test]
+    fn test_sdf_0() {
+        let at = |x: f32, y: f32, z: f32| Vec3 { x, y, z };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        let ball = sdf::sphere(at(1.0, 0.0, 0.0), 1.0);
+        assert!(close(ball(&at(4.0, 0.0, 0.0)), 2.0) && close(ball(&at(1.0, 0.0, 0.0)), -1.0));
+        let cuboid = sdf::cuboid(Vec3::new(), at(1.0, 2.0, 3.0));
+        assert!(close(cuboid(&at(4.0, 0.0, 0.0)), 3.0) && close(cuboid(&Vec3::new()), -1.0));
+        assert!(close(cuboid(&at(4.0, 6.0, 3.0)), 5.0));
+        assert!(close(sdf::rounded_box(Vec3::new(), at(1.0, 1.0, 1.0), 0.25)(&at(2.0, 0.0, 0.0)), 0.75));
+        assert!(close(sdf::torus(Vec3::new(), 2.0, 0.5)(&at(0.0, 0.0, -2.0)), -0.5));
+        assert!(close(sdf::torus(Vec3::new(), 2.0, 0.5)(&Vec3::new()), 1.5));
+        assert!(close(sdf::capsule(Vec3::new(), at(0.0, 2.0, 0.0), 0.5)(&at(0.0, 3.0, 0.0)), 0.5));
+        assert!(close(sdf::capsule(Vec3::new(), at(0.0, 2.0, 0.0), 0.5)(&at(1.0, 1.0, 0.0)), 0.5));
+        assert!(close(sdf::cylinder(Vec3::new(), 1.0, 2.0)(&at(0.0, 3.0, 0.0)), 1.0));
+        assert!(close(sdf::cylinder(Vec3::new(), 1.0, 2.0)(&at(0.0, 0.0, 0.5)), -0.5));
+        assert!(close(sdf::plane(Vec3::new(), at(0.0, 2.0, 0.0))(&at(5.0, -1.5, 5.0)), -1.5));
+
+        let other = sdf::sphere(at(-1.0, 0.0, 0.0), 1.0);
+        let p = at(0.0, 0.0, 0.0);
+        assert!(close(sdf::union(ball.clone(), other.clone())(&at(3.0, 0.0, 0.0)), 1.0));
+        assert!(close(sdf::intersection(ball.clone(), other.clone())(&p), 0.0));
+        assert!(close(sdf::difference(ball.clone(), other.clone())(&at(1.5, 0.0, 0.0)), -0.5));
+        // the smooth union fills the seam and matches the union away from it
+        let blobs = sdf::smooth_union(ball.clone(), other.clone(), 0.5);
+        assert!(blobs(&at(0.0, 0.5, 0.0)) < sdf::union(ball.clone(), other.clone())(&at(0.0, 0.5, 0.0)) - 0.1);
+        assert!(close(blobs(&at(3.0, 0.0, 0.0)), 1.0));
+        assert!(close(sdf::smooth_intersection(ball.clone(), other.clone(), 0.5)(&at(3.0, 0.0, 0.0)), 3.0));
+        assert!(sdf::smooth_difference(ball.clone(), other.clone(), 0.5)(&at(0.1, 0.0, 0.0)) > 0.0);
+        assert!(close(sdf::blend(ball.clone(), other.clone(), 0.25)(&at(3.0, 0.0, 0.0)), 1.5));
+        assert!(close(sdf::shell(ball.clone(), 0.1)(&at(1.0, 0.0, 0.0)), 0.9));
+        let moved = sdf::transformed(ball.clone(), &Transform::translate(at(0.0, 2.0, 0.0)));
+        assert!(close(moved(&at(1.0, 2.0, 0.0)), -1.0));
+        assert!(close(sdf::scaled(ball.clone(), 2.0)(&at(6.0, 0.0, 0.0)), 2.0));
+
+        // level one of the sponge removes the middle of every face and its center
+        let sponge = sdf::menger_sponge(2);
+        assert!(sponge(&Vec3::new()) > 0.0 && sponge(&at(0.0, 0.0, 0.9)) > 0.0);
+        assert!(sponge(&at(0.9, 0.9, 0.9)) < 0.0 && close(sponge(&at(3.0, 0.0, 0.0)), 2.0));
+        let bulb = sdf::mandelbulb(8.0, 16);
+        let d = bulb(&at(3.0, 0.0, 0.0));
+        assert!(d > 0.5 && d < 2.0);
+        assert!(bulb(&at(0.0, 0.0, 0.5)) <= 0.0);
+    }
+
+    #[test]
+    fn test_sdf_1() {
+        let m = Material::Lambertian(0.5);
+        let z = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+        let unit = Aabb::new(Vec3 { x: -1.1, y: -1.1, z: -1.1 }, Vec3 { x: 1.1, y: 1.1, z: 1.1 });
+        let ball = Sdf::new(sdf::sphere(Vec3::new(), 1.0), unit, m);
+        let ray = |x: f32, y: f32| Ray { origin: Vec3 { x, y, z: 5.0 }, direction: -z };
+        let h = ball.is_hit(&ray(0.0, 0.0), &Interval::new()).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-3 && (h.normal - z).len() < 1e-3 && h.front_face);
+        assert!(ball.is_hit(&ray(0.8, 0.8), &Interval::new()).is_none());
+        let hits = ball.all_hits(&ray(0.6, 0.0), &Interval::new());
+        assert_eq!(hits.iter().map(|h| h.front_face).collect::<Vec<_>>(), vec![true, false]);
+        assert!((hits[1].t - 5.8).abs() < 1e-3 && hits[1].normal.z < -0.7);
+        let inside = ball.is_hit(&Ray { origin: Vec3::new(), direction: z * 2.0 }, &Interval::new()).unwrap();
+        assert!((inside.t - 0.5).abs() < 1e-3 && !inside.front_face);
+        // bounds touching the surface keep the near hit
+        let tight = Sdf::new(sdf::sphere(Vec3::new(), 1.0), Aabb::new(Vec3 { x: -1.0, y: -1.0, z: -1.0 }, Vec3 { x: 1.0, y: 1.0, z: 1.0 }), m);
+        let h = tight.is_hit(&ray(0.0, 0.0), &Interval::new()).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-3 && h.front_face);
+        assert_eq!(tight.all_hits(&ray(0.0, 0.0), &Interval::new()).len(), 2);
+
+        // a triangle in front of a field shape in the same hierarchy
+        let triangle = Triangle::new(Vec3 { x: -3.0, y: -3.0, z: 2.0 }, Vec3 { x: 3.0, y: -3.0, z: 2.0 }, Vec3 { x: -3.0, y: 3.0, z: 2.0 });
+        let scene = Bvh::new(vec![Box::new(triangle), Box::new(Sdf::new(sdf::sphere(Vec3::new(), 1.0), unit, m))]);
+        assert_eq!(scene.is_hit(&ray(-0.5, -0.5), &Interval::new()).unwrap().primitive_id, Some(0));
+        assert_eq!(scene.is_hit(&ray(0.5, 0.5), &Interval::new()).unwrap().primitive_id, Some(1));
+
+        // blobs merge over the gap between them, and fields cut other solids
+        let blobs = sdf::smooth_union(sdf::sphere(Vec3 { x: -1.1, y: 0.0, z: 0.0 }, 1.0), sdf::sphere(Vec3 { x: 1.1, y: 0.0, z: 0.0 }, 1.0), 0.5);
+        let bounds = Aabb::new(Vec3 { x: -2.5, y: -1.5, z: -1.5 }, Vec3 { x: 2.5, y: 1.5, z: 1.5 });
+        assert!(Sdf::new(blobs, bounds, m).is_hit(&ray(0.0, 0.0), &Interval::new()).is_some());
+        let cube = Box::new(Cuboid::new(Vec3 { x: -1.0, y: -1.0, z: -1.0 }, Vec3 { x: 1.0, y: 1.0, z: 1.0 }, m));
+        let hollow = Sdf::new(sdf::sphere(Vec3::new(), 1.2), bounds, m);
+        let carved = Csg::difference(cube, Box::new(hollow));
+        assert!(carved.is_hit(&ray(0.0, 0.0), &Interval::new()).is_none());
+        assert_eq!(carved.is_hit(&ray(0.95, 0.95), &Interval::new()).unwrap().t, 4.0);
+
+        let sponge = Sdf::new(sdf::menger_sponge(2), unit, m);
+        assert!(sponge.is_hit(&ray(0.0, 0.0), &Interval::new()).is_none());
+        assert!((sponge.is_hit(&ray(0.8, 0.8), &Interval::new()).unwrap().t - 4.0).abs() < 1e-3);
+    }
+}
@@ -1,12 +1,79 @@
 use super::basic::*;
 use super::mesh::*;
-use std::rc::Rc;
-use std::thread;
+use super::volume::*;
+use super::renderer::*;
+use super::sampler::*;
 use std::sync::{Arc, Mutex};
 
+#[derive(Clone, Copy)]
 pub struct Camera {
     pub origin: Vec3,
     pub screen: Screen,
+    pub lens_radius: f32, // zero for a pinhole camera, the screen is in focus
+}
+
+impl Camera {
+    // pinhole camera
+    pub fn new(origin: Vec3, screen: Screen) -> Camera {
+        Camera { origin, screen, lens_radius: 0.0 }
+    }
+
+    // camera at look_from aimed at look_at, vertical field of view in degrees,
+    // with the screen one unit in front of the camera; an up vector along the
+    // view direction is replaced by one perpendicular to it
+    pub fn look_at(look_from: Vec3, look_at: Vec3, up: Vec3, vfov: f32, aspect: f32) -> Camera {
+        let forward = (look_at - look_from).normalized();
+        let up = if cross(&forward, &up.normalized()).len() < 1e-6 {
+            orthonormal_basis(&forward).0
+        } else {
+            up
+        };
+        let camera_to_world = Mat4::look_at(look_from, look_at, up).inverse().unwrap_or_default();
+        let h_len = 2.0 * (vfov.to_radians() * 0.5).tan();
+        let w_len = aspect * h_len;
+        let horizontal = Vec3 { x: w_len, y: 0.0, z: 0.0 }.transform_vector(&camera_to_world);
+        let vertical = Vec3 { x: 0.0, y: h_len, z: 0.0 }.transform_vector(&camera_to_world);
+        let center = Vec3 { x: 0.0, y: 0.0, z: -1.0 }.transform_point(&camera_to_world);
+        let start_point = center - horizontal * 0.5 + vertical * 0.5;
+        Camera::new(look_from, Screen { start_point, horizontal, vertical })
+    }
+
+    // thin lens camera with the given aperture diameter, focused focus_dist away
+    // by moving the screen along the view rays
+    pub fn with_aperture(self, aperture: f32, focus_dist: f32) -> Camera {
+        let center = self.screen.start_point + self.screen.horizontal * 0.5 - self.screen.vertical * 0.5;
+        let k = focus_dist / (center - self.origin).len();
+        let screen = Screen {
+            start_point: self.origin + (self.screen.start_point - self.origin) * k,
+            horizontal: self.screen.horizontal * k,
+            vertical: self.screen.vertical * k,
+        };
+        Camera { origin: self.origin, screen, lens_radius: aperture * 0.5 }
+    }
+
+    // ray through the screen point (u, v), both in [0, 1] from the top left corner
+    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
+        self.get_ray_lens(u, v, (0.5, 0.5))
+    }
+
+    // ray from the lens position given by two uniform numbers in [0, 1)
+    // through the screen point (u, v)
+    pub fn get_ray_lens(&self, u: f32, v: f32, lens: (f32, f32)) -> Ray {
+        let target = self.screen.start_point + self.screen.horizontal * u - self.screen.vertical * v;
+        let mut origin = self.origin;
+        if self.lens_radius > 0.0 {
+            let (dx, dy) = sample_unit_disk(lens);
+            origin += self.screen.horizontal.normalized() * (dx * self.lens_radius)
+                + self.screen.vertical.normalized() * (dy * self.lens_radius);
+        }
+        Ray { origin, direction: target - origin }
+    }
+
+    // unit vector from the camera towards the center of the screen
+    pub fn view_direction(&self) -> Vec3 {
+        let center = self.screen.start_point + self.screen.horizontal * 0.5 - self.screen.vertical * 0.5;
+        (center - self.origin).normalized()
+    }
 }
 
 pub struct Ray {
@@ -20,8 +87,7 @@ impl Ray {
     }
 
     pub fn gradient_color(&self) -> Vec3 {
-        let mut unit_direction: Vec3 = self.direction.clone();
-        unit_direction.to_unit_len();
+        let unit_direction: Vec3 = self.direction.normalized();
         let t = 0.5 * (unit_direction.y + 1.0);
         Vec3 { x: 1.0, y: 1.0, z: 1.0 } * (1.0 - t) + Vec3 { x: 0.5, y: 0.7, z: 1.0 } * t
     }
@@ -45,9 +111,15 @@ impl Interval {
     }
 }
 
+impl Default for Interval {
+    fn default() -> Interval {
+        Interval::new()
+    }
+}
 
+#[derive(Clone, Copy)]
 pub struct Screen {
-    pub start_point: Vec3, // the center of the screen
+    pub start_point: Vec3, // the top left corner of the screen
     pub horizontal: Vec3, // the horizontal vector of the screen
     pub vertical: Vec3, // the vertical vector of the screen
 }
@@ -63,21 +135,51 @@ impl Screen {
     }
 
     pub fn gradient_render(&self, camera: Vec3, width: usize, height: usize, filename: &str) {
-        let mut image = Image::new(width, height);
-        for row in 0..height {
-            for col in 0..width {
-                let u = col as f32 / width as f32;
-                let v = row as f32 / height as f32;
-                let ray = Ray {
-                    origin: camera,
-                    direction: self.start_point - camera + self.horizontal * u - self.vertical * v,
-                };
-                let color = ray.gradient_color();
-                image[row][col] = Pix::from_vec3(color);
+        let buffer = self.gradient_buffer(camera, width, height);
+        write_p3_file(filename, &OutputTransform::default().to_image(&buffer));
+    }
+
+    // linear radiance of the sky gradient, to be encoded with any OutputTransform
+    pub fn gradient_buffer(&self, camera: Vec3, width: usize, height: usize) -> FrameBuffer {
+        let camera = Camera::new(camera, *self);
+        render(&camera, &Mesh::new(), None, &RenderSettings::new(width, height))
+    }
+}
+
+// path traced radiance along a ray, scattering off surfaces and through the optional fog
+pub fn ray_color(
+    ray: &Ray,
+    world: &dyn Hitable,
+    fog: Option<&Fog>,
+    max_depth: u32,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let mut throughput = Vec3 { x: 1.0, y: 1.0, z: 1.0 };
+    let mut ray = Ray { origin: ray.origin, direction: ray.direction };
+    let mut channel = None;
+    for _ in 0..max_depth {
+        let surface = world.is_hit(&ray, &Interval::initialize(1e-3, f32::MAX));
+        let t_surface = surface.as_ref().map_or(f32::MAX, |h| h.t);
+        let rec = match fog.and_then(|f| f.sample(&ray, t_surface, sampler.get_1d())) {
+            Some(medium) => medium,
+            None => match surface {
+                Some(h) => h,
+                None => return throughput * ray.gradient_color(),
+            },
+        };
+        match rec.material.scatter(&ray, &rec, &mut channel, sampler) {
+            Some((attenuation, scattered)) => {
+                throughput *= attenuation;
+                // nothing more can reach the camera, as in a fully absorbed channel
+                if throughput.max_component() <= 0.0 {
+                    return Vec3::new();
+                }
+                ray = scattered;
             }
+            None => return throughput * rec.material.emitted(),
         }
-        write_p3_file(filename, &image);
     }
+    Vec3::new()
 }
 
 pub struct Job {
@@ -109,9 +211,9 @@ impl Job {
 }
 
 pub fn do_job(job: Job) -> Option<(Hitrecord, Job)> {
-    let mut camera = job.camera.lock().unwrap();
+    let _camera = job.camera.lock().unwrap();
     let cur_scene = job.scene.clone();
-    let mut job_res = cur_scene.is_hit(&job.ray, &job.interv);
+    let _job_res = cur_scene.is_hit(&job.ray, &job.interv);
     
     None
 }
\ No newline at end of file
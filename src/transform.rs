@@ -0,0 +1,126 @@
+use std::ops::Mul;
+use std::sync::Arc;
+use super::tracer::*;
+use super::basic::*;
+use super::mesh::*;
+use super::bvh::*;
+
+// affine transform together with its cached inverse
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+}
+
+impl Transform {
+    pub fn new() -> Transform {
+        Transform { matrix: Mat4::identity(), inverse: Mat4::identity() }
+    }
+
+    // None if the matrix cannot be inverted
+    pub fn from_matrix(matrix: Mat4) -> Option<Transform> {
+        let inverse = matrix.inverse()?;
+        Some(Transform { matrix, inverse })
+    }
+
+    pub fn translate(t: Vec3) -> Transform {
+        Transform {
+            matrix: Mat4::translation(t),
+            inverse: Mat4::translation(-t),
+        }
+    }
+
+    // non-uniform scale, every component must be non-zero
+    pub fn scale(s: Vec3) -> Transform {
+        Transform {
+            matrix: Mat4::scaling(s),
+            inverse: Mat4::scaling(Vec3 { x: 1.0 / s.x, y: 1.0 / s.y, z: 1.0 / s.z }),
+        }
+    }
+
+    // rotation by angle (in radians) around the given axis
+    pub fn rotate(axis: Vec3, angle: f32) -> Transform {
+        let matrix = Mat4::rotation(axis, angle);
+        Transform { matrix, inverse: matrix.transpose() }
+    }
+
+    pub fn from_quat(q: &Quat) -> Transform {
+        let matrix = q.to_mat4();
+        Transform { matrix, inverse: matrix.transpose() }
+    }
+
+    // apply self first, then other
+    pub fn then(&self, other: &Transform) -> Transform {
+        *other * *self
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform { matrix: self.inverse, inverse: self.matrix }
+    }
+
+    #[inline]
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        self.matrix.transform_point(p)
+    }
+
+    #[inline]
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    // normals transform with the inverse transpose, the result is not normalized
+    #[inline]
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+
+    // the ray in the transformed space, direction is not renormalized so t is preserved
+    #[inline]
+    pub fn ray(&self, ray: &Ray) -> Ray {
+        Ray { origin: self.point(&ray.origin), direction: self.vector(&ray.direction) }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::new()
+    }
+}
+
+// composition, (a * b) applies b first
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+}
+
+// a shared Hitable placed in the scene under a transform, without copying its geometry
+pub struct Instance {
+    pub object: Arc<dyn Hitable>,
+    pub transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hitable>, transform: Transform) -> Instance {
+        Instance { object, transform }
+    }
+}
+
+impl Hitable for Instance {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        let local_ray = self.transform.inverse().ray(ray);
+        let mut rec = self.object.is_hit(&local_ray, t_range)?;
+        rec.p = self.transform.point(&rec.p);
+        rec.normal = self.transform.normal(&rec.normal).normalized();
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.object.bounding_box()?.transformed(&self.transform))
+    }
+}
@@ -0,0 +1,132 @@
+use super::tracer::*;
+use super::basic::*;
+use super::mesh::*;
+use super::bvh::*;
+
+// homogeneous participating medium filling the inside of a closed boundary
+pub struct ConstantMedium {
+    pub boundary: Box<dyn Hitable>,
+    pub density: f32,
+    pub phase: Material, // Isotropic or HenyeyGreenstein
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hitable>, density: f32, phase: Material) -> ConstantMedium {
+        ConstantMedium { boundary, density, phase }
+    }
+}
+
+impl Hitable for ConstantMedium {
+    fn is_hit(&self, ray: &Ray, t_range: &Interval) -> Option<Hitrecord> {
+        // entry and exit points of the boundary along the whole line
+        let enter = self.boundary.is_hit(ray, &Interval::initialize(-f32::MAX, f32::MAX))?;
+        let exit = self.boundary.is_hit(ray, &Interval::initialize(enter.t + 1e-4, f32::MAX))?;
+
+        let t_enter = enter.t.max(t_range.t_min).max(0.0);
+        let t_exit = exit.t.min(t_range.t_max);
+        if t_enter >= t_exit {
+            return None;
+        }
+
+        let ray_len = ray.direction.len();
+        let inside = (t_exit - t_enter) * ray_len;
+        let flight = free_flight_distance(self.density, random_f32());
+        if flight > inside {
+            return None;
+        }
+
+        let t = t_enter + flight / ray_len;
+        Some(medium_record(ray, t, self.phase))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+// global homogeneous fog, applied by the integrator along every ray segment
+#[derive(Clone, Copy)]
+pub struct Fog {
+    pub density: f32,
+    pub phase: Material, // Isotropic or HenyeyGreenstein
+    pub distance: f32, // extent of the fog along rays that escape the scene
+}
+
+impl Fog {
+    pub fn new(density: f32, albedo: Vec3) -> Fog {
+        Fog { density, phase: Material::Isotropic(albedo), distance: 100.0 }
+    }
+
+    // scattering event before t_max, or None if the ray travels through unscattered,
+    // u is a uniform number in [0, 1) choosing the free-flight distance
+    pub fn sample(&self, ray: &Ray, t_max: f32, u: f32) -> Option<Hitrecord> {
+        let ray_len = ray.direction.len();
+        let t_max = t_max.min(self.distance / ray_len);
+        let t = free_flight_distance(self.density, u) / ray_len;
+        if t < t_max {
+            Some(medium_record(ray, t, self.phase))
+        } else {
+            None
+        }
+    }
+}
+
+// exponentially distributed distance to the next collision
+#[inline]
+fn free_flight_distance(density: f32, u: f32) -> f32 {
+    if density <= 0.0 {
+        return f32::MAX;
+    }
+    -(1.0 - u).ln() / density
+}
+
+fn medium_record(ray: &Ray, t: f32, phase: Material) -> Hitrecord {
+    Hitrecord {
+        t,
+        p: ray.at(t),
+        normal: Vec3 { x: 1.0, y: 0.0, z: 0.0 }, // arbitrary, media have no surface
+        front_face: true,
+        material: phase,
+        object_id: None,
+        primitive_id: None,
+        uv: (0.0, 0.0),
+    }
+}
+
+// new direction for a ray travelling along `direction`, scattered by the
+// Henyey-Greenstein phase function with asymmetry g (g = 0 is isotropic),
+// u holds two uniform numbers in [0, 1)
+pub fn sample_henyey_greenstein(direction: &Vec3, g: f32, u: (f32, f32)) -> Vec3 {
+    let xi = u.0;
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * xi
+    } else {
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        (1.0 + g * g - s * s) / (2.0 * g)
+    };
+    let cos_theta = cos_theta.clamp(-1.0, 1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.1;
+
+    let w = direction.normalized();
+    let (u, v) = orthonormal_basis(&w);
+    u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + w * cos_theta
+}
+
+// Henyey-Greenstein phase function value for the cosine between the travel and scatter directions
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * std::f32::consts::PI * denom * denom.sqrt())
+}
+
+// two unit vectors perpendicular to the unit vector w and to each other
+pub(crate) fn orthonormal_basis(w: &Vec3) -> (Vec3, Vec3) {
+    let a = if w.x.abs() > 0.9 {
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 }
+    } else {
+        Vec3 { x: 1.0, y: 0.0, z: 0.0 }
+    };
+    let v = cross(w, &a).normalized();
+    let u = cross(&v, w);
+    (u, v)
+}